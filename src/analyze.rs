@@ -0,0 +1,217 @@
+use crate::{
+	BErr, FileType, HPAK, PK2D,
+	walk::{Entry, Visitor, walk}
+};
+use bytes::Bytes;
+use std::{
+	collections::BTreeMap,
+	convert::TryInto,
+	path::Path
+};
+
+const HEADER_LEN: usize = 16; // how much of the start of the files is compared
+const SIZE_FIELDS: [usize; 3] = [0, 4, 8];
+const EXAMPLES: usize = 3;
+
+// Everything seen of one group of a bundle type, across every bundle of that type
+struct GroupStats {
+	bundle: FileType,
+	group: usize,
+	ty: FileType,
+	bundles: usize, // bundles that have files in the group
+	sizes: Vec<usize>,
+	header: Vec<Option<u8>>, // bytes every file agrees on, None where they differ
+	magics: BTreeMap<String, usize>,
+	looks_like: BTreeMap<String, usize>,
+	size_fields: [usize; 3], // files with their own size at each of SIZE_FIELDS
+	entry_sizes: [Option<Option<usize>>; 2], // for a u16 and a u32 count at 0, the entry size every file agrees on
+	with: [usize; 8], // bundles that also have files in each group
+	same_count: [usize; 8], // bundles with as many files in each group
+	examples: Vec<String>
+}
+
+impl GroupStats {
+	fn new(bundle: FileType, group: usize, ty: FileType) -> Self {
+		GroupStats {
+			bundle, group, ty, bundles: 0, sizes: Vec::new(), header: Vec::new(), magics: BTreeMap::new(),
+			looks_like: BTreeMap::new(), size_fields: [0; 3], entry_sizes: [None; 2], with: [0; 8], same_count: [0; 8],
+			examples: Vec::new()
+		}
+	}
+
+	fn add_bundle(&mut self, path: &str, groups: &[(FileType, &[Bytes]); 8]) {
+		let files = groups[self.group].1;
+		self.bundles += 1;
+		if self.examples.len() < EXAMPLES {
+			self.examples.push(path.into());
+		}
+		for (i, (_, group)) in groups.iter().enumerate() {
+			self.with[i] += !group.is_empty() as usize;
+			self.same_count[i] += (group.len() == files.len()) as usize;
+		}
+		for file in files {
+			self.add_file(file);
+		}
+	}
+
+	fn add_file(&mut self, file: &[u8]) {
+		let first = self.sizes.is_empty();
+		self.sizes.push(file.len());
+		let start = &file[..file.len().min(HEADER_LEN)];
+		if first {
+			self.header = start.iter().map(|b| Some(*b)).collect();
+		} else {
+			self.header.truncate(start.len());
+			for (known, b) in self.header.iter_mut().zip(start) {
+				if *known != Some(*b) {
+					*known = None;
+				}
+			}
+		}
+		if file.len() >= 4 && file[..4].iter().all(|b| b.is_ascii_alphanumeric() || *b == b' ') {
+			*self.magics.entry(String::from_utf8_lossy(&file[..4]).into_owned()).or_default() += 1;
+		}
+		let guess = FileType::guess_from(file, true);
+		if guess != FileType::OtherOrNotGuessable {
+			*self.looks_like.entry(format!("{:?}", guess)).or_default() += 1;
+		}
+		for (count, at) in self.size_fields.iter_mut().zip(SIZE_FIELDS.iter()) {
+			*count += (read_u32(file, *at) == Some(file.len())) as usize;
+		}
+		// a count at the start and fixed size entries after it
+		for (agreed, width) in self.entry_sizes.iter_mut().zip([2, 4]) {
+			let entry_size = match read_count(file, width) {
+				Some(count) if count > 0 && (file.len() - width).is_multiple_of(count) => Some((file.len() - width) / count),
+				_ => None
+			};
+			*agreed = match (first, *agreed) {
+				(true, _) => Some(entry_size),
+				(false, Some(previous)) if previous == entry_size => Some(previous),
+				_ => Some(None)
+			};
+		}
+	}
+
+	fn report(&self, bundles_seen: usize) {
+		println!("{:?} (group {} of {:?}, .{}):", self.ty, self.group, self.bundle, self.ty.get_extension());
+		if self.sizes.is_empty() {
+			println!("\tin none of the {} {:?} bundles", bundles_seen, self.bundle);
+			return
+		}
+		println!("\t{} files in {} of the {} {:?} bundles, for example {}", self.sizes.len(), self.bundles, bundles_seen, self.bundle,
+			self.examples.join(", "));
+		let mut sizes = self.sizes.clone();
+		sizes.sort_unstable();
+		println!("\tsizes: min {}, median {}, max {}, total {}", sizes[0], sizes[sizes.len() / 2], sizes[sizes.len() - 1],
+			sizes.iter().sum::<usize>());
+		let mut buckets: BTreeMap<usize, usize> = BTreeMap::new();
+		for size in &sizes {
+			*buckets.entry(size.next_power_of_two()).or_default() += 1;
+		}
+		let buckets: Vec<String> = buckets.iter().map(|(limit, n)| format!("<={}: {}", limit, n)).collect();
+		println!("\t\t{}", buckets.join(", "));
+		let header: Vec<String> = self.header.iter().map(|b| b.map(|b| format!("{:02X}", b)).unwrap_or_else(|| "..".into())).collect();
+		println!("\theader: {}", header.join(" "));
+		if !self.magics.is_empty() {
+			let magics: Vec<String> = self.magics.iter().map(|(m, n)| format!("{:?} x{}", m, n)).collect();
+			println!("\tmagic-like starts: {}", magics.join(", "));
+		}
+		if !self.looks_like.is_empty() {
+			let types: Vec<String> = self.looks_like.iter().map(|(t, n)| format!("{} x{}", t, n)).collect();
+			println!("\tstarts like a known type: {}", types.join(", "));
+		}
+		for (count, at) in self.size_fields.iter().zip(SIZE_FIELDS.iter()) {
+			if *count > 0 {
+				println!("\tu32 at {:#x} is the file's size in {} of {} files", at, count, self.sizes.len());
+			}
+		}
+		for (agreed, width) in self.entry_sizes.iter().zip([2, 4]) {
+			if let Some(Some(entry_size)) = agreed {
+				println!("\tu{} count at 0 followed by {} byte entries in every file", width * 8, entry_size);
+			}
+		}
+		let mut always = Vec::new();
+		let mut never = Vec::new();
+		let mut same = Vec::new();
+		for group in (0..8).filter(|g| *g != self.group) {
+			match self.with[group] {
+				n if n == self.bundles => always.push(group.to_string()),
+				0 => never.push(group.to_string()),
+				_ => ()
+			}
+			if self.same_count[group] * 2 >= self.bundles && self.with[group] > 0 {
+				same.push(format!("{} ({}/{})", group, self.same_count[group], self.bundles));
+			}
+		}
+		println!("\talways with groups [{}], never with [{}]", always.join(", "), never.join(", "));
+		if !same.is_empty() {
+			println!("\tas many files as groups {}", same.join(", "));
+		}
+	}
+}
+
+fn read_u32(buf: &[u8], at: usize) -> Option<usize> {
+	Some(u32::from_le_bytes(buf.get(at..at + 4)?.try_into().unwrap()) as usize)
+}
+
+fn read_count(buf: &[u8], width: usize) -> Option<usize> {
+	if width == 2 {
+		Some(u16::from_le_bytes(buf.get(..2)?.try_into().unwrap()) as usize)
+	} else {
+		read_u32(buf, 0)
+	}
+}
+
+// Looks at the groups of every HPAK and PK2D bundle the walk finds that nobody knows the format of
+struct Analysis {
+	groups: Vec<GroupStats>,
+	bundles_seen: BTreeMap<String, usize>
+}
+
+impl Visitor for Analysis {
+	fn file(&mut self, _entry: &Entry, _ty: FileType, _size: usize, _compressed: Option<(&str, usize)>) {}
+
+	fn bundle(&mut self, entry: &Entry, bundle: FileType, groups: &[(FileType, &[Bytes]); 8]) {
+		*self.bundles_seen.entry(format!("{:?}", bundle)).or_default() += 1;
+		for (group, (ty, files)) in groups.iter().enumerate() {
+			if !is_unknown(*ty) || files.is_empty() {
+				continue;
+			}
+			let stats = match self.groups.iter_mut().find(|s| s.bundle == bundle && s.group == group) {
+				Some(stats) => stats,
+				None => {
+					self.groups.push(GroupStats::new(bundle, group, *ty));
+					self.groups.last_mut().unwrap()
+				}
+			};
+			stats.add_bundle(entry.path, groups);
+		}
+	}
+
+	fn failed(&mut self, entry: &Entry, problem: String) {
+		println!("{} {}", entry.path, problem);
+	}
+}
+
+pub fn is_unknown(ty: FileType) -> bool {
+	matches!(ty, FileType::Unknown0 | FileType::Unknown1 | FileType::Unknown2 | FileType::Unknown3 | FileType::Unknown4
+		| FileType::Unknown5 | FileType::Unknown6 | FileType::Unknown7)
+}
+
+// Prints what every unknown bundle group has in common, for working out what they are
+pub fn analyze(target: &Path) -> Result<(), BErr> {
+	let mut analysis = Analysis {groups: Vec::new(), bundles_seen: BTreeMap::new()};
+	walk(&mut analysis, target)?;
+	// report every unknown group, even the ones that never show up
+	let empty: [Vec<Bytes>; 8] = Default::default();
+	for (bundle, groups) in [(FileType::HPAK, HPAK::from(empty.clone()).get_type_map()), (FileType::PK2D, PK2D::from(empty.clone()).get_type_map())] {
+		let seen = analysis.bundles_seen.get(&format!("{:?}", bundle)).copied().unwrap_or(0);
+		for (group, (ty, _)) in groups.iter().enumerate().filter(|(_, (ty, _))| is_unknown(*ty)) {
+			match analysis.groups.iter().find(|s| s.bundle == bundle && s.group == group) {
+				Some(stats) => stats.report(seen),
+				None => GroupStats::new(bundle, group, *ty).report(seen)
+			}
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,272 @@
+use crate::{
+	BErr,
+	magic::*,
+	model::{Bone, Rotation, Transform, pivot_matrix},
+	nitro::{NitroFile, read_dict},
+	util::Reader
+};
+
+// Values over time, sampled every `step` frames from `start` on, and held past the ends.
+// A constant is a track with one sample.
+pub struct Track<const N: usize> {
+	start: usize,
+	step: usize,
+	samples: Vec<[f32; N]>
+}
+
+impl<const N: usize> Track<N> {
+	fn constant(value: [f32; N]) -> Self {
+		Track {start: 0, step: 1, samples: vec![value]}
+	}
+
+	pub fn sample(&self, frame: usize) -> [f32; N] {
+		let position = frame.saturating_sub(self.start);
+		let (i, within) = (position / self.step, position % self.step);
+		let last = self.samples.len() - 1;
+		if i >= last {
+			return self.samples[last]
+		}
+		let t = within as f32 / self.step as f32;
+		let (a, b) = (self.samples[i], self.samples[i + 1]);
+		let mut out = a;
+		for (o, (a, b)) in out.iter_mut().zip(a.iter().zip(b.iter())) {
+			*o = a + (b - a) * t;
+		}
+		out
+	}
+}
+
+// A part of a bone's transform: left at nothing, left at the model's rest pose, or animated
+pub enum Part<T> {
+	Identity,
+	Rest,
+	Animated(T)
+}
+
+pub struct BoneAnimation {
+	pub translation: Part<[Track<1>; 3]>,
+	pub rotation: Part<Track<9>>,
+	pub scale: Part<[Track<1>; 3]>
+}
+
+impl BoneAnimation {
+	pub fn sample(&self, frame: usize, rest: Option<&Bone>) -> Transform {
+		let translation = match &self.translation {
+			Part::Identity => [0.0; 3],
+			Part::Rest => rest.map_or([0.0; 3], |b| b.translation),
+			Part::Animated(tracks) => [0, 1, 2].map(|i| tracks[i].sample(frame)[0])
+		};
+		let rotation = match &self.rotation {
+			Part::Identity => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+			Part::Rest => rest.map_or([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], |b| b.rotation),
+			Part::Animated(track) => {
+				let m = track.sample(frame);
+				[[m[0], m[1], m[2]], [m[3], m[4], m[5]], [m[6], m[7], m[8]]]
+			}
+		};
+		let scale = match &self.scale {
+			Part::Identity => [1.0; 3],
+			Part::Rest => rest.map_or([1.0; 3], |b| b.scale),
+			Part::Animated(tracks) => [0, 1, 2].map(|i| tracks[i].sample(frame)[0])
+		};
+		(translation, rotation, scale)
+	}
+}
+
+// A skeletal animation, with one entry per bone of the model it's made for
+pub struct SkeletalAnimation {
+	pub name: String,
+	pub frames: usize,
+	pub bones: Vec<BoneAnimation>
+}
+
+pub fn parse_nsbca(buf: &[u8]) -> Result<Vec<SkeletalAnimation>, BErr> {
+	let file = NitroFile::parse_g3d(buf)?;
+	if file.magic != NSBCA_MAGIC {
+		return Err("not an NSBCA".into())
+	}
+	let start = file.get_section_offset(JNT0_MAGIC).ok_or("NSBCA has no joint animation section")? - 8;
+	read_dict(buf, start + 8)?.into_iter().map(|(name, entry)| {
+		let offset = Reader::new(entry, 0).u32()? as usize;
+		parse_animation(buf, start + offset, name)
+	}).collect()
+}
+
+fn parse_animation(buf: &[u8], at: usize, name: String) -> Result<SkeletalAnimation, BErr> {
+	let mut r = Reader::new(buf, at + 4); // "J\0AC"
+	let frames = r.u16()? as usize;
+	let bone_count = r.u16()? as usize;
+	r.pos += 4;
+	let rotations = Rotations {pivots: at + r.u32()? as usize, matrices: at + r.u32()? as usize};
+	let bones = (0..bone_count).map(|_| {
+		let offset = r.u16()? as usize;
+		parse_bone(buf, at, at + offset, &rotations)
+	}).collect::<Result<_, _>>()?;
+	Ok(SkeletalAnimation {name, frames, bones})
+}
+
+// Where the two kinds of rotations are kept. Rotations are stored once and referred to by index.
+struct Rotations {
+	pivots: usize,
+	matrices: usize
+}
+
+impl Rotations {
+	fn get(&self, buf: &[u8], index: u16) -> Result<[f32; 9], BErr> {
+		let rotation = if index & 0x8000 != 0 {
+			// packed like the pivots in models
+			let mut r = Reader::new(buf, self.pivots + (index & 0x7FFF) as usize * 6);
+			let flags = r.u16()?;
+			let (a, b) = (r.fx16()?, r.fx16()?);
+			pivot_matrix(flags & 0xF, (flags >> 4) & 0xF, a, b)
+		} else {
+			// two rows of 13 bit values, with the last value made of the low bits of the other five,
+			// and the third row is their cross product
+			let mut r = Reader::new(buf, self.matrices + index as usize * 10);
+			let v = [r.u16()?, r.u16()?, r.u16()?, r.u16()?, r.u16()?];
+			let mut m = [0.0; 6];
+			for (m, v) in m.iter_mut().zip(v.iter()) {
+				*m = (*v as i16 >> 3) as f32 / 4096.0;
+			}
+			let low = ((v[4] & 7) << 12) | ((v[0] & 7) << 9) | ((v[1] & 7) << 6) | ((v[2] & 7) << 3) | (v[3] & 7);
+			m[5] = ((low << 1) as i16 >> 1) as f32 / 4096.0;
+			let (a, b) = ([m[0], m[1], m[2]], [m[3], m[4], m[5]]);
+			let c = [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]];
+			// stored for row vectors, like the models
+			[[a[0], b[0], c[0]], [a[1], b[1], c[1]], [a[2], b[2], c[2]]]
+		};
+		Ok(flatten(&rotation))
+	}
+}
+
+fn flatten(m: &Rotation) -> [f32; 9] {
+	[m[0][0], m[0][1], m[0][2], m[1][0], m[1][1], m[1][2], m[2][0], m[2][1], m[2][2]]
+}
+
+// Each animated value is either a constant, or a curve given by where its samples are and how they're spaced
+struct Curve {
+	start: usize,
+	step: usize,
+	count: usize,
+	fx16: bool,
+	data: usize
+}
+
+fn read_curve(r: &mut Reader, anim: usize) -> Result<Curve, BErr> {
+	let info = r.u32()?;
+	let data = anim + r.u32()? as usize;
+	let start = (info & 0xFFFF) as usize;
+	let end = ((info >> 16) & 0xFFF) as usize;
+	let step = 1 << ((info >> 30) & 3);
+	let count = end.saturating_sub(start).div_ceil(step);
+	Ok(Curve {start, step, count: count.max(1), fx16: info & 0x10000000 != 0, data})
+}
+
+fn parse_bone(buf: &[u8], anim: usize, at: usize, rotations: &Rotations) -> Result<BoneAnimation, BErr> {
+	let mut r = Reader::new(buf, at);
+	let flags = r.u16()?;
+	r.pos += 2;
+	if flags & 1 != 0 {
+		return Ok(BoneAnimation {translation: Part::Identity, rotation: Part::Identity, scale: Part::Identity})
+	}
+	// translations and scales have a value for each axis, then the scale's inverse which isn't needed
+	let read_axes = |r: &mut Reader, constant_bit: u16, pair: bool| -> Result<[Track<1>; 3], BErr> {
+		let mut tracks = Vec::with_capacity(3);
+		for axis in 0..3 {
+			if flags & (constant_bit << axis) != 0 {
+				let value = r.fx32()?;
+				if pair {
+					r.pos += 4;
+				}
+				tracks.push(Track::constant([value]));
+			} else {
+				let curve = read_curve(r, anim)?;
+				let width = if curve.fx16 {2} else {4} * if pair {2} else {1};
+				let samples = (0..curve.count).map(|i| {
+					let mut r = Reader::new(buf, curve.data + i * width);
+					Ok([if curve.fx16 {r.fx16()?} else {r.fx32()?}])
+				}).collect::<Result<_, BErr>>()?;
+				tracks.push(Track {start: curve.start, step: curve.step, samples});
+			}
+		}
+		let mut tracks = tracks.into_iter();
+		Ok([tracks.next().unwrap(), tracks.next().unwrap(), tracks.next().unwrap()])
+	};
+	let translation = if flags & 2 != 0 {
+		Part::Identity
+	} else if flags & 4 != 0 {
+		Part::Rest
+	} else {
+		Part::Animated(read_axes(&mut r, 1 << 3, false)?)
+	};
+	let rotation = if flags & 0x40 != 0 {
+		Part::Identity
+	} else if flags & 0x80 != 0 {
+		Part::Rest
+	} else if flags & 0x100 != 0 {
+		let index = r.u16()?;
+		r.pos += 2;
+		Part::Animated(Track::constant(rotations.get(buf, index)?))
+	} else {
+		let curve = read_curve(&mut r, anim)?;
+		let samples = (0..curve.count).map(|i| {
+			let index = Reader::new(buf, curve.data + i * 2).u16()?;
+			rotations.get(buf, index)
+		}).collect::<Result<_, BErr>>()?;
+		Part::Animated(Track {start: curve.start, step: curve.step, samples})
+	};
+	let scale = if flags & 0x200 != 0 {
+		Part::Identity
+	} else if flags & 0x400 != 0 {
+		Part::Rest
+	} else {
+		Part::Animated(read_axes(&mut r, 1 << 11, true)?)
+	};
+	Ok(BoneAnimation {translation, rotation, scale})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::nitro::{build_g3d, write_dict};
+
+	// one bone, moving along y over a curve with a constant x and z, and turned a quarter around x
+	fn nsbca_file() -> Vec<u8> {
+		let mut anim = b"J\0AC".to_vec();
+		// frames and bones, then where the pivot rotations, the matrix ones and the bone are
+		for field in [4u16, 1, 0, 0, 0x34, 0, 0x3A, 0, 0x18, 0] {
+			anim.extend_from_slice(&field.to_le_bytes());
+		}
+		let flags: u32 = 1 << 3 | 1 << 5 | 0x100 | 0x200;
+		// the curve covers frames 0 to 4 with a sample every other frame, in fx16
+		let curve = 4 << 16 | 1 << 28 | 1 << 30;
+		for field in [flags, 0x1000, curve, 0x30, 0xFFFF_F800, 0x8000] {
+			anim.extend_from_slice(&field.to_le_bytes());
+		}
+		// the y samples, then the pivot rotation: the first layout with b negated, a = 0 and b = 1
+		for field in [0x1000u16, 0x3000, 0x20, 0, 0x1000] {
+			anim.extend_from_slice(&field.to_le_bytes());
+		}
+		// the animation goes right after the dictionary, whose size doesn't depend on what its entries say
+		let names = ["walk".to_string()];
+		let offset = 8 + write_dict(&names, &[vec![0; 4]]).len() as u32;
+		let mut section = write_dict(&names, &[offset.to_le_bytes().to_vec()]);
+		section.extend(anim);
+		build_g3d(NSBCA_MAGIC, &[(JNT0_MAGIC, &section)])
+	}
+
+	#[test]
+	fn tracks_sample_constants_and_curves() {
+		let animations = parse_nsbca(&nsbca_file()).unwrap();
+		assert_eq!(animations.len(), 1);
+		let animation = &animations[0];
+		assert_eq!((animation.name.as_str(), animation.frames, animation.bones.len()), ("walk", 4, 1));
+		// between samples the curve is interpolated, and past its end it holds
+		for (frame, y) in [(0, 1.0), (1, 2.0), (2, 3.0), (5, 3.0)] {
+			let (translation, rotation, scale) = animation.bones[0].sample(frame, None);
+			assert_eq!(translation, [1.0, y, -0.5], "frame {}", frame);
+			assert_eq!(rotation, [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]]);
+			assert_eq!(scale, [1.0; 3]);
+		}
+	}
+}
@@ -0,0 +1,662 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	nitro::{NitroFile, read_dict, write_dict},
+	util::Reader
+};
+use bytes::{Bytes, BufMut};
+use serde::{Serialize, Deserialize};
+
+// The animations that aren't skeletal: texture patterns (NSBTP), texture scrolling, rotation and scaling (NSBTA),
+// material colors (NSBMA) and bone visibility (NSBVA). They're converted to JSON and back.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(tag = "format")]
+pub enum AnimationFile {
+	#[serde(rename = "NSBTP")]
+	Pattern {version: u16, animations: Vec<PatternAnimation>},
+	#[serde(rename = "NSBTA")]
+	TextureSRT {version: u16, animations: Vec<SRTAnimation>},
+	#[serde(rename = "NSBMA")]
+	MaterialColor {version: u16, animations: Vec<ColorAnimation>},
+	#[serde(rename = "NSBVA")]
+	Visibility {version: u16, animations: Vec<VisibilityAnimation>}
+}
+
+// A value that stays the same, or one sampled every `step` frames up to `last_frame` and every frame after that
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Track<T> {
+	Constant {constant: T},
+	Curve {
+		step: usize,
+		last_frame: u16,
+		#[serde(default, skip_serializing_if = "is_false")]
+		fx16: bool,
+		values: Vec<T>
+	}
+}
+
+fn is_false(b: &bool) -> bool {
+	!b
+}
+
+const CONSTANT: u32 = 0x20000000;
+const FX16: u32 = 0x10000000;
+const STEP_2: u32 = 0x40000000;
+const STEP_4: u32 = 0x80000000;
+
+fn step_of(tag: u32) -> usize {
+	if tag & STEP_4 != 0 {4} else if tag & STEP_2 != 0 {2} else {1}
+}
+
+fn step_bits(step: usize) -> u32 {
+	match step {
+		4 => STEP_4,
+		2 => STEP_2,
+		_ => 0
+	}
+}
+
+// how many samples a curve has: every `step` frames until the last one, then every frame
+fn sample_count(frames: usize, step: usize, last_frame: usize) -> usize {
+	if step == 1 || last_frame >= frames {
+		frames.div_ceil(step)
+	} else {
+		last_frame / step + 1 + frames.saturating_sub(last_frame + 1)
+	}
+}
+
+fn read_magic(r: &mut Reader) -> Result<String, BErr> {
+	let bytes = [r.u8()?, r.u8()?, r.u8()?, r.u8()?];
+	Ok(bytes.iter().map(|b| *b as char).collect())
+}
+
+fn put_magic(out: &mut Vec<u8>, magic: &str) {
+	let mut bytes = magic.chars().map(|c| c as u8).chain(std::iter::repeat(0));
+	for _ in 0..4 {
+		out.push(bytes.next().unwrap());
+	}
+}
+
+fn align(out: &mut Vec<u8>) {
+	out.resize((out.len() + 3) & !3, 0);
+}
+
+fn read_names(buf: &[u8], at: usize, count: usize) -> Result<Vec<String>, BErr> {
+	(0..count).map(|i| {
+		let name = buf.get(at + i * 16..at + i * 16 + 16).ok_or("name out of range")?;
+		let len = name.iter().position(|b| *b == 0).unwrap_or(16);
+		Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+	}).collect()
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+	let mut bytes = name.bytes().chain(std::iter::repeat(0));
+	for _ in 0..16 {
+		out.push(bytes.next().unwrap());
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct PatternAnimation {
+	pub name: String,
+	pub magic: String,
+	pub frames: u16,
+	pub textures: Vec<String>,
+	pub palettes: Vec<String>,
+	pub materials: Vec<PatternMaterial>
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct PatternMaterial {
+	pub name: String,
+	pub flags: u16,
+	pub ratio: u16,
+	pub keyframes: Vec<PatternKeyframe>
+}
+
+// from `frame` on, the material is drawn with this texture and palette
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct PatternKeyframe {
+	pub frame: u16,
+	pub texture: String,
+	pub palette: String
+}
+
+impl PatternAnimation {
+	fn parse(buf: &[u8], at: usize, name: String) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, at);
+		let magic = read_magic(&mut r)?;
+		let frames = r.u16()?;
+		let texture_count = r.u8()? as usize;
+		let palette_count = r.u8()? as usize;
+		let textures = read_names(buf, at + r.u16()? as usize, texture_count)?;
+		let palettes = read_names(buf, at + r.u16()? as usize, palette_count)?;
+		let materials = read_dict(buf, at + 0xC)?.into_iter().map(|(name, entry)| {
+			let mut r = Reader::new(entry, 0);
+			let count = r.u16()? as usize;
+			let flags = r.u16()?;
+			let ratio = r.u16()?;
+			let mut keys = Reader::new(buf, at + r.u16()? as usize);
+			let keyframes = (0..count).map(|_| {
+				let frame = keys.u16()?;
+				let texture = keys.u8()? as usize;
+				let palette = keys.u8()? as usize;
+				Ok(PatternKeyframe {
+					frame,
+					texture: textures.get(texture).cloned().ok_or_else(|| format!("texture {} isn't in the list", texture))?,
+					palette: palettes.get(palette).cloned().ok_or_else(|| format!("palette {} isn't in the list", palette))?
+				})
+			}).collect::<Result<_, BErr>>()?;
+			Ok(PatternMaterial {name, flags, ratio, keyframes})
+		}).collect::<Result<_, BErr>>()?;
+		Ok(PatternAnimation {name, magic, frames, textures, palettes, materials})
+	}
+
+	fn to_bytes(&self) -> Result<Vec<u8>, BErr> {
+		// names used by keyframes that were added to the JSON without being listed go on the end of the lists
+		let mut textures = self.textures.clone();
+		let mut palettes = self.palettes.clone();
+		for key in self.materials.iter().flat_map(|m| &m.keyframes) {
+			if !textures.contains(&key.texture) {
+				textures.push(key.texture.clone());
+			}
+			if !palettes.contains(&key.palette) {
+				palettes.push(key.palette.clone());
+			}
+		}
+		if textures.len() > 255 || palettes.len() > 255 {
+			return Err("a pattern animation can only use 255 textures and palettes".into())
+		}
+		let names: Vec<String> = self.materials.iter().map(|m| m.name.clone()).collect();
+		let dict_len = write_dict(&names, &vec![vec![0; 8]; names.len()]).len();
+		let mut keys = Vec::new();
+		let mut entries = Vec::new();
+		let keys_at = 0xC + dict_len;
+		for material in &self.materials {
+			let mut entry = Vec::new();
+			entry.put_u16_le(material.keyframes.len() as u16);
+			entry.put_u16_le(material.flags);
+			entry.put_u16_le(material.ratio);
+			entry.put_u16_le((keys_at + keys.len()) as u16);
+			entries.push(entry);
+			for key in &material.keyframes {
+				keys.put_u16_le(key.frame);
+				keys.put_u8(textures.iter().position(|t| *t == key.texture).unwrap() as u8);
+				keys.put_u8(palettes.iter().position(|p| *p == key.palette).unwrap() as u8);
+			}
+		}
+		let textures_at = keys_at + keys.len();
+		let palettes_at = textures_at + textures.len() * 16;
+		let mut out = Vec::new();
+		put_magic(&mut out, &self.magic);
+		out.put_u16_le(self.frames);
+		out.put_u8(textures.len() as u8);
+		out.put_u8(palettes.len() as u8);
+		out.put_u16_le(textures_at as u16);
+		out.put_u16_le(palettes_at as u16);
+		out.extend_from_slice(&write_dict(&names, &entries));
+		out.extend_from_slice(&keys);
+		for name in textures.iter().chain(palettes.iter()) {
+			put_name(&mut out, name);
+		}
+		Ok(out)
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct SRTAnimation {
+	pub name: String,
+	pub magic: String,
+	pub frames: u16,
+	pub flags: u8,
+	pub matrix_mode: u8,
+	pub materials: Vec<SRTMaterial>
+}
+
+// rotation is in degrees
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct SRTMaterial {
+	pub name: String,
+	pub scale_s: Track<f32>,
+	pub scale_t: Track<f32>,
+	pub rotation: Track<f32>,
+	pub translate_s: Track<f32>,
+	pub translate_t: Track<f32>
+}
+
+// rotations are kept as a sine and cosine
+fn angle(sin: f32, cos: f32) -> f32 {
+	sin.atan2(cos).to_degrees()
+}
+
+fn sin_cos(degrees: f32) -> [u16; 2] {
+	let radians = degrees.to_radians();
+	[radians.sin(), radians.cos()].map(|v| (v * 4096.0).round() as i16 as u16)
+}
+
+impl SRTAnimation {
+	fn parse(buf: &[u8], at: usize, name: String) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, at);
+		let magic = read_magic(&mut r)?;
+		let frames = r.u16()?;
+		let flags = r.u8()?;
+		let matrix_mode = r.u8()?;
+		let materials = read_dict(buf, at + 8)?.into_iter().map(|(name, entry)| {
+			let mut r = Reader::new(entry, 0);
+			let mut tracks = Vec::with_capacity(5);
+			for i in 0..5 {
+				let tag = r.u32()?;
+				let value = r.u32()?;
+				let rotation = i == 2;
+				tracks.push(if tag & CONSTANT != 0 {
+					Track::Constant {constant: if rotation {
+						angle(value as u16 as i16 as f32, (value >> 16) as u16 as i16 as f32)
+					} else {
+						value as i32 as f32 / 4096.0
+					}}
+				} else {
+					let step = step_of(tag);
+					let last_frame = tag as u16;
+					let fx16 = tag & FX16 != 0;
+					let mut samples = Reader::new(buf, at + value as usize);
+					let values = (0..sample_count(frames as usize, step, last_frame as usize)).map(|_| {
+						Ok(if rotation {
+							let sin = samples.fx16()?;
+							angle(sin, samples.fx16()?)
+						} else if fx16 {
+							samples.fx16()?
+						} else {
+							samples.fx32()?
+						})
+					}).collect::<Result<_, BErr>>()?;
+					Track::Curve {step, last_frame, fx16: fx16 && !rotation, values}
+				});
+			}
+			let mut tracks = tracks.into_iter();
+			let mut next = || tracks.next().unwrap();
+			Ok(SRTMaterial {name, scale_s: next(), scale_t: next(), rotation: next(), translate_s: next(), translate_t: next()})
+		}).collect::<Result<_, BErr>>()?;
+		Ok(SRTAnimation {name, magic, frames, flags, matrix_mode, materials})
+	}
+
+	fn to_bytes(&self) -> Vec<u8> {
+		let names: Vec<String> = self.materials.iter().map(|m| m.name.clone()).collect();
+		let data_at = 8 + write_dict(&names, &vec![vec![0; 40]; names.len()]).len();
+		let mut data = Vec::new();
+		let mut entries = Vec::new();
+		for material in &self.materials {
+			let mut entry = Vec::new();
+			let tracks = [&material.scale_s, &material.scale_t, &material.rotation, &material.translate_s, &material.translate_t];
+			for (i, track) in tracks.iter().enumerate() {
+				let rotation = i == 2;
+				match track {
+					Track::Constant {constant} => {
+						entry.put_u32_le(CONSTANT);
+						if rotation {
+							let [sin, cos] = sin_cos(*constant);
+							entry.put_u16_le(sin);
+							entry.put_u16_le(cos);
+						} else {
+							entry.put_i32_le((constant * 4096.0).round() as i32);
+						}
+					}
+					Track::Curve {step, last_frame, fx16, values} => {
+						let fx16 = *fx16 && !rotation;
+						entry.put_u32_le(step_bits(*step) | if fx16 {FX16} else {0} | *last_frame as u32);
+						entry.put_u32_le((data_at + data.len()) as u32);
+						for value in values {
+							if rotation {
+								let [sin, cos] = sin_cos(*value);
+								data.put_u16_le(sin);
+								data.put_u16_le(cos);
+							} else if fx16 {
+								data.put_i16_le((value * 4096.0).round() as i16);
+							} else {
+								data.put_i32_le((value * 4096.0).round() as i32);
+							}
+						}
+						align(&mut data);
+					}
+				}
+			}
+			entries.push(entry);
+		}
+		let mut out = Vec::new();
+		put_magic(&mut out, &self.magic);
+		out.put_u16_le(self.frames);
+		out.put_u8(self.flags);
+		out.put_u8(self.matrix_mode);
+		out.extend_from_slice(&write_dict(&names, &entries));
+		out.extend_from_slice(&data);
+		out
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct ColorAnimation {
+	pub name: String,
+	pub magic: String,
+	pub frames: u16,
+	pub materials: Vec<ColorMaterial>
+}
+
+// colors are [r, g, b] from 0 to 31, and alpha goes from 0 to 31 too
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct ColorMaterial {
+	pub name: String,
+	pub diffuse: Track<[u8; 3]>,
+	pub ambient: Track<[u8; 3]>,
+	pub emission: Track<[u8; 3]>,
+	pub specular: Track<[u8; 3]>,
+	pub alpha: Track<u8>
+}
+
+fn split_color(color: u16) -> [u8; 3] {
+	[0, 5, 10].map(|s| ((color >> s) & 0x1F) as u8)
+}
+
+fn join_color(color: &[u8; 3]) -> u16 {
+	color.iter().enumerate().map(|(i, c)| ((*c as u16) & 0x1F) << (i * 5)).sum()
+}
+
+// Material color tracks pack everything into one word: the constant or where the samples are in the low 16 bits,
+// and the last frame above that
+const LAST_FRAME_MASK: u32 = 0x1FFF0000;
+
+fn read_color_track(buf: &[u8], at: usize, frames: usize, tag: u32) -> Result<Track<[u8; 3]>, BErr> {
+	if tag & CONSTANT != 0 {
+		return Ok(Track::Constant {constant: split_color(tag as u16)})
+	}
+	let step = step_of(tag);
+	let last_frame = ((tag & LAST_FRAME_MASK) >> 16) as u16;
+	let mut r = Reader::new(buf, at + (tag & 0xFFFF) as usize);
+	let values = (0..sample_count(frames, step, last_frame as usize)).map(|_| Ok(split_color(r.u16()?))).collect::<Result<_, BErr>>()?;
+	Ok(Track::Curve {step, last_frame, fx16: false, values})
+}
+
+fn read_alpha_track(buf: &[u8], at: usize, frames: usize, tag: u32) -> Result<Track<u8>, BErr> {
+	if tag & CONSTANT != 0 {
+		return Ok(Track::Constant {constant: tag as u8 & 0x1F})
+	}
+	let step = step_of(tag);
+	let last_frame = ((tag & LAST_FRAME_MASK) >> 16) as u16;
+	let mut r = Reader::new(buf, at + (tag & 0xFFFF) as usize);
+	let values = (0..sample_count(frames, step, last_frame as usize)).map(|_| r.u8()).collect::<Result<_, BErr>>()?;
+	Ok(Track::Curve {step, last_frame, fx16: false, values})
+}
+
+impl ColorAnimation {
+	fn parse(buf: &[u8], at: usize, name: String) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, at);
+		let magic = read_magic(&mut r)?;
+		let frames = r.u16()?;
+		let materials = read_dict(buf, at + 8)?.into_iter().map(|(name, entry)| {
+			let mut r = Reader::new(entry, 0);
+			let mut color = || -> Result<_, BErr> {read_color_track(buf, at, frames as usize, r.u32()?)};
+			let (diffuse, ambient, emission, specular) = (color()?, color()?, color()?, color()?);
+			let alpha = read_alpha_track(buf, at, frames as usize, r.u32()?)?;
+			Ok(ColorMaterial {name, diffuse, ambient, emission, specular, alpha})
+		}).collect::<Result<_, BErr>>()?;
+		Ok(ColorAnimation {name, magic, frames, materials})
+	}
+
+	fn to_bytes(&self) -> Result<Vec<u8>, BErr> {
+		let names: Vec<String> = self.materials.iter().map(|m| m.name.clone()).collect();
+		let data_at = 8 + write_dict(&names, &vec![vec![0; 20]; names.len()]).len();
+		let mut data = Vec::new();
+		let mut entries = Vec::new();
+		for material in &self.materials {
+			let mut entry = Vec::new();
+			for track in [&material.diffuse, &material.ambient, &material.emission, &material.specular] {
+				entry.put_u32_le(match track {
+					Track::Constant {constant} => CONSTANT | join_color(constant) as u32,
+					Track::Curve {step, last_frame, values, ..} => {
+						let offset = data_at + data.len();
+						for value in values {
+							data.put_u16_le(join_color(value));
+						}
+						align(&mut data);
+						color_tag(*step, *last_frame, offset)?
+					}
+				});
+			}
+			entry.put_u32_le(match &material.alpha {
+				Track::Constant {constant} => CONSTANT | (*constant & 0x1F) as u32,
+				Track::Curve {step, last_frame, values, ..} => {
+					let offset = data_at + data.len();
+					data.extend_from_slice(values);
+					align(&mut data);
+					color_tag(*step, *last_frame, offset)?
+				}
+			});
+			entries.push(entry);
+		}
+		let mut out = Vec::new();
+		put_magic(&mut out, &self.magic);
+		out.put_u16_le(self.frames);
+		out.put_u16_le(0);
+		out.extend_from_slice(&write_dict(&names, &entries));
+		out.extend_from_slice(&data);
+		Ok(out)
+	}
+}
+
+fn color_tag(step: usize, last_frame: u16, offset: usize) -> Result<u32, BErr> {
+	if offset > 0xFFFF {
+		return Err("material color animation is too big".into())
+	}
+	Ok(step_bits(step) | ((last_frame as u32) << 16 & LAST_FRAME_MASK) | offset as u32)
+}
+
+// Which bones are drawn on each frame, as a string of 0s and 1s for each bone
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct VisibilityAnimation {
+	pub name: String,
+	pub magic: String,
+	pub frames: u16,
+	pub bones: Vec<String>
+}
+
+impl VisibilityAnimation {
+	fn parse(buf: &[u8], at: usize, name: String) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, at);
+		let magic = read_magic(&mut r)?;
+		let frames = r.u16()?;
+		let bone_count = r.u16()? as usize;
+		r.pos += 4; // size and padding
+		let bits = buf.get(r.pos..).ok_or("visibility data out of range")?;
+		let visible = |i: usize| -> Result<bool, BErr> {
+			let byte = bits.get(i / 8).ok_or("visibility data out of range")?;
+			Ok(byte >> (i % 8) & 1 != 0)
+		};
+		let bones = (0..bone_count).map(|bone| {
+			(0..frames as usize).map(|frame| Ok(if visible(frame * bone_count + bone)? {'1'} else {'0'})).collect()
+		}).collect::<Result<_, BErr>>()?;
+		Ok(VisibilityAnimation {name, magic, frames, bones})
+	}
+
+	fn to_bytes(&self) -> Result<Vec<u8>, BErr> {
+		let frames = self.frames as usize;
+		let words = (frames * self.bones.len()).div_ceil(32);
+		let mut bits = vec![0u32; words];
+		for (bone, line) in self.bones.iter().enumerate() {
+			if line.len() != frames {
+				return Err(format!("bone {} has {} frames of visibility, but the animation is {} frames", bone, line.len(), frames).into())
+			}
+			for (frame, c) in line.chars().enumerate() {
+				if c == '1' {
+					let i = frame * self.bones.len() + bone;
+					bits[i / 32] |= 1 << (i % 32);
+				}
+			}
+		}
+		let mut out = Vec::new();
+		put_magic(&mut out, &self.magic);
+		out.put_u16_le(self.frames);
+		out.put_u16_le(self.bones.len() as u16);
+		out.put_u16_le((12 + words * 4) as u16);
+		out.put_u16_le(0);
+		for word in bits {
+			out.put_u32_le(word);
+		}
+		Ok(out)
+	}
+}
+
+fn parse_all<T>(buf: &[u8], section: u32, parse: fn(&[u8], usize, String) -> Result<T, BErr>) -> Result<Vec<T>, BErr> {
+	let file = NitroFile::parse_g3d(buf)?;
+	let start = file.get_section_offset(section).ok_or("the animation section is missing")? - 8;
+	read_dict(buf, start + 8)?.into_iter().map(|(name, entry)| {
+		let offset = Reader::new(entry, 0).u32()? as usize;
+		parse(buf, start + offset, name)
+	}).collect()
+}
+
+fn version(buf: &[u8]) -> Result<u16, BErr> {
+	let v = buf.get(6..8).ok_or("animation file too short for a header")?;
+	Ok(u16::from_le_bytes([v[0], v[1]]))
+}
+
+impl AnimationFile {
+	pub fn parse(buf: &[u8]) -> Result<Option<Self>, BErr> {
+		let magic = match buf.get(..4) {
+			Some(m) => u32::from_le_bytes([m[0], m[1], m[2], m[3]]),
+			None => return Ok(None)
+		};
+		Ok(Some(match magic {
+			NSBTP_MAGIC => AnimationFile::Pattern {version: version(buf)?, animations: parse_all(buf, PAT0_MAGIC, PatternAnimation::parse)?},
+			NSBTA_MAGIC => AnimationFile::TextureSRT {version: version(buf)?, animations: parse_all(buf, SRT0_MAGIC, SRTAnimation::parse)?},
+			NSBMA_MAGIC => AnimationFile::MaterialColor {version: version(buf)?, animations: parse_all(buf, MAT0_MAGIC, ColorAnimation::parse)?},
+			NSBVA_MAGIC => AnimationFile::Visibility {version: version(buf)?, animations: parse_all(buf, VIS0_MAGIC, VisibilityAnimation::parse)?},
+			_ => return Ok(None)
+		}))
+	}
+
+	pub fn to_bytes(&self) -> Result<Vec<u8>, BErr> {
+		let (magic, section, version, names, animations): (u32, u32, u16, Vec<&String>, Vec<Vec<u8>>) = match self {
+			AnimationFile::Pattern {version, animations} => (NSBTP_MAGIC, PAT0_MAGIC, *version,
+				animations.iter().map(|a| &a.name).collect(), animations.iter().map(|a| a.to_bytes()).collect::<Result<_, _>>()?),
+			AnimationFile::TextureSRT {version, animations} => (NSBTA_MAGIC, SRT0_MAGIC, *version,
+				animations.iter().map(|a| &a.name).collect(), animations.iter().map(|a| a.to_bytes()).collect()),
+			AnimationFile::MaterialColor {version, animations} => (NSBMA_MAGIC, MAT0_MAGIC, *version,
+				animations.iter().map(|a| &a.name).collect(), animations.iter().map(|a| a.to_bytes()).collect::<Result<_, _>>()?),
+			AnimationFile::Visibility {version, animations} => (NSBVA_MAGIC, VIS0_MAGIC, *version,
+				animations.iter().map(|a| &a.name).collect(), animations.iter().map(|a| a.to_bytes()).collect::<Result<_, _>>()?)
+		};
+		let names: Vec<String> = names.into_iter().cloned().collect();
+		let dict_len = write_dict(&names, &vec![vec![0; 4]; names.len()]).len();
+		let mut offset = (8 + dict_len + 3) & !3;
+		let mut entries = Vec::new();
+		for animation in &animations {
+			entries.push((offset as u32).to_le_bytes().to_vec());
+			offset += (animation.len() + 3) & !3;
+		}
+		let mut block = Vec::new();
+		block.put_u32_le(section);
+		block.put_u32_le(offset as u32);
+		block.extend_from_slice(&write_dict(&names, &entries));
+		align(&mut block);
+		for animation in animations {
+			block.extend_from_slice(&animation);
+			align(&mut block);
+		}
+		// a Nitro header with one section
+		let mut out = Vec::new();
+		out.put_u32_le(magic);
+		out.put_u16_le(0xFEFF);
+		out.put_u16_le(version);
+		out.put_u32_le((0x14 + block.len()) as u32);
+		out.put_u16_le(0x10);
+		out.put_u16_le(1);
+		out.put_u32_le(0x14);
+		out.extend_from_slice(&block);
+		Ok(out)
+	}
+}
+
+// Writes `<file>.json` next to an animation file
+pub fn write_animation_json(helper: &IOHelper, path: &RelPath, buf: &[u8]) {
+	let result = AnimationFile::parse(buf).and_then(|parsed| {
+		if let Some(parsed) = parsed {
+			let mut json_path = path.clone();
+			let name = json_path.pop().unwrap_or_default();
+			json_path.push(format!("{}.json", name));
+			helper.write_file(&json_path, &serde_json::to_vec_pretty(&parsed)?)?;
+		}
+		Ok(())
+	});
+	if let Err(e) = result {
+		println!("Couldn't convert {:?} to JSON: {}", path, e);
+	}
+}
+
+// Rebuilds an animation file from its `<file>.json` when the JSON doesn't match the file anymore
+pub fn import_animation_json(helper: &IOHelper, dir: &RelPath, file_name: &str, file: Bytes) -> Result<Bytes, BErr> {
+	let mut json_path = dir.clone();
+	json_path.push(format!("{}.json", file_name));
+	if !helper.is_file(&json_path) {
+		return Ok(file)
+	}
+	let original = match AnimationFile::parse(&file)? {
+		Some(original) => original,
+		None => return Ok(file)
+	};
+	let edited: AnimationFile = serde_json::from_slice(&helper.read_file(&json_path)?)
+		.map_err(|e| format!("{:?}: {}", json_path, e))?;
+	if edited == original {
+		return Ok(file)
+	}
+	Ok(Bytes::from(edited.to_bytes()?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn json_survives_a_trip_through_the_binary() {
+		let files = [
+			json!({"format": "NSBTP", "version": 1, "animations": [{
+				"name": "blink", "magic": "M\0PT", "frames": 4, "textures": ["open", "shut"], "palettes": ["eye_pl"],
+				"materials": [{"name": "eye", "flags": 0, "ratio": 0, "keyframes": [
+					{"frame": 0, "texture": "open", "palette": "eye_pl"},
+					{"frame": 2, "texture": "shut", "palette": "eye_pl"}
+				]}]
+			}]}),
+			json!({"format": "NSBTA", "version": 1, "animations": [{
+				"name": "flow", "magic": "M\0AT", "frames": 4, "flags": 0, "matrix_mode": 0,
+				"materials": [{"name": "water",
+					"scale_s": {"constant": 1.0},
+					"scale_t": {"constant": 1.5},
+					"rotation": {"constant": 90.0},
+					"translate_s": {"step": 2, "last_frame": 2, "values": [0.0, 0.25, 0.5]},
+					"translate_t": {"step": 1, "last_frame": 3, "fx16": true, "values": [0.0, -0.5, -1.0, -1.5]}
+				}]
+			}]}),
+			json!({"format": "NSBMA", "version": 1, "animations": [{
+				"name": "flash", "magic": "M\0AM", "frames": 2,
+				"materials": [{"name": "body",
+					"diffuse": {"step": 1, "last_frame": 1, "values": [[31, 0, 0], [0, 31, 0]]},
+					"ambient": {"constant": [1, 2, 3]},
+					"emission": {"constant": [0, 0, 0]},
+					"specular": {"constant": [31, 31, 31]},
+					"alpha": {"step": 1, "last_frame": 1, "values": [31, 16]}
+				}]
+			}]}),
+			json!({"format": "NSBVA", "version": 1, "animations": [{
+				"name": "hide", "magic": "V\0AV", "frames": 3, "bones": ["110", "011"]
+			}]})
+		];
+		for json in files {
+			let file: AnimationFile = serde_json::from_value(json.clone()).unwrap();
+			let bytes = file.to_bytes().unwrap();
+			let parsed = AnimationFile::parse(&bytes).unwrap().unwrap();
+			assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+			// and it packs to the same bytes again
+			assert_eq!(parsed.to_bytes().unwrap(), bytes, "{}", json["format"]);
+		}
+	}
+}
@@ -0,0 +1,555 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	nitro::NitroFile,
+	util::Reader
+};
+use bytes::{Bytes, BufMut};
+
+// The wave archives (SWAR) and streams (STRM) of sound archives, converted to WAV and back.
+// Samples are kept as 16 bit, one list per channel, and loops run from `loop_start` to the end.
+#[derive(PartialEq, Debug)]
+pub struct Sound {
+	pub rate: u32,
+	pub channels: Vec<Vec<i16>>,
+	pub loop_start: Option<usize>
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Encoding {
+	PCM8,
+	PCM16,
+	ADPCM
+}
+
+impl Encoding {
+	fn from_type(ty: u8) -> Result<Self, BErr> {
+		match ty {
+			0 => Ok(Self::PCM8),
+			1 => Ok(Self::PCM16),
+			2 => Ok(Self::ADPCM),
+			_ => Err(format!("unknown wave type {}", ty).into())
+		}
+	}
+
+	fn to_type(self) -> u8 {
+		self as u8
+	}
+
+	// how many samples a block of this many bytes holds, ADPCM blocks start with a 4 byte header
+	fn samples_in(self, bytes: usize) -> usize {
+		match self {
+			Self::PCM8 => bytes,
+			Self::PCM16 => bytes / 2,
+			Self::ADPCM => bytes.saturating_sub(4) * 2
+		}
+	}
+
+	fn bytes_for(self, samples: usize) -> usize {
+		match self {
+			Self::PCM8 => samples,
+			Self::PCM16 => samples * 2,
+			Self::ADPCM => 4 + samples.div_ceil(2)
+		}
+	}
+
+	fn decode(self, buf: &[u8], samples: usize) -> Result<Vec<i16>, BErr> {
+		let buf = buf.get(..self.bytes_for(samples)).ok_or("wave data is past the end of the file")?;
+		Ok(match self {
+			Self::PCM8 => buf.iter().map(|s| (*s as i8 as i16) << 8).collect(),
+			Self::PCM16 => buf.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect(),
+			Self::ADPCM => {
+				let mut state = ADPCMState {
+					predictor: i16::from_le_bytes([buf[0], buf[1]]) as i32,
+					index: (buf[2] as i32).min(88)
+				};
+				buf[4..].iter().flat_map(|b| [b & 0xF, b >> 4]).take(samples).map(|n| state.step(n)).collect()
+			}
+		})
+	}
+
+	fn encode(self, samples: &[i16]) -> Vec<u8> {
+		match self {
+			Self::PCM8 => samples.iter().map(|s| (s >> 8) as u8).collect(),
+			Self::PCM16 => samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+			Self::ADPCM => {
+				let first = samples.first().copied().unwrap_or(0);
+				// start with the step size that follows the first few samples best, loud sounds would take a while
+				// to catch up from the smallest one
+				let start = &samples[..samples.len().min(64)];
+				let index = (0..89).min_by_key(|index| {
+					let mut state = ADPCMState {predictor: first as i32, index: *index};
+					start.iter().map(|s| {
+						state.encode(*s);
+						(state.predictor - *s as i32).abs()
+					}).sum::<i32>()
+				}).unwrap();
+				let mut state = ADPCMState {predictor: first as i32, index};
+				let mut out = Vec::with_capacity(self.bytes_for(samples.len()));
+				out.extend_from_slice(&first.to_le_bytes());
+				out.extend_from_slice(&[index as u8, 0]);
+				for pair in samples.chunks(2) {
+					let low = state.encode(pair[0]);
+					let high = pair.get(1).map_or(0, |s| state.encode(*s));
+					out.push(low | (high << 4));
+				}
+				out
+			}
+		}
+	}
+}
+
+const ADPCM_INDEX: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+const ADPCM_STEPS: [i32; 89] = [
+	7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97, 107,
+	118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963,
+	1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894,
+	6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+	32767
+];
+
+#[derive(Clone, Copy)]
+struct ADPCMState {
+	predictor: i32,
+	index: i32
+}
+
+impl ADPCMState {
+	fn step(&mut self, nibble: u8) -> i16 {
+		let step = ADPCM_STEPS[self.index as usize];
+		let mut diff = step >> 3;
+		if nibble & 1 != 0 {
+			diff += step >> 2;
+		}
+		if nibble & 2 != 0 {
+			diff += step >> 1;
+		}
+		if nibble & 4 != 0 {
+			diff += step;
+		}
+		// the DS clamps to -0x7FFF, not -0x8000
+		self.predictor = if nibble & 8 != 0 {(self.predictor - diff).max(-0x7FFF)} else {(self.predictor + diff).min(0x7FFF)};
+		self.index = (self.index + ADPCM_INDEX[(nibble & 7) as usize]).clamp(0, 88);
+		self.predictor as i16
+	}
+
+	// the nibble that lands closest to `sample`
+	fn encode(&mut self, sample: i16) -> u8 {
+		let (nibble, state) = (0..16).map(|n| {
+			let mut state = *self;
+			let out = state.step(n);
+			(n, state, (out as i32 - sample as i32).abs())
+		}).min_by_key(|(_, _, error)| *error).map(|(n, state, _)| (n, state)).unwrap();
+		*self = state;
+		nibble
+	}
+}
+
+// DS timers count at about 16.76MHz
+fn timer_for(rate: u32) -> u16 {
+	(16756991 / rate.max(1)) as u16
+}
+
+// A wave of a SWAR: 12 bytes of info, then the samples
+struct Wave {
+	encoding: Encoding,
+	sound: Sound
+}
+
+const WAVE_HEADER: usize = 12;
+type MovedLoop = (usize, usize); // from, to
+
+impl Wave {
+	fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, 0);
+		let encoding = Encoding::from_type(r.u8()?)?;
+		let looped = r.u8()? != 0;
+		let rate = r.u16()? as u32;
+		r.pos += 2; // timer
+		let loop_offset = r.u16()? as usize * 4;
+		let length = loop_offset + r.u32()? as usize * 4;
+		let samples = encoding.samples_in(length);
+		let loop_start = if looped {Some(encoding.samples_in(loop_offset))} else {None};
+		Ok(Wave {encoding, sound: Sound {rate, channels: vec![encoding.decode(&buf[WAVE_HEADER..], samples)?], loop_start}})
+	}
+
+	// Also gives the loop start if it had to move
+	fn to_bytes(&self) -> Result<(Vec<u8>, Option<MovedLoop>), BErr> {
+		if self.sound.channels.len() != 1 {
+			return Err(format!("waves in wave archives are mono, this one has {} channels", self.sound.channels.len()).into())
+		}
+		let mut samples = self.sound.channels[0].clone();
+		// the loop has to start on a word and the wave has to end on one
+		let per_word = match self.encoding {
+			Encoding::PCM8 => 4,
+			Encoding::PCM16 => 2,
+			Encoding::ADPCM => 8
+		};
+		if let Some(start) = self.sound.loop_start.filter(|s| *s >= samples.len()) {
+			return Err(format!("the loop starts at sample {}, but there are only {} samples", start, samples.len()).into())
+		}
+		let loop_start = self.sound.loop_start.map(|s| s - s % per_word);
+		let moved = self.sound.loop_start.zip(loop_start).filter(|(from, to)| from != to);
+		samples.resize(samples.len().div_ceil(per_word) * per_word, 0);
+		let data = self.encoding.encode(&samples);
+		let loop_offset = self.encoding.bytes_for(loop_start.unwrap_or(0)) / 4;
+		let mut buf = Vec::with_capacity(WAVE_HEADER + data.len());
+		buf.put_u8(self.encoding.to_type());
+		buf.put_u8(loop_start.is_some() as u8);
+		buf.put_u16_le(self.sound.rate as u16);
+		buf.put_u16_le(timer_for(self.sound.rate));
+		buf.put_u16_le(loop_offset as u16);
+		buf.put_u32_le((data.len() / 4 - loop_offset) as u32);
+		buf.extend_from_slice(&data);
+		Ok((buf, moved))
+	}
+}
+
+// The waves of a SWAR, as where each one is in the file and what it sounds like
+fn parse_swar(buf: &[u8]) -> Result<Vec<(usize, usize, Wave)>, BErr> {
+	let file = NitroFile::parse(buf)?;
+	let data = file.get_section_offset(DATA_MAGIC).ok_or("SWAR has no DATA section")?;
+	let end = data + file.get_section(DATA_MAGIC).unwrap().len();
+	let mut r = Reader::new(buf, data + 32);
+	let count = r.u32()? as usize;
+	let offsets = (0..count).map(|_| Ok(r.u32()? as usize)).collect::<Result<Vec<_>, BErr>>()?;
+	offsets.iter().enumerate().map(|(i, start)| {
+		let next = offsets.get(i + 1).copied().unwrap_or(end);
+		let wave = buf.get(*start..next).ok_or("SWAR wave is out of bounds")?;
+		Ok((*start, next, Wave::parse(wave)?))
+	}).collect()
+}
+
+// Lays the waves out again after the original header and offsets
+fn write_swar(original: &[u8], waves: &[Vec<u8>]) -> Result<Vec<u8>, BErr> {
+	let data = NitroFile::parse(original)?.get_section_offset(DATA_MAGIC).ok_or("SWAR has no DATA section")?;
+	let waves_start = data + 36 + waves.len() * 4;
+	let mut buf = original[..data + 36].to_vec();
+	let mut offset = waves_start;
+	for wave in waves {
+		buf.put_u32_le(offset as u32);
+		offset += wave.len();
+	}
+	for wave in waves {
+		buf.extend_from_slice(wave);
+	}
+	let size = buf.len() as u32;
+	buf[8..12].copy_from_slice(&size.to_le_bytes());
+	buf[data - 4..data].copy_from_slice(&(size - data as u32 + 8).to_le_bytes());
+	Ok(buf)
+}
+
+// A STRM is split in blocks, each holding a piece of every channel one after the other
+struct Stream {
+	encoding: Encoding,
+	block_length: usize,
+	sound: Sound
+}
+
+const HEAD_SIZE: usize = 0x50;
+
+impl Stream {
+	fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let file = NitroFile::parse(buf)?;
+		let head = file.get_section_offset(HEAD_MAGIC).ok_or("STRM has no HEAD section")?;
+		let mut r = Reader::new(buf, head);
+		let encoding = Encoding::from_type(r.u8()?)?;
+		let looped = r.u8()? != 0;
+		let channel_count = r.u8()? as usize;
+		r.pos += 1;
+		let rate = r.u16()? as u32;
+		r.pos += 2; // timer
+		let loop_start = r.u32()? as usize;
+		r.pos += 4; // sample count
+		let data = r.u32()? as usize;
+		let blocks = r.u32()? as usize;
+		let block_length = r.u32()? as usize;
+		let block_samples = r.u32()? as usize;
+		let last_length = r.u32()? as usize;
+		let last_samples = r.u32()? as usize;
+		let mut channels = vec![Vec::new(); channel_count];
+		let mut at = data;
+		for block in 0..blocks {
+			let last = block + 1 == blocks;
+			let (length, samples) = if last {(last_length, last_samples)} else {(block_length, block_samples)};
+			for channel in channels.iter_mut() {
+				let block = buf.get(at..).ok_or("STRM block is past the end of the file")?;
+				channel.extend(encoding.decode(block, samples)?);
+				at += if last {(length + 3) & !3} else {length};
+			}
+		}
+		Ok(Stream {encoding, block_length, sound: Sound {rate, channels, loop_start: if looped {Some(loop_start)} else {None}}})
+	}
+
+	// Keeps the original's block size and whatever else in its HEAD this doesn't know about
+	fn to_bytes(&self, original: &[u8]) -> Result<Vec<u8>, BErr> {
+		let head = NitroFile::parse(original)?.get_section_offset(HEAD_MAGIC).ok_or("STRM has no HEAD section")?;
+		let sound = &self.sound;
+		let samples = sound.channels.first().map_or(0, |c| c.len());
+		if sound.channels.iter().any(|c| c.len() != samples) {
+			return Err("channels have different lengths".into())
+		}
+		let block_samples = self.encoding.samples_in(self.block_length).max(1);
+		let blocks = samples.div_ceil(block_samples).max(1);
+		let last_samples = samples - (blocks - 1) * block_samples;
+		let last_length = self.encoding.bytes_for(last_samples);
+		let mut data = Vec::new();
+		for block in 0..blocks {
+			for channel in &sound.channels {
+				let start = block * block_samples;
+				let encoded = self.encoding.encode(&channel[start..(start + block_samples).min(samples)]);
+				data.extend_from_slice(&encoded);
+				data.resize((data.len() + 3) & !3, 0);
+			}
+		}
+		let mut buf = original[..head - 8].to_vec();
+		buf.extend_from_slice(b"HEAD");
+		buf.put_u32_le(HEAD_SIZE as u32);
+		buf.put_u8(self.encoding.to_type());
+		buf.put_u8(sound.loop_start.is_some() as u8);
+		buf.put_u8(sound.channels.len() as u8);
+		buf.put_u8(original[head + 3]);
+		buf.put_u16_le(sound.rate as u16);
+		buf.put_u16_le(timer_for(sound.rate));
+		buf.put_u32_le(sound.loop_start.unwrap_or(0) as u32);
+		buf.put_u32_le(samples as u32);
+		buf.put_u32_le((head - 8 + HEAD_SIZE + 8) as u32);
+		buf.put_u32_le(blocks as u32);
+		buf.put_u32_le(self.block_length as u32);
+		buf.put_u32_le(block_samples as u32);
+		buf.put_u32_le(last_length as u32);
+		buf.put_u32_le(last_samples as u32);
+		buf.extend_from_slice(original.get(head + 0x28..head + HEAD_SIZE - 8).ok_or("STRM HEAD is too short")?);
+		buf.extend_from_slice(b"DATA");
+		buf.put_u32_le(data.len() as u32 + 8);
+		buf.extend_from_slice(&data);
+		let size = buf.len() as u32;
+		buf[8..12].copy_from_slice(&size.to_le_bytes());
+		Ok(buf)
+	}
+}
+
+fn write_wav(sound: &Sound, bits: u16) -> Vec<u8> {
+	let channels = sound.channels.len().max(1);
+	let samples = sound.channels.first().map_or(0, |c| c.len());
+	let block_align = channels * bits as usize / 8;
+	let mut data = Vec::with_capacity(samples * block_align);
+	for i in 0..samples {
+		for channel in &sound.channels {
+			if bits == 8 {
+				data.put_u8(((channel[i] >> 8) as u8) ^ 0x80); // 8 bit WAVs are unsigned
+			} else {
+				data.put_i16_le(channel[i]);
+			}
+		}
+	}
+	let mut buf = Vec::with_capacity(data.len() + 128);
+	buf.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+	buf.extend_from_slice(b"fmt ");
+	buf.put_u32_le(16);
+	buf.put_u16_le(1); // PCM
+	buf.put_u16_le(channels as u16);
+	buf.put_u32_le(sound.rate);
+	buf.put_u32_le(sound.rate * block_align as u32);
+	buf.put_u16_le(block_align as u16);
+	buf.put_u16_le(bits);
+	if let Some(loop_start) = sound.loop_start {
+		buf.extend_from_slice(b"smpl");
+		buf.put_u32_le(36 + 24);
+		buf.put_u32_le(0); // manufacturer
+		buf.put_u32_le(0); // product
+		buf.put_u32_le(1_000_000_000 / sound.rate.max(1)); // sample period in nanoseconds
+		buf.put_u32_le(60); // unity note
+		buf.put_u32_le(0); // pitch fraction
+		buf.put_u32_le(0); // SMPTE format
+		buf.put_u32_le(0); // SMPTE offset
+		buf.put_u32_le(1); // loop count
+		buf.put_u32_le(0); // sampler data
+		buf.put_u32_le(0); // cue point
+		buf.put_u32_le(0); // forward loop
+		buf.put_u32_le(loop_start as u32);
+		buf.put_u32_le(samples.saturating_sub(1) as u32); // the last sample played, not the one after
+		buf.put_u32_le(0); // fraction
+		buf.put_u32_le(0); // loop forever
+	}
+	buf.extend_from_slice(b"data");
+	buf.put_u32_le(data.len() as u32);
+	buf.extend_from_slice(&data);
+	if data.len() & 1 != 0 {
+		buf.put_u8(0);
+	}
+	let size = buf.len() as u32 - 8;
+	buf[4..8].copy_from_slice(&size.to_le_bytes());
+	buf
+}
+
+// Reads 8 or 16 bit PCM WAVs. Anything after the end of the first loop is dropped, since the DS loops to the end.
+fn read_wav(buf: &[u8]) -> Result<Sound, BErr> {
+	if buf.get(..4) != Some(b"RIFF") || buf.get(8..12) != Some(b"WAVE") {
+		return Err("not a WAV file".into())
+	}
+	let mut format = None;
+	let mut data = None;
+	let mut loop_points = None;
+	let mut at = 12;
+	while at + 8 <= buf.len() {
+		let mut r = Reader::new(buf, at + 4);
+		let size = r.u32()? as usize;
+		let chunk = buf.get(at + 8..at + 8 + size).ok_or("WAV chunk is past the end of the file")?;
+		let mut r = Reader::new(chunk, 0);
+		match &buf[at..at + 4] {
+			b"fmt " => {
+				let tag = r.u16()?;
+				let channels = r.u16()? as usize;
+				let rate = r.u32()?;
+				r.pos += 6;
+				let bits = r.u16()?;
+				if (tag != 1 && tag != 0xFFFE) || (bits != 8 && bits != 16) || channels == 0 {
+					return Err("WAVs have to be 8 or 16 bit PCM".into())
+				}
+				format = Some((channels, rate, bits));
+			}
+			b"data" => data = Some(chunk),
+			b"smpl" => {
+				r.pos = 28;
+				if r.u32()? > 0 {
+					r.pos = 36 + 8;
+					loop_points = Some((r.u32()? as usize, r.u32()? as usize));
+				}
+			}
+			_ => ()
+		}
+		at += 8 + size + (size & 1);
+	}
+	let (channel_count, rate, bits) = format.ok_or("WAV has no fmt chunk")?;
+	let data = data.ok_or("WAV has no data chunk")?;
+	let width = bits as usize / 8;
+	let mut channels = vec![Vec::new(); channel_count];
+	for frame in data.chunks_exact(width * channel_count) {
+		for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(width)) {
+			channel.push(if bits == 8 {((sample[0] ^ 0x80) as i8 as i16) << 8} else {i16::from_le_bytes([sample[0], sample[1]])});
+		}
+	}
+	if let Some((_, end)) = loop_points {
+		for channel in channels.iter_mut() {
+			channel.truncate(end + 1);
+		}
+	}
+	Ok(Sound {rate, channels, loop_start: loop_points.map(|(start, _)| start)})
+}
+
+fn wav_bits(encoding: Encoding) -> u16 {
+	if encoding == Encoding::PCM8 {8} else {16}
+}
+
+// `<file>.<n>.wav` for each wave of a SWAR, `<file>.wav` for a STRM
+fn wav_path(path: &RelPath, file_name: &str, index: Option<usize>) -> RelPath {
+	let mut path = path.clone();
+	path.push(match index {
+		Some(i) => format!("{}.{}.wav", file_name, i),
+		None => format!("{}.wav", file_name)
+	});
+	path
+}
+
+// Writes WAVs next to a SWAR or STRM
+pub fn write_wavs(helper: &IOHelper, path: &RelPath, buf: &[u8]) {
+	let mut dir = path.clone();
+	let name = dir.pop().unwrap_or_default();
+	let magic = buf.get(..4).map(|m| u32::from_le_bytes([m[0], m[1], m[2], m[3]]));
+	let result = (|| -> Result<(), BErr> {
+		if magic == Some(SWAR_MAGIC) {
+			for (i, (_, _, wave)) in parse_swar(buf)?.iter().enumerate() {
+				helper.write_file(&wav_path(&dir, &name, Some(i)), &write_wav(&wave.sound, wav_bits(wave.encoding)))?;
+			}
+		} else if magic == Some(STRM_MAGIC) {
+			let stream = Stream::parse(buf)?;
+			helper.write_file(&wav_path(&dir, &name, None), &write_wav(&stream.sound, wav_bits(stream.encoding)))?;
+		}
+		Ok(())
+	})();
+	if let Err(e) = result {
+		println!("Couldn't convert {:?} to WAV: {}", path, e);
+	}
+}
+
+// Encodes WAVs that no longer sound like their SWAR or STRM back into it, in the encoding it used
+pub fn import_wavs(helper: &IOHelper, dir: &RelPath, file_name: &str, file: Bytes) -> Result<Bytes, BErr> {
+	let magic = file.get(..4).map(|m| u32::from_le_bytes([m[0], m[1], m[2], m[3]]));
+	if magic == Some(SWAR_MAGIC) {
+		let waves = parse_swar(&file)?;
+		let mut changed = false;
+		let mut encoded = Vec::with_capacity(waves.len());
+		for (i, (start, end, wave)) in waves.into_iter().enumerate() {
+			let path = wav_path(dir, file_name, Some(i));
+			let edited = if helper.is_file(&path) {
+				Some(read_wav(&helper.read_file(&path)?).map_err(|e| format!("{:?}: {}", path, e))?)
+			} else {
+				None
+			};
+			match edited {
+				Some(sound) if sound != wave.sound => {
+					changed = true;
+					let (bytes, moved) = Wave {encoding: wave.encoding, sound}.to_bytes().map_err(|e| format!("{:?}: {}", path, e))?;
+					if let Some((from, to)) = moved {
+						println!("Moved the loop start of {:?} from sample {} to {} to fit the wave's encoding", path, from, to);
+					}
+					encoded.push(bytes);
+				}
+				_ => encoded.push(file[start..end].to_vec())
+			}
+		}
+		if changed {
+			return Ok(Bytes::from(write_swar(&file, &encoded)?))
+		}
+	} else if magic == Some(STRM_MAGIC) {
+		let path = wav_path(dir, file_name, None);
+		if helper.is_file(&path) {
+			let stream = Stream::parse(&file)?;
+			let sound = read_wav(&helper.read_file(&path)?).map_err(|e| format!("{:?}: {}", path, e))?;
+			if sound != stream.sound {
+				let edited = Stream {encoding: stream.encoding, block_length: stream.block_length, sound};
+				return Ok(Bytes::from(edited.to_bytes(&file).map_err(|e| format!("{:?}: {}", path, e))?))
+			}
+		}
+	}
+	Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sound(loop_start: Option<usize>) -> Sound {
+		let left = (0..1000).map(|i| (i * 97 % 65536 - 32768) as i16).collect();
+		let right = (0..1000).map(|i| (i * -61 % 32768) as i16).collect();
+		Sound {rate: 32728, channels: vec![left, right], loop_start}
+	}
+
+	#[test]
+	fn wavs_round_trip() {
+		for loop_start in [None, Some(0), Some(400), Some(999)] {
+			let s = sound(loop_start);
+			assert_eq!(read_wav(&write_wav(&s, 16)).unwrap(), s);
+			// 8 bit only keeps the high byte of each sample
+			let mut low = sound(loop_start);
+			for channel in low.channels.iter_mut() {
+				for sample in channel.iter_mut() {
+					*sample &= !0xFF;
+				}
+			}
+			assert_eq!(read_wav(&write_wav(&low, 8)).unwrap(), low);
+		}
+	}
+
+	#[test]
+	fn samples_past_the_loop_end_are_dropped() {
+		let mut wav = write_wav(&sound(Some(10)), 16);
+		// the loop end is the fourth u32 of the loop record, which follows 36 bytes of sampler header
+		let end_at = wav.windows(4).position(|w| w == b"smpl").unwrap() + 8 + 36 + 12;
+		wav[end_at..end_at + 4].copy_from_slice(&99u32.to_le_bytes());
+		let read = read_wav(&wav).unwrap();
+		assert!(read.channels.iter().all(|c| c.len() == 100));
+	}
+}
@@ -0,0 +1,405 @@
+use crate::{
+	BErr, PK2D,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	nitro::NitroFile,
+	graphics::{IndexedImage, NCGR, NCLR, encode_indexed}
+};
+use bytes::Buf;
+use serde::Serialize;
+
+// (width, height) by shape, then size
+const OBJ_SIZES: [[(usize, usize); 4]; 3] = [
+	[(8, 8), (16, 16), (32, 32), (64, 64)],
+	[(16, 8), (32, 8), (32, 16), (64, 32)],
+	[(8, 16), (8, 32), (16, 32), (32, 64)]
+];
+
+// One hardware sprite, placed relative to the center of its cell
+#[derive(Serialize)]
+pub struct Oam {
+	pub x: i16,
+	pub y: i16,
+	pub width: usize,
+	pub height: usize,
+	pub tile: u16,
+	pub palette: u8,
+	pub priority: u8,
+	pub hflip: bool,
+	pub vflip: bool,
+	pub colors_256: bool,
+	pub affine: bool, // rotated/scaled sprites are drawn without their transform
+	pub double_size: bool,
+	pub mode: u8,
+	pub mosaic: bool,
+	pub attributes: [u16; 3]
+}
+
+impl Oam {
+	fn from_attributes(a: [u16; 3]) -> Self {
+		let affine = a[0] & 0x100 != 0;
+		let (width, height) = OBJ_SIZES[(a[0] >> 14).min(2) as usize][(a[1] >> 14) as usize];
+		Oam {
+			x: ((a[1] & 0x1FF) << 7) as i16 >> 7,
+			y: a[0] as u8 as i8 as i16,
+			width, height,
+			tile: a[2] & 0x3FF,
+			palette: (a[2] >> 12) as u8,
+			priority: (a[2] >> 10) as u8 & 3,
+			hflip: !affine && a[1] & 0x1000 != 0,
+			vflip: !affine && a[1] & 0x2000 != 0,
+			colors_256: a[0] & 0x2000 != 0,
+			affine,
+			double_size: affine && a[0] & 0x200 != 0,
+			mode: (a[0] >> 10) as u8 & 3,
+			mosaic: a[0] & 0x1000 != 0,
+			attributes: a
+		}
+	}
+}
+
+#[derive(Serialize)]
+pub struct Cell {
+	pub attributes: u16,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub bounds: Option<[i16; 4]>, // max x, max y, min x, min y, only in some files
+	pub oams: Vec<Oam>
+}
+
+impl Cell {
+	// (left, top, right, bottom) around all the sprites
+	fn extent(&self) -> Option<(i32, i32, i32, i32)> {
+		self.oams.iter().map(|o| (o.x as i32, o.y as i32, o.x as i32 + o.width as i32, o.y as i32 + o.height as i32))
+			.reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+	}
+
+	// draws the cell with its center at (x, y), earlier sprites go on top and color 0 is see-through
+	fn draw(&self, ncgr: &NCGR, mapping: u32, image: &mut IndexedImage, x: i32, y: i32) {
+		// tile numbers count 32 byte blocks, or bigger ones with the larger 1D mappings
+		let unit = if ncgr.bpp == 4 {64} else {32};
+		for oam in self.oams.iter().rev() {
+			let (columns, rows) = (oam.width / 8, oam.height / 8);
+			let base = if mapping < 4 {oam.tile as usize * (unit << mapping)} else {oam.tile as usize * unit};
+			for ty in 0..rows {
+				for tx in 0..columns {
+					let start = if mapping < 4 {
+						base + (ty * columns + tx) * 64
+					} else {
+						// 2D mapping lays the graphics out 32 blocks wide
+						base + ty * 32 * unit + tx * 64
+					};
+					let tile = match ncgr.pixels.get(start..start + 64) {
+						Some(tile) => tile,
+						None => continue
+					};
+					for (i, value) in tile.iter().enumerate() {
+						if *value == 0 {
+							continue;
+						}
+						let (px, py) = (tx * 8 + i % 8, ty * 8 + i / 8);
+						let px = if oam.hflip {oam.width - 1 - px} else {px};
+						let py = if oam.vflip {oam.height - 1 - py} else {py};
+						let dx = x + oam.x as i32 + px as i32;
+						let dy = y + oam.y as i32 + py as i32;
+						if dx < 0 || dy < 0 || dx >= image.width as i32 || dy >= image.height as i32 {
+							continue;
+						}
+						let bank = if ncgr.bpp == 4 {oam.palette * 16} else {0};
+						image.pixels[dy as usize * image.width + dx as usize] = value.wrapping_add(bank);
+					}
+				}
+			}
+		}
+	}
+}
+
+// NCER: cells built out of sprites
+#[derive(Serialize)]
+pub struct NCER {
+	pub mapping: u32, // 0-3 for 1D mapping with 32 to 256 byte boundaries, 4 for 2D
+	pub cells: Vec<Cell>
+}
+
+impl NCER {
+	pub fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let file = NitroFile::parse(buf)?;
+		if file.magic != NCER_MAGIC {
+			return Err("not an NCER".into())
+		}
+		let section = file.get_section(CEBK_MAGIC).ok_or("NCER has no cell section")?;
+		if section.len() < 0x10 {
+			return Err("cell section too small".into())
+		}
+		let mut header = section;
+		let count = header.get_u16_le() as usize;
+		let extended = header.get_u16_le() & 1 != 0;
+		let table = header.get_u32_le() as usize;
+		let mapping = header.get_u32_le();
+		let entry_len = if extended {16} else {8};
+		let oam_start = table + count * entry_len;
+		let mut cells = Vec::with_capacity(count);
+		for c in 0..count {
+			let mut entry = section.get(table + c * entry_len..table + (c + 1) * entry_len).ok_or("cell table out of range")?;
+			let oam_count = entry.get_u16_le() as usize;
+			let attributes = entry.get_u16_le();
+			let offset = oam_start + entry.get_u32_le() as usize;
+			let bounds = if extended {
+				Some([entry.get_i16_le(), entry.get_i16_le(), entry.get_i16_le(), entry.get_i16_le()])
+			} else {
+				None
+			};
+			let oams = section.get(offset..offset + oam_count * 6).ok_or("sprites out of range")?
+				.chunks_exact(6)
+				.map(|o| Oam::from_attributes([0, 2, 4].map(|i| u16::from_le_bytes([o[i], o[i + 1]]))))
+				.collect();
+			cells.push(Cell {attributes, bounds, oams});
+		}
+		Ok(NCER {mapping, cells})
+	}
+
+	pub fn render(&self, cell: &Cell, ncgr: &NCGR) -> IndexedImage {
+		let (left, top, right, bottom) = cell.extent().unwrap_or((0, 0, 1, 1));
+		let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+		let mut image = IndexedImage {width, height, pixels: vec![0; width * height]};
+		cell.draw(ncgr, self.mapping, &mut image, -left, -top);
+		image
+	}
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Playback {
+	Forward,
+	ForwardLoop,
+	PingPong,
+	PingPongLoop,
+	Unknown(u32)
+}
+
+impl Playback {
+	fn from_id(id: u32) -> Self {
+		match id {
+			1 => Self::Forward,
+			2 => Self::ForwardLoop,
+			3 => Self::PingPong,
+			4 => Self::PingPongLoop,
+			_ => Self::Unknown(id)
+		}
+	}
+
+	fn loops(self) -> bool {
+		matches!(self, Self::ForwardLoop | Self::PingPongLoop)
+	}
+}
+
+#[derive(Serialize)]
+pub struct Frame {
+	pub cell: u16,
+	pub duration: u16, // in 60ths of a second
+	pub x: i16,
+	pub y: i16,
+	pub rotation: u16,
+	pub scale_x: f32,
+	pub scale_y: f32
+}
+
+#[derive(Serialize)]
+pub struct Sequence {
+	pub playback: Playback,
+	pub loop_start: u16,
+	pub kind: u16,
+	pub element_type: u16, // 0 for just a cell, 1 with rotation, scale and position, 2 with position
+	pub frames: Vec<Frame>
+}
+
+// NANR: sequences of cells from an NCER
+#[derive(Serialize)]
+pub struct NANR {
+	pub sequences: Vec<Sequence>
+}
+
+impl NANR {
+	pub fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let file = NitroFile::parse(buf)?;
+		if file.magic != NANR_MAGIC {
+			return Err("not an NANR".into())
+		}
+		let section = file.get_section(ABNK_MAGIC).ok_or("NANR has no animation section")?;
+		if section.len() < 0x10 {
+			return Err("animation section too small".into())
+		}
+		let mut header = section;
+		let count = header.get_u16_le() as usize;
+		header.advance(2); // total frame count
+		let sequence_start = header.get_u32_le() as usize;
+		let frame_start = header.get_u32_le() as usize;
+		let element_start = header.get_u32_le() as usize;
+		let mut sequences = Vec::with_capacity(count);
+		for s in 0..count {
+			let at = sequence_start + s * 0x10;
+			let mut sequence = section.get(at..at + 0x10).ok_or("sequence out of range")?;
+			let frame_count = sequence.get_u16_le() as usize;
+			let loop_start = sequence.get_u16_le();
+			let element_type = sequence.get_u16_le();
+			let kind = sequence.get_u16_le();
+			let playback = Playback::from_id(sequence.get_u32_le());
+			let frames_at = frame_start + sequence.get_u32_le() as usize;
+			let element_len = match element_type {
+				1 => 0x10,
+				2 => 8,
+				_ => 2
+			};
+			let mut frames = Vec::with_capacity(frame_count);
+			for f in 0..frame_count {
+				let mut frame = section.get(frames_at + f * 8..frames_at + f * 8 + 8).ok_or("frame out of range")?;
+				let at = element_start + frame.get_u32_le() as usize;
+				let duration = frame.get_u16_le();
+				let mut element = section.get(at..at + element_len).ok_or("frame element out of range")?;
+				let mut frame = Frame {cell: element.get_u16_le(), duration, x: 0, y: 0, rotation: 0, scale_x: 1.0, scale_y: 1.0};
+				if element_type == 1 {
+					frame.rotation = element.get_u16_le();
+					frame.scale_x = element.get_i32_le() as f32 / 4096.0;
+					frame.scale_y = element.get_i32_le() as f32 / 4096.0;
+				} else if element_type == 2 {
+					element.advance(2);
+				}
+				if element_type == 1 || element_type == 2 {
+					frame.x = element.get_i16_le();
+					frame.y = element.get_i16_le();
+				}
+				frames.push(frame);
+			}
+			sequences.push(Sequence {playback, loop_start, kind, element_type, frames});
+		}
+		Ok(NANR {sequences})
+	}
+
+	// Every frame of the sequence on one canvas big enough for all of them, with how long each stays up.
+	// Ping-pong sequences are played back and forth once. Rotation and scale aren't applied.
+	pub fn render(&self, sequence: &Sequence, ncer: &NCER, ncgr: &NCGR) -> (Vec<IndexedImage>, Vec<u16>) {
+		let mut order: Vec<&Frame> = sequence.frames.iter().collect();
+		if let Playback::PingPong | Playback::PingPongLoop = sequence.playback {
+			order.extend(sequence.frames.iter().rev().skip(1).take(sequence.frames.len().saturating_sub(2)));
+		}
+		let extents: Vec<_> = order.iter().filter_map(|f| {
+			let (l, t, r, b) = ncer.cells.get(f.cell as usize)?.extent()?;
+			Some((l + f.x as i32, t + f.y as i32, r + f.x as i32, b + f.y as i32))
+		}).collect();
+		let (left, top, right, bottom) = extents.into_iter()
+			.reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+			.unwrap_or((0, 0, 1, 1));
+		let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+		let images = order.iter().map(|f| {
+			let mut image = IndexedImage {width, height, pixels: vec![0; width * height]};
+			if let Some(cell) = ncer.cells.get(f.cell as usize) {
+				cell.draw(ncgr, ncer.mapping, &mut image, f.x as i32 - left, f.y as i32 - top);
+			}
+			image
+		}).collect();
+		(images, order.iter().map(|f| f.duration).collect())
+	}
+}
+
+// Writes `<n>.ncer.json` and a `<n>.ncer.<cell>.png` for every cell of each NCER, then `<n>.nanr.json` and an animated
+// `<n>.nanr.<sequence>.png` for every sequence of each NANR. Like the other images, they use the graphics, palette
+// and cells with the same number, or the first ones in the bundle.
+pub fn write_cell_pngs(helper: &IOHelper, path: &RelPath, pk2d: &PK2D, graphics: &[Result<NCGR, BErr>], palettes: &[Result<NCLR, BErr>]) {
+	let pick = |i: usize, len: usize| if i < len {i} else {0};
+	let write = |name: String, content: Result<Vec<u8>, BErr>| {
+		let result = content.and_then(|content| {
+			let mut out_path = path.clone();
+			out_path.push(name.clone());
+			Ok(helper.write_file(&out_path, &content)?)
+		});
+		if let Err(e) = result {
+			println!("Couldn't convert {:?} {}: {}", path, name, e);
+		}
+	};
+	let sources = |i: usize| -> Result<(&NCGR, &NCLR), BErr> {
+		let g = pick(i, graphics.len());
+		match (graphics.get(g), palettes.get(pick(g, palettes.len()))) {
+			(Some(Ok(ncgr)), Some(Ok(nclr))) => Ok((ncgr, nclr)),
+			_ => Err("no usable graphics or palette in the bundle".into())
+		}
+	};
+	let cells: Vec<_> = pk2d.ncer.iter().map(|c| NCER::parse(c)).collect();
+	for (i, ncer) in cells.iter().enumerate() {
+		let ncer = match ncer {
+			Ok(ncer) => ncer,
+			Err(e) => {
+				write(format!("{}.ncer.json", i), Err(e.to_string().into()));
+				continue;
+			}
+		};
+		write(format!("{}.ncer.json", i), serde_json::to_vec_pretty(ncer).map_err(|e| e.into()));
+		for (c, cell) in ncer.cells.iter().enumerate() {
+			let png = sources(i).and_then(|(ncgr, nclr)| encode_indexed(&[&ncer.render(cell, ncgr)], &nclr.colors, true, None));
+			write(format!("{}.ncer.{}.png", i, c), png);
+		}
+	}
+	for (i, nanr) in pk2d.nanr.iter().enumerate() {
+		let nanr = match NANR::parse(nanr) {
+			Ok(nanr) => nanr,
+			Err(e) => {
+				write(format!("{}.nanr.json", i), Err(e));
+				continue;
+			}
+		};
+		write(format!("{}.nanr.json", i), serde_json::to_vec_pretty(&nanr).map_err(|e| e.into()));
+		let c = pick(i, cells.len());
+		for (s, sequence) in nanr.sequences.iter().enumerate() {
+			let png = match (cells.get(c), sources(c)) {
+				(Some(Ok(ncer)), Ok((ncgr, nclr))) => {
+					let (frames, delays) = nanr.render(sequence, ncer, ncgr);
+					let frames: Vec<_> = frames.iter().collect();
+					encode_indexed(&frames, &nclr.colors, true, Some((&delays, sequence.playback.loops())))
+				}
+				(_, Err(e)) => Err(e),
+				_ => Err("no usable cells in the bundle".into())
+			};
+			write(format!("{}.nanr.{}.png", i, s), png);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{nitro::build, graphics::tests::ncgr_file};
+
+	#[test]
+	fn cells_place_their_sprites() {
+		// a flipped 16x8 sprite of tiles 1 and 2 with palette 1, and an 8x8 one of tile 0 to its right
+		let oams: [[u16; 3]; 2] = [[0x40FC, 0x11F8, 0x1001], [0x00FC, 0x0008, 0x0000]];
+		let mut section = Vec::new();
+		for field in [1u32 | 1 << 16, 0x18, 0, 0, 0, 0] {
+			section.extend_from_slice(&field.to_le_bytes());
+		}
+		for field in [2u16, 0, 0, 0, 15, 3, (-8i16) as u16, (-4i16) as u16] {
+			section.extend_from_slice(&field.to_le_bytes());
+		}
+		section.extend(oams.iter().flatten().flat_map(|a| a.to_le_bytes()));
+		let ncer = NCER::parse(&build(NCER_MAGIC, &[(CEBK_MAGIC, &section)])).unwrap();
+
+		let cell = &ncer.cells[0];
+		assert_eq!(cell.bounds, Some([15, 3, -8, -4]));
+		let oam = &cell.oams[0];
+		assert_eq!((oam.x, oam.y, oam.width, oam.height, oam.tile, oam.palette), (-8, -4, 16, 8, 1, 1));
+		assert!(oam.hflip && !oam.vflip && !oam.affine);
+
+		// no zeros, so every pixel shows
+		let pixels: Vec<u8> = (0..3 * 64).map(|p| (p % 15 + 1) as u8).collect();
+		let ncgr = NCGR::parse(&ncgr_file(4, 3, 1, false, &pixels)).unwrap();
+		let image = ncer.render(cell, &ncgr);
+		assert_eq!((image.width, image.height), (24, 8));
+		for y in 0..8 {
+			for x in 0..16 {
+				let px = 15 - x;
+				assert_eq!(image.pixels[y * 24 + x], pixels[64 + px / 8 * 64 + y * 8 + px % 8] + 16);
+			}
+			for x in 16..24 {
+				assert_eq!(image.pixels[y * 24 + x], pixels[y * 8 + x - 16]);
+			}
+		}
+	}
+}
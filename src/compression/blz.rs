@@ -0,0 +1,218 @@
+use crate::meta::CompressionLevel;
+use super::lz::{parse, Token};
+
+// Backward LZ, used for the arm9 binary and overlays so they can be decompressed in place.
+// The data is decoded from the end towards the start, and the file ends with an 8 byte footer:
+// the compressed length (counting the footer) in 24 bits, the footer length, then how much longer the decompressed data is.
+// Whatever comes before the compressed part is stored as is.
+pub fn decompress(in_buf: &[u8]) -> Result<Vec<u8>, String> {
+	let n = in_buf.len();
+	if n < 8 {
+		return Err("too small to have a BLZ footer".into())
+	}
+	let inc_len = u32::from_le_bytes([in_buf[n - 4], in_buf[n - 3], in_buf[n - 2], in_buf[n - 1]]) as usize;
+	let hdr_len = in_buf[n - 5] as usize;
+	let enc_len = u32::from_le_bytes([in_buf[n - 8], in_buf[n - 7], in_buf[n - 6], 0]) as usize;
+	if inc_len == 0 {
+		return Err("not BLZ compressed".into())
+	}
+	if hdr_len < 8 || hdr_len > enc_len || enc_len > n {
+		return Err("BLZ footer is out of range".into())
+	}
+	let dec_len = n - enc_len;
+	let raw_len = n + inc_len;
+	let mut out = vec![0; raw_len];
+	out[..dec_len].copy_from_slice(&in_buf[..dec_len]);
+	let mut src = n - hdr_len;
+	let mut dst = raw_len;
+	let mut next = || {
+		if src <= dec_len {
+			return Err("compressed data ends early".to_string())
+		}
+		src -= 1;
+		Ok(in_buf[src] as usize)
+	};
+	while dst > dec_len {
+		let flags = next()?;
+		for bit in (0..8).rev() {
+			if dst <= dec_len {
+				break;
+			}
+			if flags & (1 << bit) == 0 {
+				dst -= 1;
+				out[dst] = next()? as u8;
+				continue;
+			}
+			let token = next()? << 8 | next()?;
+			let len = (token >> 12) + 3;
+			let disp = (token & 0xFFF) + 3;
+			for _ in 0..len {
+				if dst <= dec_len {
+					break;
+				}
+				dst -= 1;
+				out[dst] = *out.get(dst + disp).ok_or("back-reference past the end of the data")?;
+			}
+		}
+	}
+	Ok(out)
+}
+
+// None if compressing doesn't make the data any smaller. At least `min_raw` bytes at the start are left uncompressed.
+pub fn compress(in_buf: &[u8], level: CompressionLevel, min_raw: usize) -> Option<Vec<u8>> {
+	let n = in_buf.len();
+	let reversed: Vec<u8> = in_buf.iter().rev().copied().collect();
+	// the token stream in the order it's decoded, which gets reversed at the end
+	let mut stream = Vec::new();
+	let mut flag_pos = 0;
+	let mut count = 8;
+	let mut covered = 0;
+	// Decoding in place is only safe if the output never catches up with compressed data still to be read,
+	// which holds if the stream stops where it has saved the most. Everything after that is left uncompressed.
+	let mut best = (0, 0, 0, 0, 8); // (saved, stream length, bytes covered, last flag byte, flag bits used)
+	for token in parse(&reversed, level, 0x12, 3, 0x1002, |_| 2) {
+		if count == 8 {
+			flag_pos = stream.len();
+			stream.push(0);
+			count = 0;
+		}
+		match token {
+			Token::Literal(b) => {
+				stream.push(b);
+				covered += 1;
+			}
+			Token::Match(len, disp) => {
+				stream[flag_pos] |= 0x80 >> count;
+				let token = ((len - 3) << 12) | (disp - 3);
+				stream.push((token >> 8) as u8);
+				stream.push(token as u8);
+				covered += len;
+			}
+		}
+		count += 1;
+		if n - covered < min_raw {
+			break;
+		}
+		if covered > stream.len() && covered - stream.len() > best.0 {
+			best = (covered - stream.len(), stream.len(), covered, flag_pos, count);
+		}
+	}
+	let (_, stream_len, covered, flag_pos, count) = best;
+	stream.truncate(stream_len);
+	if count < 8 {
+		stream[flag_pos] &= !(0xFF >> count); // tokens that didn't make the cut
+	}
+	let mut out = Vec::with_capacity(n);
+	out.extend_from_slice(&in_buf[..n - covered]);
+	out.extend(stream.iter().rev());
+	while out.len() % 4 != 0 {
+		out.push(0xFF);
+	}
+	let hdr_len = out.len() - (n - covered) - stream_len + 8;
+	let enc_len = stream_len + hdr_len;
+	if out.len() + 8 >= n {
+		return None
+	}
+	out.extend_from_slice(&(enc_len as u32 | (hdr_len as u32) << 24).to_le_bytes());
+	out.extend_from_slice(&((n - out.len() - 4) as u32).to_le_bytes());
+	Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Decodes the way the DS does, in one buffer holding the compressed data followed by room for the rest,
+	// so a write over compressed data that hasn't been read yet shows up as wrong output
+	fn decompress_in_place(compressed: &[u8]) -> Vec<u8> {
+		let n = compressed.len();
+		let inc_len = u32::from_le_bytes([compressed[n - 4], compressed[n - 3], compressed[n - 2], compressed[n - 1]]) as usize;
+		let hdr_len = compressed[n - 5] as usize;
+		let enc_len = u32::from_le_bytes([compressed[n - 8], compressed[n - 7], compressed[n - 6], 0]) as usize;
+		let dec_len = n - enc_len;
+		let mut buf = compressed.to_vec();
+		buf.resize(n + inc_len, 0);
+		let (mut src, mut dst) = (n - hdr_len, n + inc_len);
+		while dst > dec_len {
+			src -= 1;
+			let flags = buf[src];
+			for bit in (0..8).rev() {
+				if dst <= dec_len {
+					break;
+				}
+				if flags & (1 << bit) == 0 {
+					src -= 1;
+					dst -= 1;
+					buf[dst] = buf[src];
+				} else {
+					src -= 2;
+					let token = (buf[src + 1] as usize) << 8 | buf[src] as usize;
+					for _ in 0..(token >> 12) + 3 {
+						if dst <= dec_len {
+							break;
+						}
+						dst -= 1;
+						buf[dst] = buf[dst + (token & 0xFFF) + 3];
+					}
+				}
+				assert!(dst >= src, "the output caught up with the compressed data");
+			}
+		}
+		buf
+	}
+
+	fn noise(len: usize, seed: u32) -> Vec<u8> {
+		let mut x = seed | 1;
+		(0..len).map(|_| {
+			x ^= x << 13;
+			x ^= x >> 17;
+			x ^= x << 5;
+			x as u8
+		}).collect()
+	}
+
+	// BLZ works from the end back, so it matters which end the compressible part is at: data near the start is
+	// decoded last, when the output is closest to catching up with the compressed data
+	fn inputs() -> Vec<Vec<u8>> {
+		let mut inputs = vec![Vec::new(), vec![1], vec![1, 2, 3], vec![7; 0x1000]];
+		inputs.push([noise(0x800, 1), vec![0; 0x800]].concat());
+		inputs.push([vec![0; 0x800], noise(0x800, 2)].concat());
+		inputs.push(b"the quick brown fox jumps over the lazy dog. ".repeat(40));
+		// references reach from 3 to 0x1002 bytes back
+		for disp in [0x1001, 0x1002, 0x1003] {
+			let mut buf = noise(disp, disp as u32);
+			buf.extend_from_within(..0x40);
+			inputs.push(buf);
+		}
+		inputs
+	}
+
+	#[test]
+	fn round_trips_and_decodes_in_place() {
+		let mut compressed_any = false;
+		for input in inputs() {
+			for level in [CompressionLevel::Fast, CompressionLevel::Optimal] {
+				for min_raw in [0, 0x40, 0x800] {
+					let compressed = match compress(&input, level, min_raw) {
+						Some(compressed) => compressed,
+						None => continue // didn't get any smaller
+					};
+					compressed_any = true;
+					assert_eq!(&compressed[..min_raw], &input[..min_raw]);
+					assert_eq!(decompress(&compressed).unwrap(), input, "{:?}, {} bytes", level, input.len());
+					assert_eq!(decompress_in_place(&compressed), input, "{:?}, {} bytes", level, input.len());
+				}
+			}
+		}
+		assert!(compressed_any);
+	}
+
+	#[test]
+	fn the_first_min_raw_bytes_are_left_alone() {
+		let input = vec![0; 0x1000];
+		let compressed = compress(&input, CompressionLevel::Optimal, 0x800).unwrap();
+		assert!(compressed.len() > 0x800);
+		assert_eq!(decompress(&compressed).unwrap(), input);
+		assert!(compress(&input, CompressionLevel::Optimal, 0x1000).is_none());
+	}
+}
@@ -0,0 +1,239 @@
+use super::{read_header, write_header};
+use crate::meta::HuffmanType;
+use std::{
+	cmp::Reverse,
+	collections::BinaryHeap
+};
+
+// After the header comes the tree: a byte giving its size in halfwords minus one, the root node, then pairs of nodes.
+// A node's low 6 bits say how many pairs past its own its children are; bits 7 and 6 mark child 0 and 1 as data.
+// The bitstream is a series of little endian words read from the top bit down, 4 bit data fills the low nibble first.
+pub fn decompress(in_buf: &[u8]) -> Result<Vec<u8>, String> {
+	let (ty, len, tree_start) = read_header(in_buf)?;
+	let bits = (ty & 0xF) as usize;
+	let tree_size = *in_buf.get(tree_start).ok_or("compressed data ends early")? as usize;
+	let tree_end = tree_start + (tree_size + 1) * 2;
+	let mut pos = tree_end;
+	if tree_end > in_buf.len() {
+		return Err("compressed data ends early".into())
+	}
+	let root = tree_start + 1;
+	let mut out = Vec::with_capacity(len);
+	let mut node = root;
+	let mut nibble = None;
+	'words: while out.len() < len {
+		let word = in_buf.get(pos..pos + 4).ok_or("compressed data ends early")?;
+		let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+		pos += 4;
+		for bit in (0..32).rev() {
+			let dir = (word >> bit) as usize & 1;
+			let value = in_buf[node];
+			let child = (node & !1) + (value as usize & 0x3F) * 2 + 2 + dir;
+			if child >= tree_end {
+				return Err("Huffman tree points past its end".into())
+			}
+			if value & (0x80 >> dir) == 0 {
+				node = child;
+				continue;
+			}
+			node = root;
+			let data = in_buf[child];
+			if bits == 8 {
+				out.push(data);
+			} else if let Some(low) = nibble.take() {
+				out.push(low | (data & 0xF) << 4);
+			} else {
+				nibble = Some(data & 0xF);
+			}
+			if out.len() >= len {
+				break 'words;
+			}
+		}
+	}
+	Ok(out)
+}
+
+enum Node {
+	Leaf(u8),
+	Internal([usize; 2], usize) // children, how many internal nodes are below
+}
+
+pub fn compress(in_buf: &[u8], ty: HuffmanType) -> Result<Vec<u8>, String> {
+	let symbols: Vec<u8> = match ty {
+		HuffmanType::Huffman8 => in_buf.to_vec(),
+		HuffmanType::Huffman4 => in_buf.iter().flat_map(|b| [b & 0xF, b >> 4]).collect()
+	};
+	let mut counts = [0usize; 256];
+	for s in &symbols {
+		counts[*s as usize] += 1;
+	}
+	let mut nodes = Vec::new();
+	let mut heap = BinaryHeap::new();
+	for (symbol, count) in counts.iter().enumerate() {
+		if *count > 0 {
+			heap.push(Reverse((*count, nodes.len())));
+			nodes.push(Node::Leaf(symbol as u8));
+		}
+	}
+	// the root can't be a leaf, so make sure there are at least two
+	for filler in 0..2u8 {
+		if heap.len() < 2 && counts[filler as usize] == 0 {
+			heap.push(Reverse((0, nodes.len())));
+			nodes.push(Node::Leaf(filler));
+		}
+	}
+	while heap.len() > 1 {
+		let Reverse((c0, n0)) = heap.pop().unwrap();
+		let Reverse((c1, n1)) = heap.pop().unwrap();
+		let below = internal_count(&nodes, n0) + internal_count(&nodes, n1);
+		heap.push(Reverse((c0 + c1, nodes.len())));
+		nodes.push(Node::Internal([n0, n1], below));
+	}
+	let root = nodes.len() - 1;
+	let mut codes = vec![(0u64, 0u32); 256];
+	assign_codes(&nodes, root, 0, 0, &mut codes);
+	let tree = lay_out_tree(&nodes, root)?;
+
+	let bits = match ty {
+		HuffmanType::Huffman4 => 4,
+		HuffmanType::Huffman8 => 8
+	};
+	let mut out = Vec::with_capacity(in_buf.len() + tree.len() + 8);
+	write_header(&mut out, 0x20 | bits, in_buf.len())?;
+	if out.len() > 4 {
+		return Err(format!("{} bytes is too large to compress", in_buf.len()))
+	}
+	out.extend_from_slice(&tree);
+	let mut word = 0u32;
+	let mut used = 0;
+	for s in &symbols {
+		let (code, len) = codes[*s as usize];
+		for bit in (0..len).rev() {
+			word |= ((code >> bit) as u32 & 1) << (31 - used);
+			used += 1;
+			if used == 32 {
+				out.extend_from_slice(&word.to_le_bytes());
+				word = 0;
+				used = 0;
+			}
+		}
+	}
+	if used > 0 {
+		out.extend_from_slice(&word.to_le_bytes());
+	}
+	Ok(out)
+}
+
+fn internal_count(nodes: &[Node], n: usize) -> usize {
+	match nodes[n] {
+		Node::Leaf(_) => 0,
+		Node::Internal(_, below) => below + 1
+	}
+}
+
+fn assign_codes(nodes: &[Node], n: usize, code: u64, len: u32, codes: &mut [(u64, u32)]) {
+	match nodes[n] {
+		Node::Leaf(s) => codes[s as usize] = (code, len),
+		Node::Internal(children, _) => {
+			for (bit, child) in children.iter().enumerate() {
+				assign_codes(nodes, *child, code << 1 | bit as u64, len + 1, codes);
+			}
+		}
+	}
+}
+
+// Children can only be 63 pairs past their parent, so the order pairs are written in matters. Going depth first
+// (smaller subtrees first) keeps few nodes waiting, and a waiting node gets its children written as soon as it runs out of room.
+fn lay_out_tree(nodes: &[Node], root: usize) -> Result<Vec<u8>, String> {
+	let mut table = vec![0u8, 0];
+	// (node, where its byte is in the table, last pair its children can go in)
+	let mut waiting = vec![(root, 1, 63)];
+	while !waiting.is_empty() {
+		let pair = table.len() / 2 - 1;
+		let urgent = (0..waiting.len()).min_by_key(|i| waiting[*i].2).unwrap();
+		let next = if waiting[urgent].2 < pair + waiting.len() {
+			urgent
+		} else {
+			waiting.len() - 1
+		};
+		let (node, slot, last) = waiting.remove(next);
+		if pair > last {
+			return Err("Huffman tree can't be laid out".into())
+		}
+		let children = match nodes[node] {
+			Node::Internal(children, _) => children,
+			Node::Leaf(_) => unreachable!()
+		};
+		table[slot] |= (pair - (slot & !1) / 2) as u8;
+		let mut later = Vec::new();
+		for (i, child) in children.iter().enumerate() {
+			match nodes[*child] {
+				Node::Leaf(s) => {
+					table[slot] |= 0x80 >> i;
+					table.push(s);
+				}
+				Node::Internal(..) => {
+					later.push((*child, table.len(), pair + 64));
+					table.push(0);
+				}
+			}
+		}
+		// the smaller subtree goes on top
+		later.sort_by_key(|(n, _, _)| Reverse(internal_count(nodes, *n)));
+		waiting.extend(later);
+	}
+	// the bitstream has to start on a word boundary
+	if table.len() % 4 != 0 {
+		table.extend_from_slice(&[0, 0]);
+	}
+	table[0] = (table.len() / 2 - 1) as u8;
+	Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// counts growing like the Fibonacci numbers give the deepest tree there can be for that many symbols
+	fn skewed(symbols: u8) -> Vec<u8> {
+		let (mut a, mut b) = (1, 1);
+		let mut buf = Vec::new();
+		for s in 0..symbols {
+			buf.extend(std::iter::repeat_n(s, a));
+			(a, b) = (b, a + b);
+		}
+		buf
+	}
+
+	// a tree needs two leaves, so one symbol is the odd case, and every byte value is the widest
+	fn inputs() -> Vec<Vec<u8>> {
+		vec![
+			Vec::new(),
+			vec![0x5A],
+			vec![0; 100],
+			vec![0xFF; 7],
+			vec![1, 2, 1, 2, 1],
+			(0..=255).collect(),
+			(0..4096).map(|i| (i * 7 % 256) as u8).collect(),
+			skewed(16),
+			skewed(24)
+		]
+	}
+
+	#[test]
+	fn both_sizes_round_trip() {
+		for ty in [HuffmanType::Huffman4, HuffmanType::Huffman8] {
+			for input in inputs() {
+				let compressed = compress(&input, ty).unwrap();
+				assert_eq!(HuffmanType::from_magic(compressed[0]), Some(ty));
+				assert_eq!(decompress(&compressed).unwrap(), input, "{:?}, {} bytes", ty, input.len());
+			}
+		}
+	}
+
+	#[test]
+	fn one_symbol_takes_a_bit_each() {
+		let compressed = compress(&[0x11; 64], HuffmanType::Huffman8).unwrap();
+		assert_eq!(compressed.len(), 4 + 4 + 64 / 8);
+	}
+}
@@ -0,0 +1,463 @@
+use crate::meta::{LZType, CompressionLevel};
+use super::{read_header, write_header};
+
+// LZ10, LZ11 and LZ40 all share the flag byte framing and only differ in how a back-reference is encoded
+pub fn decompress(in_buf: &[u8]) -> Result<Vec<u8>, String> {
+	let (ty, len, mut pos) = read_header(in_buf)?;
+	let mut out = Vec::with_capacity(len);
+	let mut next = || {
+		let b = *in_buf.get(pos).ok_or("compressed data ends early")?;
+		pos += 1;
+		Ok::<usize, String>(b as usize)
+	};
+	while out.len() < len {
+		let flags = next()?;
+		for bit in (0..8).rev() {
+			if out.len() >= len {
+				break;
+			}
+			if flags & (1 << bit) == 0 {
+				out.push(next()? as u8);
+				continue;
+			}
+			let (b0, b1) = (next()?, next()?);
+			let (length, disp) = match ty {
+				0x10 => ((b0 >> 4) + 3, (((b0 & 0xF) << 8) | b1) + 1),
+				0x11 => match b0 >> 4 {
+					0 => {
+						let b2 = next()?;
+						((((b0 & 0xF) << 4) | (b1 >> 4)) + 0x11, (((b1 & 0xF) << 8) | b2) + 1)
+					}
+					1 => {
+						let (b2, b3) = (next()?, next()?);
+						((((b0 & 0xF) << 12) | (b1 << 4) | (b2 >> 4)) + 0x111, (((b2 & 0xF) << 8) | b3) + 1)
+					}
+					l => (l + 1, (((b0 & 0xF) << 8) | b1) + 1)
+				},
+				_ => {
+					// LZ40 stores the distance as is, with the length in the low nibble
+					let disp = (b0 >> 4) | (b1 << 4);
+					match b0 & 0xF {
+						0 => (next()? + 0x10, disp),
+						1 => ((next()? | (next()? << 8)) + 0x110, disp),
+						l => (l, disp)
+					}
+				}
+			};
+			if disp == 0 || disp > out.len() {
+				return Err(format!("back-reference to {} bytes back with only {} written", disp, out.len()))
+			}
+			for _ in 0..length.min(len - out.len()) {
+				out.push(out[out.len() - disp]);
+			}
+		}
+	}
+	Ok(out)
+}
+
+// LZ11, which is what most of the game's compressed files use.
+pub fn safe_compress(in_buf: &[u8], level: CompressionLevel) -> Result<Vec<u8>, String> {
+	let mut writer = BlockWriter::new(0x11, in_buf.len())?;
+	for token in parse(in_buf, level, 0x10110, 1, WINDOW, lz11_token_len) {
+		match token {
+			Token::Literal(b) => writer.literal(b),
+			Token::Match(len, disp) => {
+				let d = disp - 1;
+				if len > 0x110 {
+					let l = len - 0x111;
+					writer.reference(&[0x10 | (l >> 12) as u8, (l >> 4) as u8, ((l << 4) | (d >> 8)) as u8, d as u8]);
+				} else if len > 0x10 {
+					let l = len - 0x11;
+					writer.reference(&[(l >> 4) as u8, ((l << 4) | (d >> 8)) as u8, d as u8]);
+				} else {
+					writer.reference(&[(((len - 1) << 4) | (d >> 8)) as u8, d as u8]);
+				}
+			}
+		}
+	}
+	Ok(writer.out)
+}
+
+pub fn compress_lz10(in_buf: &[u8], level: CompressionLevel) -> Result<Vec<u8>, String> {
+	let mut writer = BlockWriter::new(0x10, in_buf.len())?;
+	// a distance of 1 breaks decompression into VRAM, which works in 16 bit units
+	for token in parse(in_buf, level, 0x12, 2, WINDOW, |_| 2) {
+		match token {
+			Token::Literal(b) => writer.literal(b),
+			Token::Match(len, disp) => {
+				let token = ((len - 3) << 12) | (disp - 1);
+				writer.reference(&[(token >> 8) as u8, token as u8]);
+			}
+		}
+	}
+	Ok(writer.out)
+}
+
+pub fn compress_lz40(in_buf: &[u8], level: CompressionLevel) -> Result<Vec<u8>, String> {
+	let mut writer = BlockWriter::new(0x40, in_buf.len())?;
+	// the distance isn't stored minus one here, so 0x1000 doesn't fit
+	for token in parse(in_buf, level, 0x1010F, 1, WINDOW - 1, lz40_token_len) {
+		match token {
+			Token::Literal(b) => writer.literal(b),
+			Token::Match(len, disp) => {
+				let d = disp << 4;
+				if len >= 0x110 {
+					let l = len - 0x110;
+					writer.reference(&[(d | 1) as u8, (d >> 8) as u8, l as u8, (l >> 8) as u8]);
+				} else if len >= 0x10 {
+					writer.reference(&[d as u8, (d >> 8) as u8, (len - 0x10) as u8]);
+				} else {
+					writer.reference(&[(d | len) as u8, (d >> 8) as u8]);
+				}
+			}
+		}
+	}
+	Ok(writer.out)
+}
+
+pub fn compress_as(in_buf: &[u8], ty: LZType, level: CompressionLevel) -> Result<Vec<u8>, String> {
+	match ty {
+		LZType::LZ10 => compress_lz10(in_buf, level),
+		LZType::LZ11 => safe_compress(in_buf, level),
+		LZType::LZ40 => compress_lz40(in_buf, level)
+	}
+}
+
+fn lz11_token_len(len: usize) -> usize {
+	if len > 0x110 {
+		4
+	} else if len > 0x10 {
+		3
+	} else {
+		2
+	}
+}
+
+fn lz40_token_len(len: usize) -> usize {
+	if len >= 0x110 {
+		4
+	} else if len >= 0x10 {
+		3
+	} else {
+		2
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Token {
+	Literal(u8),
+	Match(usize, usize) // length, distance
+}
+
+// Splits the input into literals and back-references. `token_len` is how many bytes a back-reference of a given length costs.
+pub(super) fn parse(data: &[u8], level: CompressionLevel, max_len: usize, min_disp: usize, window: usize, token_len: impl Fn(usize) -> usize) -> Vec<Token> {
+	match level {
+		CompressionLevel::Fast => parse_greedy(data, max_len, min_disp, window),
+		CompressionLevel::Optimal => parse_optimal(data, max_len, min_disp, window, token_len)
+	}
+}
+
+fn parse_greedy(data: &[u8], max_len: usize, min_disp: usize, window: usize) -> Vec<Token> {
+	let mut tokens = Vec::new();
+	let mut finder = MatchFinder::new(data, window);
+	let mut pos = 0;
+	while pos < data.len() {
+		let (len, disp) = finder.longest(pos, max_len, min_disp);
+		if len >= 3 {
+			tokens.push(Token::Match(len, disp));
+			for p in pos..pos + len {
+				finder.insert(p);
+			}
+			pos += len;
+		} else {
+			tokens.push(Token::Literal(data[pos]));
+			finder.insert(pos);
+			pos += 1;
+		}
+	}
+	tokens
+}
+
+// Shortest path through the input, in bits (every token also costs a flag bit).
+// The distance doesn't change what a back-reference costs, so the longest match at each position
+// stands in for every shorter one. Lengths past the first few are only tried where the token size changes.
+fn parse_optimal(data: &[u8], max_len: usize, min_disp: usize, window: usize, token_len: impl Fn(usize) -> usize) -> Vec<Token> {
+	let n = data.len();
+	let mut finder = MatchFinder::new(data, window);
+	let mut longest = Vec::with_capacity(n);
+	for pos in 0..n {
+		// inside a long run the next position matches one byte less at the same distance, or as much if the
+		// last one stopped at max_len and the run goes on. Rescanning the whole run for every byte of it would be quadratic
+		let found = match longest.last() {
+			Some(&(len, disp)) if len == max_len && pos + len <= n && data[pos + len - 1] == data[pos + len - 1 - disp] => (len, disp),
+			Some(&(len, disp)) if len > 0x112 => (len - 1, disp),
+			_ => finder.longest(pos, max_len, min_disp)
+		};
+		longest.push(found);
+		finder.insert(pos);
+	}
+	let mut cost = vec![0usize; n + 1];
+	let mut choice = vec![0usize; n]; // length taken at each position, 1 for a literal
+	for pos in (0..n).rev() {
+		cost[pos] = cost[pos + 1] + 9;
+		choice[pos] = 1;
+		let (len, _) = longest[pos];
+		if len < 3 {
+			continue;
+		}
+		let mut try_len = |l: usize| {
+			let c = cost[pos + l] + token_len(l) * 8 + 1;
+			if c < cost[pos] {
+				cost[pos] = c;
+				choice[pos] = l;
+			}
+		};
+		for l in 3..=len.min(0x12) {
+			try_len(l);
+		}
+		for l in [0x10F, 0x110, 0x111, len].iter() {
+			if *l > 0x12 && *l <= len {
+				try_len(*l);
+			}
+		}
+	}
+	let mut tokens = Vec::new();
+	let mut pos = 0;
+	while pos < n {
+		let l = choice[pos];
+		if l == 1 {
+			tokens.push(Token::Literal(data[pos]));
+		} else {
+			tokens.push(Token::Match(l, longest[pos].1));
+		}
+		pos += l;
+	}
+	tokens
+}
+
+const WINDOW: usize = 0x1000;
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 256; // how many earlier occurrences to try before settling for the best so far
+
+// Hash chains over 3 byte prefixes. Positions have to be inserted in order.
+struct MatchFinder<'a> {
+	data: &'a [u8],
+	window: usize,
+	head: Vec<u32>,
+	prev: Vec<u32>
+}
+
+impl<'a> MatchFinder<'a> {
+	fn new(data: &'a [u8], window: usize) -> Self {
+		MatchFinder {
+			data, window,
+			head: vec![u32::MAX; 1 << HASH_BITS],
+			prev: vec![u32::MAX; data.len()]
+		}
+	}
+
+	fn hash(&self, pos: usize) -> usize {
+		let d = self.data;
+		let v = (d[pos] as u32) << 16 | (d[pos + 1] as u32) << 8 | d[pos + 2] as u32;
+		(v.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+	}
+
+	fn insert(&mut self, pos: usize) {
+		if pos + 3 > self.data.len() {
+			return
+		}
+		let h = self.hash(pos);
+		self.prev[pos] = self.head[h];
+		self.head[h] = pos as u32;
+	}
+
+	// longest match for `pos` among already inserted positions, as (length, distance)
+	fn longest(&self, pos: usize, max_len: usize, min_disp: usize) -> (usize, usize) {
+		let max_len = max_len.min(self.data.len() - pos);
+		if max_len < 3 {
+			return (0, 0)
+		}
+		let mut best = (0, 0);
+		let mut candidate = self.head[self.hash(pos)];
+		let mut chain = 0;
+		while candidate != u32::MAX && chain < MAX_CHAIN {
+			let c = candidate as usize;
+			let disp = pos - c;
+			if disp > self.window {
+				break;
+			}
+			if disp >= min_disp {
+				let len = self.data[c..].iter().zip(&self.data[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+				if len > best.0 {
+					best = (len, disp);
+					if len == max_len {
+						break;
+					}
+				}
+			}
+			candidate = self.prev[c];
+			chain += 1;
+		}
+		best
+	}
+}
+
+// Token stream shared by the LZ writers: a flag byte followed by up to 8 literals or back-references.
+struct BlockWriter {
+	out: Vec<u8>,
+	flag_pos: usize,
+	count: usize
+}
+
+impl BlockWriter {
+	fn new(ty: u8, len: usize) -> Result<Self, String> {
+		let mut out = Vec::with_capacity(len / 2 + 16);
+		write_header(&mut out, ty, len)?;
+		Ok(BlockWriter {out, flag_pos: 0, count: 8})
+	}
+
+	fn next_token(&mut self, is_ref: bool) {
+		if self.count == 8 {
+			self.flag_pos = self.out.len();
+			self.out.push(0);
+			self.count = 0;
+		}
+		if is_ref {
+			self.out[self.flag_pos] |= 0x80 >> self.count;
+		}
+		self.count += 1;
+	}
+
+	fn literal(&mut self, b: u8) {
+		self.next_token(false);
+		self.out.push(b);
+	}
+
+	fn reference(&mut self, bytes: &[u8]) {
+		self.next_token(true);
+		self.out.extend_from_slice(bytes);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// doesn't compress, so the only matches are the ones a test puts in
+	fn noise(len: usize, seed: u32) -> Vec<u8> {
+		let mut x = seed | 1;
+		(0..len).map(|_| {
+			x ^= x << 13;
+			x ^= x >> 17;
+			x ^= x << 5;
+			x as u8
+		}).collect()
+	}
+
+	// noise and then the same noise again, so the one match there is is exactly `len` long
+	fn repeated(len: usize) -> Vec<u8> {
+		let mut buf = noise(len, len as u32);
+		buf.extend_from_within(..);
+		buf
+	}
+
+	// a repeat from exactly `disp` bytes back
+	fn repeat_from(disp: usize) -> Vec<u8> {
+		let mut buf = noise(disp, disp as u32);
+		buf.extend_from_within(..0x10);
+		buf
+	}
+
+	fn small_inputs() -> Vec<Vec<u8>> {
+		vec![Vec::new(), vec![7], vec![1, 2], b"abababababababababababab".to_vec()]
+	}
+
+	// runs around the longest reference and a repeat at each end of the window
+	fn lz10_inputs() -> Vec<Vec<u8>> {
+		let mut inputs = small_inputs();
+		inputs.extend([0x11, 0x12, 0x13, 0x15, 0x24, 0x25].iter().map(|len| vec![0xAA; *len]));
+		inputs.extend([0x10, 0xFFF, 0x1000, 0x1001].iter().map(|disp| repeat_from(*disp)));
+		inputs
+	}
+
+	// each size of LZ11 reference, either side of where it switches to the next
+	fn lz11_inputs() -> Vec<Vec<u8>> {
+		let mut inputs = small_inputs();
+		inputs.extend([3, 0x10, 0x11, 0x12, 0x110, 0x111, 0x112, 0x800].iter().map(|len| repeated(*len)));
+		inputs.extend([0x10111, 0x10112, 0x20224].iter().map(|len| vec![0x55; *len]));
+		inputs.extend([0xFFF, 0x1000, 0x1001].iter().map(|disp| repeat_from(*disp)));
+		inputs
+	}
+
+	// LZ40 switches sizes at 0x10 and 0x110, and can't reach back a full 0x1000
+	fn lz40_inputs() -> Vec<Vec<u8>> {
+		let mut inputs = small_inputs();
+		inputs.extend([3, 0xF, 0x10, 0x11, 0x10F, 0x110, 0x111, 0x800].iter().map(|len| repeated(*len)));
+		inputs.extend([0x10110, 0x10111, 0x2021E].iter().map(|len| vec![0x55; *len]));
+		inputs.extend([0xFFE, 0xFFF, 0x1000].iter().map(|disp| repeat_from(*disp)));
+		inputs
+	}
+
+	fn round_trip(ty: LZType, inputs: Vec<Vec<u8>>) {
+		for input in inputs {
+			for level in [CompressionLevel::Fast, CompressionLevel::Optimal] {
+				let compressed = compress_as(&input, ty, level).unwrap();
+				assert_eq!(LZType::from_magic(compressed[0]), Some(ty));
+				assert_eq!(decompress(&compressed).unwrap(), input, "{:?} {:?}, {} bytes", ty, level, input.len());
+			}
+		}
+	}
+
+	#[test]
+	fn lz10_round_trips() {
+		round_trip(LZType::LZ10, lz10_inputs());
+	}
+
+	#[test]
+	fn lz11_round_trips() {
+		round_trip(LZType::LZ11, lz11_inputs());
+	}
+
+	#[test]
+	fn lz40_round_trips() {
+		round_trip(LZType::LZ40, lz40_inputs());
+	}
+
+	#[test]
+	fn lz10_finds_repeats_at_the_edge_of_the_window() {
+		let mut buf = noise(0x1000, 3);
+		let alone = compress_lz10(&buf, CompressionLevel::Fast).unwrap().len();
+		buf.extend_from_within(..0x100);
+		assert!(compress_lz10(&buf, CompressionLevel::Fast).unwrap().len() < alone + 0x40);
+	}
+
+	// Each word shows up by its first five bytes and then without its first byte. A greedy parse copies
+	// the five bytes and then the rest, where a literal and then one long copy is shorter
+	fn greedy_traps() -> Vec<u8> {
+		let mut buf = Vec::new();
+		for i in 0..100 {
+			let word = noise(16, i + 1);
+			buf.extend_from_slice(&word[..5]);
+			buf.extend(noise(3, 1000 + i));
+			buf.extend_from_slice(&word[1..]);
+			buf.extend(noise(3, 2000 + i));
+			buf.extend_from_slice(&word);
+		}
+		buf
+	}
+
+	#[test]
+	fn optimal_is_never_longer_than_fast() {
+		let inputs = [lz10_inputs(), lz11_inputs(), lz40_inputs(), vec![greedy_traps()]].concat();
+		for ty in [LZType::LZ10, LZType::LZ11, LZType::LZ40] {
+			let mut smaller = false;
+			for input in &inputs {
+				let fast = compress_as(input, ty, CompressionLevel::Fast).unwrap().len();
+				let optimal = compress_as(input, ty, CompressionLevel::Optimal).unwrap().len();
+				assert!(optimal <= fast, "{:?}, {} bytes: {} optimal, {} fast", ty, input.len(), optimal, fast);
+				smaller |= optimal < fast;
+			}
+			assert!(smaller, "{:?} optimal never did better", ty);
+		}
+	}
+}
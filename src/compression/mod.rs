@@ -0,0 +1,60 @@
+// The BIOS-style codecs. Every one of them starts with a type byte and a 24 bit decompressed size.
+// BLZ is the odd one out, it has a footer instead and is only used for code.
+mod lz;
+mod rle;
+mod huffman;
+pub mod blz;
+pub use lz::compress_as;
+pub use rle::compress as compress_rle;
+pub use huffman::compress as compress_huffman;
+
+pub fn decompress(in_buf: &[u8]) -> Result<Vec<u8>, String> {
+	match in_buf.first() {
+		Some(0x10) | Some(0x11) | Some(0x40) => lz::decompress(in_buf),
+		Some(0x30) => rle::decompress(in_buf),
+		Some(0x24) | Some(0x28) => huffman::decompress(in_buf),
+		Some(ty) => Err(format!("unknown compression type {:#04x}", ty)),
+		None => Err("no compression header".into())
+	}
+}
+
+// What the type byte at the start of compressed data stands for
+pub fn type_name(in_buf: &[u8]) -> &'static str {
+	match in_buf.first() {
+		Some(0x10) => "LZ10",
+		Some(0x11) => "LZ11",
+		Some(0x40) => "LZ40",
+		Some(0x30) => "RLE",
+		Some(0x24) => "Huffman4",
+		Some(0x28) => "Huffman8",
+		_ => "unknown"
+	}
+}
+
+// (type, decompressed size, where the data starts)
+fn read_header(in_buf: &[u8]) -> Result<(u8, usize, usize), String> {
+	if in_buf.len() < 4 {
+		return Err("no compression header".into())
+	}
+	let len = in_buf[1] as usize | (in_buf[2] as usize) << 8 | (in_buf[3] as usize) << 16;
+	if len == 0 && (in_buf[0] == 0x11 || in_buf[0] == 0x40) && in_buf.len() >= 8 {
+		// a zero size means the real one follows as a full word
+		let len = u32::from_le_bytes([in_buf[4], in_buf[5], in_buf[6], in_buf[7]]);
+		return Ok((in_buf[0], len as usize, 8))
+	}
+	Ok((in_buf[0], len, 4))
+}
+
+fn write_header(out: &mut Vec<u8>, ty: u8, len: usize) -> Result<(), String> {
+	if len > 0 && len <= 0xFFFFFF {
+		out.extend_from_slice(&[ty, len as u8, (len >> 8) as u8, (len >> 16) as u8]);
+	} else if (ty == 0x11 || ty == 0x40) && len <= u32::MAX as usize {
+		out.extend_from_slice(&[ty, 0, 0, 0]);
+		out.extend_from_slice(&(len as u32).to_le_bytes());
+	} else if len > 0 {
+		return Err(format!("{} bytes is too large to compress", len))
+	} else {
+		out.extend_from_slice(&[ty, 0, 0, 0]);
+	}
+	Ok(())
+}
@@ -0,0 +1,86 @@
+use super::{read_header, write_header};
+
+// Each block starts with a byte: with the top bit set, the next byte repeats (low 7 bits + 3) times,
+// otherwise (low 7 bits + 1) bytes are copied as they are.
+pub fn decompress(in_buf: &[u8]) -> Result<Vec<u8>, String> {
+	let (_, len, mut pos) = read_header(in_buf)?;
+	let mut out = Vec::with_capacity(len);
+	while out.len() < len {
+		let flag = *in_buf.get(pos).ok_or("compressed data ends early")?;
+		pos += 1;
+		if flag & 0x80 != 0 {
+			let b = *in_buf.get(pos).ok_or("compressed data ends early")?;
+			pos += 1;
+			let count = ((flag & 0x7F) as usize + 3).min(len - out.len());
+			out.resize(out.len() + count, b);
+		} else {
+			let count = ((flag & 0x7F) as usize + 1).min(len - out.len());
+			let run = in_buf.get(pos..pos + count).ok_or("compressed data ends early")?;
+			out.extend_from_slice(run);
+			pos += count;
+		}
+	}
+	Ok(out)
+}
+
+pub fn compress(in_buf: &[u8]) -> Result<Vec<u8>, String> {
+	let mut out = Vec::with_capacity(in_buf.len() + in_buf.len() / 128 + 8);
+	write_header(&mut out, 0x30, in_buf.len())?;
+	let mut literal_start = 0;
+	let mut pos = 0;
+	while pos < in_buf.len() {
+		let run = in_buf[pos..].iter().take(0x82).take_while(|b| **b == in_buf[pos]).count();
+		if run >= 3 {
+			write_literals(&mut out, &in_buf[literal_start..pos]);
+			out.push(0x80 | (run - 3) as u8);
+			out.push(in_buf[pos]);
+			pos += run;
+			literal_start = pos;
+		} else {
+			pos += 1;
+		}
+	}
+	write_literals(&mut out, &in_buf[literal_start..]);
+	Ok(out)
+}
+
+fn write_literals(out: &mut Vec<u8>, literals: &[u8]) {
+	for chunk in literals.chunks(0x80) {
+		out.push((chunk.len() - 1) as u8);
+		out.extend_from_slice(chunk);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// runs are 3 to 0x82 bytes and literal blocks 1 to 0x80, so the inputs sit either side of those
+	fn inputs() -> Vec<Vec<u8>> {
+		let mut inputs = vec![Vec::new(), vec![7], vec![7, 7], vec![7, 7, 7]];
+		for len in [0x81, 0x82, 0x83, 0x84, 0x85, 0x104, 0x105] {
+			inputs.push(vec![0xC3; len]);
+		}
+		for len in [0x7F, 0x80, 0x81, 0x100, 0x101] {
+			inputs.push((0..len).map(|i| i as u8).collect());
+		}
+		// literals broken up by runs too short to be worth one, and runs right after literals
+		inputs.push((0..0x200).map(|i| if i % 5 < 2 {0} else {i as u8}).collect());
+		inputs.push([vec![1, 2, 3], vec![9; 0x90], vec![4, 5], vec![9; 3]].concat());
+		inputs
+	}
+
+	#[test]
+	fn round_trips() {
+		for input in inputs() {
+			let compressed = compress(&input).unwrap();
+			assert_eq!(compressed[0], 0x30);
+			assert_eq!(decompress(&compressed).unwrap(), input, "{} bytes", input.len());
+		}
+	}
+
+	#[test]
+	fn long_runs_take_two_bytes_a_block() {
+		assert_eq!(compress(&[0; 0x82 * 4]).unwrap().len(), 4 + 2 * 4);
+	}
+}
@@ -1,80 +1,93 @@
 use crate::{P2File, P2Subfile, HPAK, PK2D, PKAC, GroupedFiles, BErr, FileType};
+use crate::util::Reader;
 use crate::iohelper::{
-	IOHelper, FileQueueEntry
+	IOHelper, FileQueueEntry, RelPath
 };
-use crate::util::{TryUnwrap};
+use crate::util::{TryUnwrap, content_hash};
 use crate::meta::{
-	FileMeta, MetaRef, P2Meta, NamedP2Meta, LZMeta, LZType, HPAKMeta, PK2DMeta, PKACMeta
+	FileMeta, MetaRef, P2Meta, NamedP2Meta, LZMeta, LZType, RLEMeta, HuffmanMeta, HuffmanType, HPAKMeta, PK2DMeta, PKACMeta,
+	OriginalPayload, P2Layout, GroupLayout, Patches, ORIGINALS_DIR, NDSMeta, CodeMeta, SDATMeta, SDATLayout
 };
+use crate::nds::{
+	NDSRom, ARM9_RAM_ADDRESS, OVT9_OFFSET, OVT9_SIZE, OVT7_OFFSET, OVT7_SIZE, ARM9_FILE, OVERLAY9_DIR, OVERLAY7_DIR,
+	decompress_arm9, read_header_u32
+};
+use crate::pack::{make_patches, write_grouped};
 use bytes::{Bytes, Buf};
 use std::{
-	mem::{replace},
 	convert::{TryFrom, TryInto},
 	str
 };
-use nintendo_lz::decompress;
+use crate::compression::{decompress, blz};
+use crate::graphics::write_pk2d_pngs;
+use crate::textures::write_texture_pngs;
+use crate::anims::write_animation_json;
+use crate::gltf::write_hpak_models;
+use crate::sdat::{SDAT, write_sdat};
+use crate::audio::write_wavs;
+use crate::midi::write_midi;
+use crate::fonts::write_font;
+use crate::text::write_messages;
+use crate::nitro::check;
+use crate::analyze::is_unknown;
 
-pub trait Parse {
-	fn parse(bytes: &[u8]) -> Self;
+pub trait Parse: Sized {
+	fn parse(bytes: &[u8]) -> Result<Self, BErr>;
 }
 
 impl Parse for P2File {
-	fn parse(mut buf: &[u8]) -> Self {
-		let orig_buf = buf;
-		buf.advance(2); // the P2 header
-		let num_files = buf.get_u16_le();
-		let has_name_table = ((num_files >> 8) & 0x80) != 0;
-		let num_files = num_files & !(0x8000);
-		buf.advance(8); // padding
-		let header_size = buf.get_u32_le();
-		let mut partials: Vec<PartialP2File> = vec![Default::default(); num_files as usize];
-		for i in 0..num_files {
-			let offset = buf.get_u16_le() as u32;
-			let offset = offset * 0x200  + header_size;
-			partials[i as usize].offset = Some(offset);
-		}
-		buf.advance((num_files as usize & 1) * 2); // there's padding if odd number of files
-		for i in 0..num_files {
-			let p = buf.get_u32_le();
-			let len = p & 0xFFFFFF;
-			let compressed = ((p >> 24) & 0xFF) == 0x80;
-			partials[i as usize].len = Some(len);
-			partials[i as usize].compressed = Some(compressed);
+	fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, 2); // after the P2 header
+		let num_files = r.u16()?;
+		let has_name_table = num_files & 0x8000 != 0;
+		let num_files = (num_files & 0x7FFF) as usize;
+		r.pos = 12; // skip the padding
+		let header_size = r.u32()?;
+		let offsets = (0..num_files)
+			.map(|_| (r.u16()? as u32 * 0x200).checked_add(header_size).ok_or_else(|| "P2 file offset out of range".into()))
+			.collect::<Result<Vec<u32>, BErr>>()?;
+		r.pos += (num_files & 1) * 2; // there's padding if odd number of files
+		let mut lengths = Vec::with_capacity(num_files);
+		let mut compressed = Vec::with_capacity(num_files);
+		for _ in 0..num_files {
+			let p = r.u32()?;
+			lengths.push(p & 0xFFFFFF);
+			compressed.push((p >> 24) == 0x80);
 		}
+		let mut names = vec![None; num_files];
 		if has_name_table {
-			for i in 0..num_files {
-				let mut string_buf = [0; 8];
-				buf.copy_to_slice(&mut string_buf);
-				let name = str::from_utf8(&string_buf).expect("filename not utf8!?").trim_matches(char::from(0));
-				partials[i as usize].name = Some(name.into());
+			for name in &mut names {
+				let string_buf = buf.get(r.pos..r.pos + 8).ok_or("P2 name table past the end of the file")?;
+				*name = Some(str::from_utf8(string_buf)?.trim_matches(char::from(0)).to_string());
+				r.pos += 8;
 			}
 		}
-		let buf = orig_buf;
-		let subfiles = partials.iter().enumerate().map(|(i, f)| {
-			let offset = f.offset.unwrap() as usize;
-			let len = f.len.unwrap() as usize;
-			let bytes = Bytes::copy_from_slice(&buf[offset..offset + len]);
-			P2Subfile {
+		let layout = P2Layout {
+			header_size,
+			offsets: offsets.clone(),
+			lengths: lengths.clone(),
+			patches: Patches {len: buf.len() as u32, patches: Vec::new()} // filled in once we can compare against a rebuild
+		};
+		let mut subfiles = Vec::with_capacity(num_files);
+		for (i, name) in names.into_iter().enumerate() {
+			let (offset, len) = (offsets[i] as usize, lengths[i] as usize);
+			let content = buf.get(offset..offset + len).ok_or_else(|| format!("P2 subfile {} is past the end of the file", i))?;
+			subfiles.push(P2Subfile {
 				index: i as u16,
-				content: bytes,
-				name: f.name.clone(),
-				compressed: f.compressed.unwrap(),
-			}
-		}).collect();
-		P2File {
-			subfiles, named: has_name_table
+				content: Bytes::copy_from_slice(content),
+				name,
+				compressed: compressed[i]
+			});
 		}
+		let mut p2 = P2File {
+			subfiles, named: has_name_table, layout: Some(layout)
+		};
+		let patches = make_patches(buf, &p2.to_bytes());
+		p2.layout.as_mut().unwrap().patches = patches;
+		Ok(p2)
 	}
 }
 
-#[derive(Default, Clone)]
-struct PartialP2File {
-	offset: Option<u32>,
-	len: Option<u32>,
-	name: Option<String>,
-	compressed: Option<bool>
-}
-
 impl From<[Vec<Bytes>; 8]> for HPAK {
 	fn from(other: [Vec<Bytes>; 8]) -> Self {
 		let [nsbca, nsbva, nsbma, nsbtp, nsbta, unknown5, unknown6, nsbmd] = other;
@@ -112,40 +125,142 @@ impl TryFrom<[Vec<Bytes>; 8]> for PKAC {
 	}
 }
 
-fn read_nametable(orig_buf: &[u8]) -> Result<Vec<String>, BErr> {
-	let mut buf = orig_buf;
+fn read_nametable(buf: &[u8]) -> Result<Vec<String>, BErr> {
+	let mut r = Reader::new(buf, 0);
 	let mut names = Vec::new();
-	let n = buf.get_u16_le();
+	let n = r.u16()?;
 	for _ in 0..n {
-		let offset = buf.get_u16_le();
-		let str_buf = &orig_buf[offset as usize..];
-		let first_nul = str_buf.iter().position(|x| *x == 0).unwrap();
-		names.push(String::from_utf8(orig_buf[offset as usize..offset as usize + first_nul].to_vec())?);
+		let offset = r.u16()? as usize;
+		let str_buf = buf.get(offset..).ok_or("PKAC name past the end of the name table")?;
+		let first_nul = str_buf.iter().position(|x| *x == 0).ok_or("PKAC name isn't terminated")?;
+		names.push(String::from_utf8(str_buf[..first_nul].to_vec())?);
 	}
 	Ok(names)
 }
 
 impl Parse for GroupedFiles {
-	fn parse(orig_buf: &[u8]) -> GroupedFiles {
-		let mut buf = orig_buf;
-		let _magic = buf.get_u32_le();
-		buf.get_u32(); // padding
+	fn parse(buf: &[u8]) -> Result<GroupedFiles, BErr> {
+		let layout = parse_group_layout(buf)?;
 		let mut file_groups = make_file_table();
-		for files in &mut file_groups {
-			let f_info_offset = buf.get_u32_le() as usize;
-			if f_info_offset == 0xFFFFFFFF {
-				continue; // used to indicate empty
+		for (files, (offsets, lengths)) in file_groups.iter_mut().zip(layout.offsets.iter().zip(&layout.lengths)) {
+			for (offset, length) in offsets.iter().zip(lengths) {
+				let (offset, length) = (*offset as usize, *length as usize);
+				files.push(Bytes::copy_from_slice(buf.get(offset..offset + length).ok_or("grouped file past the end of the file")?));
 			}
-			let mut f_info_buf = &orig_buf[f_info_offset..];
-			let n_files = f_info_buf.get_u32_le();
-			let mut len_buf = &f_info_buf[n_files as usize * 4..];
-			for _ in 0..n_files {
-				let offset = f_info_buf.get_u32_le() as usize;
-				let length = len_buf.get_u32_le() as usize;
-				files.push(Bytes::copy_from_slice(&orig_buf[offset..offset + length]))
+		}
+		Ok(file_groups)
+	}
+}
+
+// Where the header of a grouped file puts everything, so it can be put back in the same places.
+fn parse_group_layout(buf: &[u8]) -> Result<GroupLayout, BErr> {
+	let mut r = Reader::new(buf, 8); // after the magic and padding
+	let mut layout = GroupLayout {
+		info_offsets: [0xFFFFFFFF; 8],
+		offsets: vec![Vec::new(); 8],
+		lengths: vec![Vec::new(); 8],
+		patches: Patches::default()
+	};
+	for i in 0..8 {
+		let f_info_offset = r.u32()?;
+		layout.info_offsets[i] = f_info_offset;
+		if f_info_offset == 0xFFFFFFFF {
+			continue; // used to indicate empty
+		}
+		let mut f_info = Reader::new(buf, f_info_offset as usize);
+		let n_files = f_info.u32()? as usize;
+		let mut lengths = Reader::new(buf, f_info.pos + n_files.checked_mul(4).ok_or("too many grouped files")?);
+		for _ in 0..n_files {
+			layout.offsets[i].push(f_info.u32()?);
+			layout.lengths[i].push(lengths.u32()?);
+		}
+	}
+	Ok(layout)
+}
+
+// The layout, plus whatever it takes to turn what we'd write for `groups` back into `orig_buf`.
+fn make_group_layout(orig_buf: &[u8], groups: &GroupedFiles) -> Result<GroupLayout, BErr> {
+	let mut layout = parse_group_layout(orig_buf)?;
+	// lengths are checked against what the packer produces, which for PKAC name tables isn't necessarily what was read
+	for (lengths, group) in layout.lengths.iter_mut().zip(groups.iter()) {
+		if lengths.len() == group.len() {
+			for (l, f) in lengths.iter_mut().zip(group) {
+				*l = f.len() as u32;
 			}
 		}
-		file_groups
+	}
+	let magic = (&orig_buf[..4]).get_u32_le();
+	let rebuilt = write_grouped(magic, groups, Some(&layout));
+	layout.patches = make_patches(orig_buf, &rebuilt);
+	Ok(layout)
+}
+
+// For files that look like an archive but don't parse as one
+fn keep_as_is(helper: &IOHelper, file: &FileQueueEntry, meta_ref: MetaRef<FileMeta>, e: BErr) -> Result<(), BErr> {
+	println!("Keeping {:?} as is, it doesn't parse: {}", file.path, e);
+	meta_ref.submit(FileMeta::OtherFile(file.path.peek()));
+	Ok(helper.write_file(&file.path, &file.content)?)
+}
+
+// Stashes a compressed payload so the packer can write it back untouched if `content` doesn't change.
+fn store_original(helper: &IOHelper, payload: &[u8], content: &[u8]) -> Result<OriginalPayload, BErr> {
+	let name = format!("{:016x}.bin", content_hash(payload));
+	let mut path = RelPath::new();
+	path.push(ORIGINALS_DIR.into());
+	path.push(name.clone());
+	helper.write_file(&path, payload)?;
+	Ok(OriginalPayload {
+		content_hash: content_hash(content),
+		payload: name
+	})
+}
+
+// arm9 and the overlays, decompressed if they were. Returns the rom's metadata, minus the filesystem.
+pub fn extract_rom_code(helper: &IOHelper, rom: &NDSRom) -> Result<NDSMeta, BErr> {
+	let arm9 = rom.get_arm9()?;
+	let ram_address = read_header_u32(rom.get_header(), ARM9_RAM_ADDRESS);
+	let decompressed = decompress_arm9(&arm9, ram_address).unwrap_or_else(|e| {
+		println!("Keeping arm9 compressed, it doesn't decompress: {}", e);
+		None
+	});
+	let mut path = RelPath::new();
+	path.push(ARM9_FILE.into());
+	let original = write_code(helper, &path, &arm9, decompressed)?;
+	let arm9_meta = CodeMeta::new(ARM9_FILE.into(), original);
+	let overlays9 = extract_overlays(helper, rom, OVT9_OFFSET, OVT9_SIZE, OVERLAY9_DIR)?;
+	let overlays7 = extract_overlays(helper, rom, OVT7_OFFSET, OVT7_SIZE, OVERLAY7_DIR)?;
+	Ok(NDSMeta::new(Some(arm9_meta), overlays9, overlays7))
+}
+
+fn extract_overlays(helper: &IOHelper, rom: &NDSRom, offset_field: usize, size_field: usize, dir: &str) -> Result<Vec<CodeMeta>, BErr> {
+	let mut metas = Vec::new();
+	for (i, overlay) in rom.get_overlays(offset_field, size_field)?.iter().enumerate() {
+		let name = format!("{}_{:04}.bin", dir, i);
+		let mut path = RelPath::new();
+		path.push(dir.into());
+		path.push(name.clone());
+		let content = rom.get_file(overlay.file_id)?;
+		let decompressed = if overlay.compressed {
+			blz::decompress(&content).map_err(|e| println!("Keeping {} compressed, it doesn't decompress: {}", name, e)).ok()
+		} else {
+			None
+		};
+		let original = write_code(helper, &path, &content, decompressed)?;
+		metas.push(CodeMeta::new(name, original));
+	}
+	Ok(metas)
+}
+
+fn write_code(helper: &IOHelper, path: &RelPath, content: &[u8], decompressed: Option<Vec<u8>>) -> Result<Option<OriginalPayload>, BErr> {
+	match decompressed {
+		Some(decompressed) => {
+			helper.write_file(path, &decompressed)?;
+			Ok(Some(store_original(helper, content, &decompressed)?))
+		}
+		None => {
+			helper.write_file(path, content)?;
+			Ok(None)
+		}
 	}
 }
 
@@ -153,42 +268,113 @@ fn make_file_table() -> [Vec<Bytes>; 8] { // lmao
 	[Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()]
 }
 
+// Files that aren't archives are written as they are, checked, and converted to whatever the options ask for
+fn write_unpacked(helper: &IOHelper, file: &FileQueueEntry, ty: FileType, meta_ref: MetaRef<FileMeta>) -> Result<(), BErr> {
+	meta_ref.submit(FileMeta::OtherFile(file.path.peek()));
+	helper.write_file(&file.path, &file.content)?;
+	let problems = check(&file.content, ty);
+	if !problems.is_empty() {
+		println!("{:?} looks corrupt: {}", file.path, problems.join("; "));
+	}
+	let options = helper.get_options();
+	match ty {
+		FileType::NSBTX | FileType::NSBMD if options.png => write_texture_pngs(helper, &file.path, &file.content),
+		FileType::NSBTP | FileType::NSBTA | FileType::NSBMA | FileType::NSBVA if options.json => write_animation_json(helper, &file.path, &file.content),
+		FileType::SWAR | FileType::STRM if options.wav => write_wavs(helper, &file.path, &file.content),
+		FileType::SSEQ | FileType::SSAR if options.midi => write_midi(helper, &file.path, &file.content),
+		FileType::NFTR if options.png => write_font(helper, &file.path, &file.content),
+		_ => ()
+	}
+	// message files don't have a magic, so anything that isn't recognised is a candidate
+	if let Some(table) = &options.text {
+		if ty == FileType::OtherOrNotGuessable || is_unknown(ty) {
+			write_messages(helper, &file.path, &file.content, table);
+		}
+	}
+	Ok(())
+}
+
 pub fn handle_file(mut file: FileQueueEntry, meta_ref: MetaRef<FileMeta>, helper: &IOHelper) -> Result<(), BErr> {
 	let ty = file.get_or_guess_type();
+	if !ty.still_packed() {
+		return write_unpacked(helper, &file, ty, meta_ref)
+	}
 	if file.content.is_empty() {
 		println!("Ignoring empty file {:?}", file.path)
 	}
-	//println!("Guessed type {:?} for {:?}", ty, file.path);
 	match ty {
 		FileType::P2 => {
-			//println!("extract p2 {:?}", file.path);
+			let p2_container = match P2File::parse(&file.content) {
+				Ok(p2) => p2,
+				Err(e) => return keep_as_is(helper, &file, meta_ref, e)
+			};
 			helper.create_dir(&file.path)?;
-			let p2_container = P2File::parse(&file.content);
 			let name = file.path.peek();
+			// decompress up front, the metadata needs to know what the compressed payloads were
+			let mut originals = Vec::with_capacity(p2_container.subfiles.len());
+			let mut contents = Vec::with_capacity(p2_container.subfiles.len());
+			let mut kept = Vec::with_capacity(p2_container.subfiles.len());
+			for p2f in &p2_container.subfiles {
+				// a payload that doesn't decompress is kept as it is, and written back untouched as long as it stays that way
+				let content = match p2f.get_decompressed() {
+					Ok(content) => {
+						kept.push(false);
+						content
+					},
+					Err(e) => {
+						println!("Keeping P2 subfile {} of {:?} as is, it doesn't decompress: {}", p2f.index, file.path, e);
+						kept.push(true);
+						p2f.content.clone()
+					}
+				};
+				if p2f.compressed && !p2f.content.is_empty() {
+					originals.push(Some(store_original(helper, &p2f.content, &content)?));
+				} else {
+					originals.push(None);
+				}
+				contents.push(content);
+			}
 			let mut meta_refs = optioned_vec_of(if p2_container.named {
-				let meta = meta_ref.submit(NamedP2Meta::from(&p2_container, name));
+				let mut p2_meta = NamedP2Meta::from(&p2_container, name);
+				for (i, original) in originals.into_iter().enumerate() {
+					if let Some(original) = original {
+						p2_meta.set_original(i, original);
+					}
+				}
+				let meta = meta_ref.submit(p2_meta);
 				let mut vec = Vec::with_capacity(meta.len());
 				for (_, r) in meta {
 					vec.push(r);
 				}
 				vec
 			} else {
-				meta_ref.submit(P2Meta::from(&p2_container, name))
+				let mut p2_meta = P2Meta::from(&p2_container, name);
+				for (i, original) in originals.into_iter().enumerate() {
+					if let Some(original) = original {
+						p2_meta.set_original(i, original);
+					}
+				}
+				meta_ref.submit(p2_meta)
 			});
-			//let meta_refs = meta.submit(mut u: U)
-			for mut p2f in p2_container.subfiles {
-				let meta_ref = replace(&mut meta_refs[p2f.index as usize], None).unwrap();
+			for ((mut p2f, content), kept) in p2_container.subfiles.into_iter().zip(contents).zip(kept) {
+				p2f.content = content;
+				p2f.compressed = false;
+				let meta_ref = meta_refs[p2f.index as usize].take().unwrap();
 				if p2f.content.is_empty() {
 					println!("Ignoring empty P2 subfile at index {} in {:?}", p2f.index, file.path);
 					meta_ref.submit(FileMeta::EmptyFile);
 					continue;
 				}
-				p2f.decompress()?;
 				let t_guess = FileType::guess_from(&p2f.content, false);
 				let name = format!("{}.{}", p2f.suggest_name(), t_guess.get_extension());
 				let mut p = file.path.clone();
 				p.push(name);
-				helper.queue_or_write(FileQueueEntry {
+				if kept {
+					meta_ref.submit(FileMeta::OtherFile(p.peek()));
+					helper.write_file(&p, &p2f.content)?;
+					continue;
+				}
+				helper.queue_file(FileQueueEntry {
 					path: p,
 					content: p2f.content,
 					type_hint: Some(t_guess),
@@ -196,53 +382,109 @@ pub fn handle_file(mut file: FileQueueEntry, meta_ref: MetaRef<FileMeta>, helper
 				}, meta_ref)?
 			}
 		},
-		FileType::LZ => {
-			//println!("decompress {:?}", file.path);
-			let lz_type = if file.content[0] == 0x10 {LZType::LZ10} else {LZType::LZ11};
-			let meta_ref = meta_ref.submit(LZMeta::new(lz_type));
-			file.content = Bytes::from(decompress(&mut file.content.reader()).unwrap());
+		FileType::LZ | FileType::RLE | FileType::Huffman => {
+			let decompressed = match decompress(&file.content) {
+				Ok(decompressed) => Bytes::from(decompressed),
+				Err(e) => {
+					// the first byte just happened to look like a compression type
+					println!("Keeping {:?} as is, it doesn't decompress: {}", file.path, e);
+					meta_ref.submit(FileMeta::OtherFile(file.path.peek()));
+					return Ok(helper.write_file(&file.path, &file.content)?)
+				}
+			};
+			let original = store_original(helper, &file.content, &decompressed)?;
+			let magic = file.content[0];
+			let meta_ref = match ty {
+				FileType::LZ => meta_ref.submit(LZMeta::new(LZType::from_magic(magic).unwrap(), original)),
+				FileType::RLE => meta_ref.submit(RLEMeta::new(original)),
+				_ => meta_ref.submit(HuffmanMeta::new(HuffmanType::from_magic(magic).unwrap(), original))
+			};
+			file.content = decompressed;
 			file.type_hint = None;
 			file.compression_hint = Some(false);
-			helper.queue_or_write(file, meta_ref)?
+			helper.queue_file(file, meta_ref)?
 		},
 		FileType::HPAK | FileType::PK2D => {
-			//println!("extract asset store {:?}", file.path);
+			let files = match GroupedFiles::parse(&file.content) {
+				Ok(files) => files,
+				Err(e) => return keep_as_is(helper, &file, meta_ref, e)
+			};
 			helper.create_dir(&file.path)?;
-			let files = GroupedFiles::parse(&file.content);
+			let layout = make_group_layout(&file.content, &files)?;
 			let parsed = AssetBundle::from_filegroups(files, ty)?;
 			let map = parsed.get_type_map().try_unwrap().map_err(|x| x.strip_data())?;
-			let mut meta_refs = arrays_suck(parsed.submit_meta_to(meta_ref, file.path.peek()));
+			// the pngs go first, the metadata records what they were written as
+			let ncgr_pngs = match &parsed {
+				AssetBundle::PK2D(pk2d) if helper.get_options().png => write_pk2d_pngs(helper, &file.path, pk2d),
+				_ => Vec::new()
+			};
+			let mut meta_refs = arrays_suck(parsed.submit_meta_to(meta_ref, file.path.peek(), layout, ncgr_pngs));
 			for (i1, (typ, group)) in map.iter().enumerate() {
 				for (i2, data) in group.iter().enumerate() {
 					let name = format!("{}.{}", i2, typ.get_extension());
 					let mut new_path = file.path.clone();
 					new_path.push(name);
-					helper.queue_or_write(FileQueueEntry {
+					helper.queue_file(FileQueueEntry {
 						path: new_path,
 						content: data.clone(),
 						type_hint: Some(*typ),
 						compression_hint: None
-					}, replace(&mut meta_refs[i1][i2], None).unwrap())?
+					}, meta_refs[i1][i2].take().unwrap())?
+				}
+			}
+			if let AssetBundle::HPAK(hpak) = &parsed {
+				if helper.get_options().gltf {
+					write_hpak_models(helper, &file.path, hpak);
 				}
 			}
 		},
 		FileType::PKAC => {
+			let pkac: PKAC = match GroupedFiles::parse(&file.content).and_then(|parsed| parsed.try_into()) {
+				Ok(pkac) => pkac,
+				Err(e) => return keep_as_is(helper, &file, meta_ref, e)
+			};
 			helper.create_dir(&file.path)?;
-			let parsed = GroupedFiles::parse(&file.content);
-			let pkac: PKAC = parsed.try_into()?;
-			let meta = PKACMeta::from(&pkac, file.path.peek());
+			let mut meta = PKACMeta::from(&pkac, file.path.peek());
+			meta.set_layout(make_group_layout(&file.content, &pkac.clone().into())?);
 			let mut meta_refs = optioned_vec_of(meta_ref.submit(meta));
 			for (i, (name, subfile)) in pkac.files.iter().enumerate() {
-				let ty = FileType::guess_from(&subfile, true); // unsure if can be compressed or not
+				let ty = FileType::guess_from(subfile, true); // unsure if can be compressed or not
 				let name = format!("{}.{}", name, ty.get_extension());
 				let mut new_path = file.path.clone();
 				new_path.push(name);
-				helper.queue_or_write(FileQueueEntry {
+				helper.queue_file(FileQueueEntry {
 					path: new_path,
 					content: subfile.clone(),
 					type_hint: Some(ty),
 					compression_hint: None
-				}, replace(&mut meta_refs[i], None).unwrap().1)?;
+				}, meta_refs[i].take().unwrap().1)?;
+			}
+		}
+		FileType::SDAT => {
+			let sdat = match SDAT::parse(&file.content) {
+				Ok(sdat) => sdat,
+				Err(e) => return keep_as_is(helper, &file, meta_ref, e)
+			};
+			helper.create_dir(&file.path)?;
+			let contents: Vec<Bytes> = sdat.files.iter().map(|(_, f)| f.clone()).collect();
+			let mut layout = SDATLayout {
+				offsets: sdat.offsets.clone(),
+				lengths: contents.iter().map(|f| f.len() as u32).collect(),
+				patches: Patches::default()
+			};
+			layout.patches = make_patches(&file.content, &write_sdat(&sdat.headers, &contents, Some(&layout))?);
+			let headers = store_original(helper, &sdat.headers, &sdat.headers)?;
+			let mut meta_refs = optioned_vec_of(meta_ref.submit(SDATMeta::from(&sdat, file.path.peek(), headers, layout)));
+			for (i, (name, content)) in sdat.files.into_iter().enumerate() {
+				let ty = FileType::guess_from(&content, false);
+				let mut new_path = file.path.clone();
+				new_path.push(format!("{}.{}", name, ty.get_extension()));
+				helper.queue_file(FileQueueEntry {
+					path: new_path,
+					content,
+					type_hint: Some(ty),
+					compression_hint: Some(false)
+				}, meta_refs[i].take().unwrap().1)?;
 			}
 		}
 		_ => ()
@@ -272,10 +514,19 @@ impl AssetBundle {
 		}
 	}
 	
-	pub fn submit_meta_to(&self, meta_ref: MetaRef<FileMeta>, unpacked_name: String) -> [Vec<MetaRef<FileMeta>>; 8] {
+	pub fn submit_meta_to(&self, meta_ref: MetaRef<FileMeta>, unpacked_name: String, layout: GroupLayout, ncgr_pngs: Vec<Option<u64>>) -> [Vec<MetaRef<FileMeta>>; 8] {
 		match self {
-			Self::HPAK(h) => meta_ref.submit(HPAKMeta::from(h, unpacked_name)),
-			Self::PK2D(p) => meta_ref.submit(PK2DMeta::from(p, unpacked_name))
+			Self::HPAK(h) => {
+				let mut meta = HPAKMeta::from(h, unpacked_name);
+				meta.set_layout(layout);
+				meta_ref.submit(meta)
+			},
+			Self::PK2D(p) => {
+				let mut meta = PK2DMeta::from(p, unpacked_name);
+				meta.set_layout(layout);
+				meta.set_ncgr_pngs(ncgr_pngs);
+				meta_ref.submit(meta)
+			}
 		}
 	}
 }
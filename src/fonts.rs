@@ -0,0 +1,449 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	graphics::{Color, IndexedImage, encode_png, decode_png},
+	util::Reader
+};
+use bytes::{Bytes, BufMut};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+
+// NFTR fonts, as a sheet of all the glyphs (`<file>.png`) and a JSON of what isn't pixels (`<file>.json`):
+// the metrics from FINF and CGLP, and for each glyph its width from CWDH and the character codes CMAP gives it.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct FontMap {
+	version: u16,
+	font_type: u8,
+	line_feed: u8,
+	alt_glyph: u16, // shown for characters the font doesn't have
+	default_width: Width,
+	encoding: u8, // 0 UTF-8, 1 UTF-16, 2 Shift-JIS, 3 CP1252
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	metrics: Option<[u8; 4]>, // newer fonts only: height, width, bearing y and bearing x
+	cell: CellInfo,
+	glyphs: Vec<Glyph>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct Width {
+	left: i8,
+	width: u8,
+	advance: u8
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct CellInfo {
+	width: u8,
+	height: u8,
+	baseline: u8,
+	max_width: u8,
+	bpp: u8,
+	flags: u8
+}
+
+impl CellInfo {
+	fn size(&self) -> usize {
+		(self.width as usize * self.height as usize * self.bpp as usize).div_ceil(8)
+	}
+
+	fn max_level(&self) -> u8 {
+		((1u16 << self.bpp.clamp(1, 8)) - 1) as u8
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Glyph {
+	codes: Vec<u16>,
+	#[serde(flatten)]
+	width: Width
+}
+
+const GLYPHS_PER_ROW: usize = 16;
+const FINF_SIZE: usize = 0x1C;
+const CGLP_HEADER: usize = 0x10;
+const CWDH_HEADER: usize = 0x10;
+const CMAP_HEADER: usize = 0x14;
+
+// The ways a CMAP block can map its codes to glyphs
+const CMAP_DIRECT: u16 = 0; // consecutive codes to consecutive glyphs
+const CMAP_TABLE: u16 = 1; // a glyph for each code in the range
+const CMAP_SCAN: u16 = 2; // a list of (code, glyph)
+
+// Runs of codes shorter than this go in the scan block when rebuilding, it's smaller than a block of their own
+const MIN_DIRECT_RUN: usize = 4;
+
+// The glyphs are kept as one level per pixel, from 0 (nothing) to `max_level` (full ink)
+pub fn parse_nftr(buf: &[u8]) -> Result<(FontMap, Vec<Vec<u8>>), BErr> {
+	let mut r = Reader::new(buf, 0);
+	if r.u32()? != NFTR_MAGIC {
+		return Err("not an NFTR".into())
+	}
+	r.pos += 2;
+	let version = r.u16()?;
+	r.pos += 4;
+	let finf = r.u16()? as usize;
+	let mut r = Reader::new(buf, finf);
+	if r.u32()? != FINF_MAGIC {
+		return Err("NFTR has no FINF section".into())
+	}
+	let finf_size = r.u32()? as usize;
+	let font_type = r.u8()?;
+	let line_feed = r.u8()?;
+	let alt_glyph = r.u16()?;
+	let default_width = Width {left: r.u8()? as i8, width: r.u8()?, advance: r.u8()?};
+	let encoding = r.u8()?;
+	let (cglp, cwdh, cmap) = (r.u32()? as usize, r.u32()? as usize, r.u32()? as usize);
+	let metrics = if finf_size >= FINF_SIZE + 4 {Some([r.u8()?, r.u8()?, r.u8()?, r.u8()?])} else {None};
+
+	// the section offsets point past the section headers
+	if cglp < 8 {
+		return Err("NFTR has no CGLP section".into())
+	}
+	let mut r = Reader::new(buf, cglp - 8);
+	if r.u32()? != CGLP_MAGIC {
+		return Err("NFTR has no CGLP section".into())
+	}
+	let cglp_size = r.u32()? as usize;
+	let (width, height) = (r.u8()?, r.u8()?);
+	let cell_size = r.u16()? as usize;
+	let cell = CellInfo {width, height, baseline: r.u8()?, max_width: r.u8()?, bpp: r.u8()?, flags: r.u8()?};
+	if cell_size == 0 || cell.bpp == 0 || cell.bpp > 8 || cell.size() > cell_size {
+		return Err(format!("NFTR glyphs are {} bytes, too small for {}x{} at {}bpp", cell_size, width, height, cell.bpp).into())
+	}
+	let data = buf.get(cglp + 8..cglp - 8 + cglp_size).ok_or("NFTR glyphs are past the end of the file")?;
+	let bitmaps: Vec<Vec<u8>> = data.chunks_exact(cell_size).map(|g| read_glyph(g, &cell)).collect();
+
+	let mut widths = vec![default_width; bitmaps.len()];
+	let mut at = cwdh;
+	let mut seen = HashSet::new(); // a block pointing back at an earlier one would loop forever
+	while at != 0 && seen.insert(at) {
+		let mut r = Reader::new(buf, at);
+		let (first, last) = (r.u16()? as usize, r.u16()? as usize);
+		let next = r.u32()? as usize;
+		for i in first..=last {
+			let width = Width {left: r.u8()? as i8, width: r.u8()?, advance: r.u8()?};
+			if let Some(w) = widths.get_mut(i) {
+				*w = width;
+			}
+		}
+		at = next;
+	}
+
+	let mut codes = vec![Vec::new(); bitmaps.len()];
+	let mut map = |code: u16, glyph: u16| {
+		if let Some(c) = codes.get_mut(glyph as usize) {
+			c.push(code);
+		}
+	};
+	let mut at = cmap;
+	let mut seen = HashSet::new();
+	while at != 0 && seen.insert(at) {
+		let mut r = Reader::new(buf, at);
+		let (first, last, ty) = (r.u16()?, r.u16()?, r.u16()?);
+		r.pos += 2;
+		let next = r.u32()? as usize;
+		match ty {
+			CMAP_DIRECT => {
+				let glyph = r.u16()?;
+				for code in first..=last {
+					map(code, glyph.wrapping_add(code - first));
+				}
+			}
+			CMAP_TABLE => {
+				for code in first..=last {
+					let glyph = r.u16()?;
+					if glyph != 0xFFFF {
+						map(code, glyph);
+					}
+				}
+			}
+			CMAP_SCAN => {
+				for _ in 0..r.u16()? {
+					let (code, glyph) = (r.u16()?, r.u16()?);
+					map(code, glyph);
+				}
+			}
+			_ => return Err(format!("unknown CMAP type {}", ty).into())
+		}
+		at = next;
+	}
+	for c in codes.iter_mut() {
+		c.sort_unstable();
+	}
+
+	let glyphs = codes.into_iter().zip(widths).map(|(codes, width)| Glyph {codes, width}).collect();
+	Ok((FontMap {version, font_type, line_feed, alt_glyph, default_width, encoding, metrics, cell, glyphs}, bitmaps))
+}
+
+fn read_glyph(data: &[u8], cell: &CellInfo) -> Vec<u8> {
+	let bpp = cell.bpp as usize;
+	let mask = cell.max_level();
+	(0..cell.width as usize * cell.height as usize).map(|i| {
+		let bit = i * bpp;
+		// bits go from the top of each byte down, and levels can straddle two bytes
+		let pair = (data[bit / 8] as u16) << 8 | data.get(bit / 8 + 1).copied().unwrap_or(0) as u16;
+		((pair >> (16 - bpp - bit % 8)) as u8) & mask
+	}).collect()
+}
+
+fn write_glyph(levels: &[u8], cell: &CellInfo) -> Vec<u8> {
+	let bpp = cell.bpp as usize;
+	let mut data = vec![0u8; cell.size()];
+	for (i, level) in levels.iter().enumerate() {
+		let bit = i * bpp;
+		let shifted = (*level as u16 & cell.max_level() as u16) << (16 - bpp - bit % 8);
+		data[bit / 8] |= (shifted >> 8) as u8;
+		if let Some(b) = data.get_mut(bit / 8 + 1) {
+			*b |= shifted as u8;
+		}
+	}
+	data
+}
+
+// Builds a whole new NFTR: one width block for every glyph, and the character map as direct blocks for runs of
+// consecutive codes and glyphs, with everything else in one scan block
+pub fn write_nftr(map: &FontMap, bitmaps: &[Vec<u8>]) -> Result<Vec<u8>, BErr> {
+	let cell = &map.cell;
+	if cell.bpp == 0 || cell.bpp > 8 {
+		return Err(format!("fonts can't have {} bits per pixel", cell.bpp).into())
+	}
+	let count = map.glyphs.len();
+	let finf_size = FINF_SIZE + if map.metrics.is_some() {4} else {0};
+	let cglp = 0x10 + finf_size;
+	let cglp_size = align4(CGLP_HEADER + count * cell.size());
+	let cwdh = cglp + cglp_size;
+	let cwdh_size = align4(CWDH_HEADER + count * 3);
+	let cmap = cwdh + cwdh_size;
+	let blocks = cmap_blocks(&map.glyphs);
+
+	let mut buf = Vec::new();
+	buf.put_u32_le(NFTR_MAGIC);
+	buf.put_u16_le(0xFEFF);
+	buf.put_u16_le(map.version);
+	buf.put_u32_le(0); // size, filled in at the end
+	buf.put_u16_le(0x10);
+	buf.put_u16_le(3 + blocks.len() as u16);
+
+	buf.put_u32_le(FINF_MAGIC);
+	buf.put_u32_le(finf_size as u32);
+	buf.put_u8(map.font_type);
+	buf.put_u8(map.line_feed);
+	buf.put_u16_le(map.alt_glyph);
+	buf.put_u8(map.default_width.left as u8);
+	buf.put_u8(map.default_width.width);
+	buf.put_u8(map.default_width.advance);
+	buf.put_u8(map.encoding);
+	buf.put_u32_le(cglp as u32 + 8);
+	buf.put_u32_le(cwdh as u32 + 8);
+	buf.put_u32_le(if blocks.is_empty() {0} else {cmap as u32 + 8});
+	if let Some(metrics) = map.metrics {
+		buf.extend_from_slice(&metrics);
+	}
+
+	buf.put_u32_le(CGLP_MAGIC);
+	buf.put_u32_le(cglp_size as u32);
+	buf.put_u8(cell.width);
+	buf.put_u8(cell.height);
+	buf.put_u16_le(cell.size() as u16);
+	buf.put_u8(cell.baseline);
+	buf.put_u8(cell.max_width);
+	buf.put_u8(cell.bpp);
+	buf.put_u8(cell.flags);
+	for bitmap in bitmaps {
+		buf.extend_from_slice(&write_glyph(bitmap, cell));
+	}
+	buf.resize(cwdh, 0);
+
+	buf.put_u32_le(CWDH_MAGIC);
+	buf.put_u32_le(cwdh_size as u32);
+	buf.put_u16_le(0);
+	buf.put_u16_le(count.saturating_sub(1) as u16);
+	buf.put_u32_le(0);
+	for glyph in &map.glyphs {
+		buf.extend_from_slice(&[glyph.width.left as u8, glyph.width.width, glyph.width.advance]);
+	}
+	buf.resize(cmap, 0);
+
+	let block_count = blocks.len();
+	for (i, (ty, first, last, data)) in blocks.into_iter().enumerate() {
+		let size = align4(CMAP_HEADER + data.len());
+		let next = if i + 1 < block_count {(buf.len() + size + 8) as u32} else {0};
+		buf.put_u32_le(CMAP_MAGIC);
+		buf.put_u32_le(size as u32);
+		buf.put_u16_le(first);
+		buf.put_u16_le(last);
+		buf.put_u16_le(ty);
+		buf.put_u16_le(0);
+		buf.put_u32_le(next);
+		buf.extend_from_slice(&data);
+		buf.resize(align4(buf.len()), 0);
+	}
+	let size = buf.len() as u32;
+	buf[8..12].copy_from_slice(&size.to_le_bytes());
+	Ok(buf)
+}
+
+// (type, first code, last code, data)
+fn cmap_blocks(glyphs: &[Glyph]) -> Vec<(u16, u16, u16, Vec<u8>)> {
+	let mut pairs: Vec<(u16, u16)> = glyphs.iter().enumerate()
+		.flat_map(|(i, g)| g.codes.iter().map(move |c| (*c, i as u16)))
+		.collect();
+	pairs.sort_unstable();
+	pairs.dedup_by_key(|(code, _)| *code);
+	let mut blocks = Vec::new();
+	let mut scan = Vec::new();
+	let mut i = 0;
+	while i < pairs.len() {
+		let mut end = i + 1;
+		while end < pairs.len() && pairs[end].0 == pairs[end - 1].0 + 1 && pairs[end].1 == pairs[end - 1].1 + 1 {
+			end += 1;
+		}
+		if end - i >= MIN_DIRECT_RUN {
+			let mut data = Vec::new();
+			data.put_u16_le(pairs[i].1);
+			blocks.push((CMAP_DIRECT, pairs[i].0, pairs[end - 1].0, data));
+		} else {
+			scan.extend_from_slice(&pairs[i..end]);
+		}
+		i = end;
+	}
+	if !scan.is_empty() {
+		let mut data = Vec::new();
+		data.put_u16_le(scan.len() as u16);
+		for (code, glyph) in &scan {
+			data.put_u16_le(*code);
+			data.put_u16_le(*glyph);
+		}
+		blocks.push((CMAP_SCAN, 0, 0xFFFF, data));
+	}
+	blocks
+}
+
+fn align4(len: usize) -> usize {
+	(len + 3) & !3
+}
+
+// Level 0 is white and full ink black
+fn sheet_palette(cell: &CellInfo) -> Vec<Color> {
+	let max = cell.max_level() as usize;
+	(0..=max).map(|l| {
+		let v = (255 - l * 255 / max) as u8;
+		[v, v, v]
+	}).collect()
+}
+
+fn to_sheet(cell: &CellInfo, bitmaps: &[Vec<u8>]) -> IndexedImage {
+	let (cw, ch) = (cell.width as usize, cell.height as usize);
+	let rows = bitmaps.len().div_ceil(GLYPHS_PER_ROW).max(1);
+	let (width, height) = (GLYPHS_PER_ROW * cw, rows * ch);
+	let mut pixels = vec![0; width * height];
+	for (i, bitmap) in bitmaps.iter().enumerate() {
+		let (x, y) = ((i % GLYPHS_PER_ROW) * cw, (i / GLYPHS_PER_ROW) * ch);
+		for (row, line) in bitmap.chunks_exact(cw.max(1)).enumerate() {
+			let start = (y + row) * width + x;
+			pixels[start..start + cw].copy_from_slice(line);
+		}
+	}
+	IndexedImage {width, height, pixels}
+}
+
+fn from_sheet(cell: &CellInfo, sheet: &IndexedImage, count: usize) -> Result<Vec<Vec<u8>>, BErr> {
+	let (cw, ch) = (cell.width as usize, cell.height as usize);
+	let rows = count.div_ceil(GLYPHS_PER_ROW);
+	if sheet.width < GLYPHS_PER_ROW * cw || sheet.height < rows * ch {
+		return Err(format!("is {}x{}, {} glyphs need at least {}x{}", sheet.width, sheet.height, count, GLYPHS_PER_ROW * cw, rows * ch).into())
+	}
+	if let Some(level) = sheet.pixels.iter().find(|p| **p > cell.max_level()) {
+		return Err(format!("uses level {} but the font only goes up to {}", level, cell.max_level()).into())
+	}
+	Ok((0..count).map(|i| {
+		let (x, y) = ((i % GLYPHS_PER_ROW) * cw, (i / GLYPHS_PER_ROW) * ch);
+		(0..ch).flat_map(|row| {
+			let start = (y + row) * sheet.width + x;
+			sheet.pixels[start..start + cw].iter().copied()
+		}).collect()
+	}).collect())
+}
+
+fn sidecar(dir: &RelPath, file_name: &str, extension: &str) -> RelPath {
+	let mut path = dir.clone();
+	path.push(format!("{}.{}", file_name, extension));
+	path
+}
+
+// Writes `<file>.png` and `<file>.json` next to an NFTR
+pub fn write_font(helper: &IOHelper, path: &RelPath, buf: &[u8]) {
+	let mut dir = path.clone();
+	let name = dir.pop().unwrap_or_default();
+	let result = parse_nftr(buf).and_then(|(map, bitmaps)| {
+		let sheet = encode_png(&to_sheet(&map.cell, &bitmaps), &sheet_palette(&map.cell))?;
+		helper.write_file(&sidecar(&dir, &name, "png"), &sheet)?;
+		helper.write_file(&sidecar(&dir, &name, "json"), &serde_json::to_vec_pretty(&map)?)?;
+		Ok(())
+	});
+	if let Err(e) = result {
+		println!("Couldn't convert the font {:?}: {}", path, e);
+	}
+}
+
+// Rebuilds an NFTR from its sheet and JSON when either of them was edited
+pub fn import_font(helper: &IOHelper, dir: &RelPath, file_name: &str, file: Bytes) -> Result<Bytes, BErr> {
+	if file.get(..4) != Some(&NFTR_MAGIC.to_le_bytes()) {
+		return Ok(file)
+	}
+	let (png_path, json_path) = (sidecar(dir, file_name, "png"), sidecar(dir, file_name, "json"));
+	if !helper.is_file(&png_path) || !helper.is_file(&json_path) {
+		return Ok(file)
+	}
+	let (original, original_bitmaps) = parse_nftr(&file)?;
+	let map: FontMap = serde_json::from_slice(&helper.read_file(&json_path)?)
+		.map_err(|e| format!("{:?}: {}", json_path, e))?;
+	let (sheet, _) = decode_png(&helper.read_file(&png_path)?, &sheet_palette(&map.cell))
+		.map_err(|e| format!("{:?}: {}", png_path, e))?;
+	let bitmaps = from_sheet(&map.cell, &sheet, map.glyphs.len()).map_err(|e| format!("{:?}: {}", png_path, e))?;
+	if map == original && bitmaps == original_bitmaps {
+		return Ok(file)
+	}
+	Ok(Bytes::from(write_nftr(&map, &bitmaps)?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn font(bpp: u8, metrics: Option<[u8; 4]>) -> (FontMap, Vec<Vec<u8>>) {
+		let cell = CellInfo {width: 7, height: 9, baseline: 7, max_width: 7, bpp, flags: 0};
+		let codes = |i: u16| match i {
+			0..=5 => vec![0x41 + i], // a direct block
+			6 => vec![0x2000, 0x3000],
+			7 => Vec::new(),
+			_ => vec![0x20 + i * 0x10]
+		};
+		let glyphs = (0..10).map(|i| Glyph {codes: codes(i), width: Width {left: i as i8 - 2, width: 5, advance: i as u8}}).collect();
+		let map = FontMap {
+			version: 0x0102, font_type: 0, line_feed: 10, alt_glyph: 7, default_width: Width {left: 0, width: 6, advance: 7},
+			encoding: 1, metrics, cell, glyphs
+		};
+		let bitmaps = (0..10).map(|g| (0..63).map(|p| ((g * 7 + p * 3) % 251) as u8 & cell.max_level()).collect()).collect();
+		(map, bitmaps)
+	}
+
+	#[test]
+	fn fonts_round_trip() {
+		for bpp in [1, 2, 3, 4, 8] {
+			for metrics in [None, Some([9, 7, 7, 0])] {
+				let (map, bitmaps) = font(bpp, metrics);
+				let (parsed_map, parsed_bitmaps) = parse_nftr(&write_nftr(&map, &bitmaps).unwrap()).unwrap();
+				assert_eq!(parsed_map, map, "{} bpp", bpp);
+				assert_eq!(parsed_bitmaps, bitmaps, "{} bpp", bpp);
+			}
+		}
+	}
+
+	#[test]
+	fn sheets_round_trip() {
+		let (map, bitmaps) = font(2, None);
+		assert_eq!(from_sheet(&map.cell, &to_sheet(&map.cell, &bitmaps), bitmaps.len()).unwrap(), bitmaps);
+	}
+}
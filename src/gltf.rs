@@ -0,0 +1,389 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	model::{Bone, Model, Binding, Rotation, parse_nsbmd, rest_matrices, inverse},
+	animation::{SkeletalAnimation, parse_nsbca},
+	textures::{TEX0, safe_name},
+	HPAK
+};
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+// A glTF document being put together, with all of its binary data in one buffer
+#[derive(Default)]
+pub struct Document {
+	pub nodes: Vec<Value>,
+	pub meshes: Vec<Value>,
+	pub materials: Vec<Value>,
+	pub textures: Vec<Value>,
+	pub images: Vec<Value>,
+	pub samplers: Vec<Value>,
+	pub animations: Vec<Value>,
+	pub skins: Vec<Value>,
+	pub accessors: Vec<Value>,
+	pub buffer_views: Vec<Value>,
+	pub bin: Vec<u8>,
+	pub scene_nodes: Vec<usize>
+}
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const UNSIGNED_SHORT: u32 = 5123;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+impl Document {
+	fn view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+		self.bin.resize((self.bin.len() + 3) & !3, 0);
+		let mut view = json!({"buffer": 0, "byteOffset": self.bin.len(), "byteLength": data.len()});
+		if let Some(target) = target {
+			view["target"] = json!(target);
+		}
+		self.bin.extend_from_slice(data);
+		self.buffer_views.push(view);
+		self.buffer_views.len() - 1
+	}
+
+	// `kind` is the glTF accessor type, SCALAR, VEC3 and so on, which says how many floats each element has
+	pub fn floats(&mut self, values: &[f32], kind: &str, with_bounds: bool, target: Option<u32>) -> usize {
+		let size = match kind {
+			"SCALAR" => 1,
+			"VEC2" => 2,
+			"VEC3" => 3,
+			"VEC4" => 4,
+			_ => 16
+		};
+		let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+		let view = self.view(&data, target);
+		let mut accessor = json!({"bufferView": view, "componentType": FLOAT, "count": values.len() / size, "type": kind});
+		if with_bounds {
+			let mut min = vec![f32::MAX; size];
+			let mut max = vec![f32::MIN; size];
+			for element in values.chunks_exact(size) {
+				for (i, v) in element.iter().enumerate() {
+					min[i] = min[i].min(*v);
+					max[i] = max[i].max(*v);
+				}
+			}
+			accessor["min"] = json!(min);
+			accessor["max"] = json!(max);
+		}
+		self.accessors.push(accessor);
+		self.accessors.len() - 1
+	}
+
+	fn joints(&mut self, values: &[u16]) -> usize {
+		let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+		let view = self.view(&data, Some(ARRAY_BUFFER));
+		self.accessors.push(json!({"bufferView": view, "componentType": UNSIGNED_SHORT, "count": values.len() / 4, "type": "VEC4"}));
+		self.accessors.len() - 1
+	}
+
+	fn indices(&mut self, values: &[u32]) -> usize {
+		let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+		let view = self.view(&data, Some(ELEMENT_ARRAY_BUFFER));
+		self.accessors.push(json!({"bufferView": view, "componentType": UNSIGNED_INT, "count": values.len(), "type": "SCALAR"}));
+		self.accessors.len() - 1
+	}
+
+	pub fn to_json(&self, bin_name: &str) -> Value {
+		let mut gltf = json!({
+			"asset": {"version": "2.0", "generator": "kh358extractor"},
+			"scene": 0,
+			"scenes": [{"nodes": self.scene_nodes}],
+			"nodes": self.nodes,
+			"buffers": [{"uri": bin_name, "byteLength": self.bin.len()}],
+			"bufferViews": self.buffer_views,
+			"accessors": self.accessors
+		});
+		// glTF doesn't allow empty lists
+		for (key, list) in [("meshes", &self.meshes), ("materials", &self.materials), ("textures", &self.textures), ("images", &self.images),
+			("samplers", &self.samplers), ("skins", &self.skins), ("animations", &self.animations)] {
+			if !list.is_empty() {
+				gltf[key] = json!(list);
+			}
+		}
+		gltf
+	}
+}
+
+// An image for one of the model's materials, already written next to the .gltf
+pub struct MaterialImage {
+	pub uri: String,
+	pub width: usize,
+	pub height: usize
+}
+
+// Puts a model into a document: a node for the model holding the mesh and the bones, in their rest pose.
+// The mesh is skinned to the bones it was drawn with. Returns the node of each bone.
+pub fn add_model(doc: &mut Document, model: &Model, images: &[Option<MaterialImage>]) -> Vec<usize> {
+	let root = doc.nodes.len();
+	doc.nodes.push(json!({"name": model.name}));
+	doc.scene_nodes.push(root);
+
+	let bone_nodes: Vec<usize> = (0..model.bones.len()).map(|i| root + 1 + i).collect();
+	for bone in &model.bones {
+		doc.nodes.push(json!({
+			"name": bone.name,
+			"translation": bone.translation,
+			"rotation": quaternion(&bone.rotation),
+			"scale": bone.scale
+		}));
+	}
+	let mut children = Vec::new();
+	for (i, bone) in model.bones.iter().enumerate() {
+		match bone.parent.and_then(|p| bone_nodes.get(p)) {
+			Some(&parent) => {
+				let node = &mut doc.nodes[parent];
+				if node.get("children").is_none() {
+					node["children"] = json!([]);
+				}
+				node["children"].as_array_mut().unwrap().push(json!(bone_nodes[i]));
+			}
+			None => children.push(bone_nodes[i])
+		}
+	}
+
+	let material_base = doc.materials.len();
+	for (material, image) in model.materials.iter().zip(images.iter().chain(std::iter::repeat(&None))) {
+		let mut pbr = json!({"metallicFactor": 0.0, "roughnessFactor": 1.0});
+		let mut gltf_material = json!({"name": material.name, "doubleSided": material.double_sided});
+		if let Some(image) = image {
+			doc.images.push(json!({"uri": image.uri}));
+			let wrap = |axis: usize| if !material.repeat[axis] {33071} else if material.mirror[axis] {33648} else {10497};
+			doc.samplers.push(json!({"magFilter": 9728, "minFilter": 9728, "wrapS": wrap(0), "wrapT": wrap(1)}));
+			doc.textures.push(json!({"source": doc.images.len() - 1, "sampler": doc.samplers.len() - 1}));
+			pbr["baseColorTexture"] = json!({"index": doc.textures.len() - 1});
+			pbr["baseColorFactor"] = json!([1.0, 1.0, 1.0, material.alpha]);
+			// textures with see-through texels have to be cut out even when the polygon itself is solid
+			gltf_material["alphaMode"] = json!(if material.alpha < 1.0 {"BLEND"} else {"MASK"});
+		} else {
+			let [r, g, b] = material.diffuse;
+			pbr["baseColorFactor"] = json!([r, g, b, material.alpha]);
+			if material.alpha < 1.0 {
+				gltf_material["alphaMode"] = json!("BLEND");
+			}
+		}
+		gltf_material["pbrMetallicRoughness"] = pbr;
+		doc.materials.push(gltf_material);
+	}
+
+	let mut primitives = Vec::new();
+	for primitive in &model.primitives {
+		let vertices = &primitive.vertices;
+		let positions: Vec<f32> = vertices.iter().flat_map(|v| v.position).collect();
+		let mut attributes = json!({"POSITION": doc.floats(&positions, "VEC3", true, Some(ARRAY_BUFFER))});
+		if primitive.has_normals {
+			let normals: Vec<f32> = vertices.iter().flat_map(|v| v.normal).collect();
+			attributes["NORMAL"] = json!(doc.floats(&normals, "VEC3", false, Some(ARRAY_BUFFER)));
+		}
+		let image = primitive.material.and_then(|m| images.get(m)).and_then(|i| i.as_ref());
+		if let Some(image) = image {
+			let uvs: Vec<f32> = vertices.iter().flat_map(|v| [v.uv[0] / image.width as f32, v.uv[1] / image.height as f32]).collect();
+			attributes["TEXCOORD_0"] = json!(doc.floats(&uvs, "VEC2", false, Some(ARRAY_BUFFER)));
+		}
+		if !model.bones.is_empty() {
+			let (joints, weights) = joints_and_weights(vertices.iter().map(|v| &v.binding), model.bones.len());
+			attributes["JOINTS_0"] = json!(doc.joints(&joints));
+			attributes["WEIGHTS_0"] = json!(doc.floats(&weights, "VEC4", false, Some(ARRAY_BUFFER)));
+		}
+		if primitive.has_colors {
+			let colors: Vec<f32> = vertices.iter().flat_map(|v| v.color).collect();
+			attributes["COLOR_0"] = json!(doc.floats(&colors, "VEC3", false, Some(ARRAY_BUFFER)));
+		}
+		let mut gltf_primitive = json!({"attributes": attributes, "indices": doc.indices(&primitive.indices)});
+		if let Some(material) = primitive.material.filter(|m| *m < model.materials.len()) {
+			gltf_primitive["material"] = json!(material_base + material);
+		}
+		primitives.push(gltf_primitive);
+	}
+	if !primitives.is_empty() {
+		doc.meshes.push(json!({"name": model.name, "primitives": primitives}));
+		let mut node = json!({"name": format!("{}_mesh", model.name), "mesh": doc.meshes.len() - 1});
+		if !model.bones.is_empty() {
+			// the vertices are already where the bones put them, so the inverse binds undo the rest pose
+			let inverse_binds: Vec<f32> = rest_matrices(&model.bones).iter()
+				.flat_map(|m| {
+					let m = inverse(m);
+					(0..16).map(move |i| m[i % 4][i / 4])
+				})
+				.collect();
+			let accessor = doc.floats(&inverse_binds, "MAT4", false, None);
+			doc.skins.push(json!({"joints": bone_nodes, "inverseBindMatrices": accessor, "skeleton": root}));
+			node["skin"] = json!(doc.skins.len() - 1);
+		}
+		doc.nodes.push(node);
+		children.push(doc.nodes.len() - 1);
+	}
+	if !children.is_empty() {
+		doc.nodes[root]["children"] = json!(children);
+	}
+	bone_nodes
+}
+
+// the games run their animations at 60 frames a second
+const FRAME_RATE: f32 = 60.0;
+
+// Adds an animation with a channel for the translation, rotation and scale of each bone, sampled every frame
+pub fn add_skeletal_animation(doc: &mut Document, name: &str, animation: &SkeletalAnimation, bones: &[Bone], bone_nodes: &[usize]) {
+	let frames = animation.frames.max(1);
+	let times: Vec<f32> = (0..frames).map(|f| f as f32 / FRAME_RATE).collect();
+	let input = doc.floats(&times, "SCALAR", true, None);
+	let mut samplers = Vec::new();
+	let mut channels = Vec::new();
+	for (i, (bone, node)) in animation.bones.iter().zip(bone_nodes).enumerate() {
+		let rest = bones.get(i);
+		let (mut translations, mut rotations, mut scales) = (Vec::new(), Vec::new(), Vec::new());
+		let mut previous: Option<[f32; 4]> = None;
+		for frame in 0..frames {
+			let (translation, rotation, scale) = bone.sample(frame, rest);
+			let mut q = quaternion(&rotation);
+			// keep to the same side as the last frame so the rotation doesn't spin the long way round
+			if let Some(p) = previous {
+				if p.iter().zip(q.iter()).map(|(a, b)| a * b).sum::<f32>() < 0.0 {
+					q = q.map(|v| -v);
+				}
+			}
+			previous = Some(q);
+			translations.extend_from_slice(&translation);
+			rotations.extend_from_slice(&q);
+			scales.extend_from_slice(&scale);
+		}
+		for (path, values, kind) in [("translation", translations, "VEC3"), ("rotation", rotations, "VEC4"), ("scale", scales, "VEC3")] {
+			let output = doc.floats(&values, kind, false, None);
+			samplers.push(json!({"input": input, "output": output, "interpolation": "LINEAR"}));
+			channels.push(json!({"sampler": samplers.len() - 1, "target": {"node": node, "path": path}}));
+		}
+	}
+	if !channels.is_empty() {
+		doc.animations.push(json!({"name": name, "samplers": samplers, "channels": channels}));
+	}
+}
+
+// glTF allows four bones per vertex, so only the heaviest ones are kept
+fn joints_and_weights<'a>(bindings: impl Iterator<Item = &'a Binding>, bone_count: usize) -> (Vec<u16>, Vec<f32>) {
+	let mut joints = Vec::new();
+	let mut weights = Vec::new();
+	for binding in bindings {
+		let mut binding: Binding = binding.iter().copied().filter(|(b, w)| *b < bone_count && *w > 0.0).collect();
+		binding.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+		binding.truncate(4);
+		if binding.is_empty() {
+			binding.push((0, 1.0));
+		}
+		let total: f32 = binding.iter().map(|(_, w)| w).sum();
+		for i in 0..4 {
+			let (joint, weight) = binding.get(i).copied().unwrap_or((0, 0.0));
+			joints.push(joint as u16);
+			weights.push(weight / total);
+		}
+	}
+	(joints, weights)
+}
+
+// rotation matrix to [x, y, z, w]
+pub fn quaternion(m: &Rotation) -> [f32; 4] {
+	let trace = m[0][0] + m[1][1] + m[2][2];
+	let q = if trace > 0.0 {
+		let s = (trace + 1.0).sqrt() * 2.0;
+		[(m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s, s / 4.0]
+	} else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+		let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+		[s / 4.0, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s, (m[2][1] - m[1][2]) / s]
+	} else if m[1][1] > m[2][2] {
+		let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+		[(m[0][1] + m[1][0]) / s, s / 4.0, (m[1][2] + m[2][1]) / s, (m[0][2] - m[2][0]) / s]
+	} else {
+		let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+		[(m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, s / 4.0, (m[1][0] - m[0][1]) / s]
+	};
+	let len = q.iter().map(|v| v * v).sum::<f32>().sqrt();
+	if len > 0.0 {q.map(|v| v / len)} else {[0.0, 0.0, 0.0, 1.0]}
+}
+
+// Finds a material's texture, looking in the model's own file first and then in the rest of the bundle,
+// and writes it as `<file>.<model>.<texture>.png`.
+fn write_material_image(helper: &IOHelper, dir: &RelPath, prefix: &str, files: &[&(&Bytes, Option<TEX0>)], texture: &str, palette: Option<&str>) -> Result<Option<MaterialImage>, BErr> {
+	for (buf, tex0) in files.iter().copied() {
+		let tex0 = match tex0 {
+			Some(tex0) => tex0,
+			None => continue
+		};
+		let index = match tex0.textures.iter().position(|t| t.name == texture) {
+			Some(index) => index,
+			None => continue
+		};
+		let palette = palette.and_then(|p| tex0.palettes.iter().find(|x| x.name == p)).or_else(|| tex0.palette_for(index));
+		let pixels = tex0.decode_with(buf, index, palette)?;
+		let texture = &tex0.textures[index];
+		let uri = format!("{}.{}.png", prefix, safe_name(&texture.name));
+		let mut png = Vec::new();
+		let mut encoder = png::Encoder::new(&mut png, texture.width as u32, texture.height as u32);
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.write_header()?.write_image_data(&pixels)?;
+		let mut path = dir.clone();
+		path.push(uri.clone());
+		helper.write_file(&path, &png)?;
+		return Ok(Some(MaterialImage {uri, width: texture.width, height: texture.height}))
+	}
+	Err(format!("texture {} isn't in the bundle", texture).into())
+}
+
+// Writes every model of the bundle's NSBMDs as `<n>.nsbmd.<model>.gltf`, with its buffer in a `.bin` and its textures as pngs.
+// The bundle's skeletal animations are added to each of them.
+pub fn write_hpak_models(helper: &IOHelper, dir: &RelPath, hpak: &HPAK) {
+	let mut animations = Vec::new();
+	for (i, buf) in hpak.nsbca.iter().enumerate() {
+		match parse_nsbca(buf) {
+			Ok(parsed) => animations.extend(parsed.into_iter().map(|a| (format!("{}.nsbca.{}", i, a.name), a))),
+			Err(e) => println!("Couldn't read the animations in {:?}/{}.nsbca: {}", dir, i, e)
+		}
+	}
+	let files: Vec<(&Bytes, Option<TEX0>)> = hpak.nsbmd.iter().map(|buf| (buf, TEX0::parse(buf).ok().flatten())).collect();
+	for (i, buf) in hpak.nsbmd.iter().enumerate() {
+		let models = match parse_nsbmd(buf) {
+			Ok(models) => models,
+			Err(e) => {
+				println!("Couldn't read the models in {:?}/{}.nsbmd: {}", dir, i, e);
+				continue
+			}
+		};
+		// the model's own textures come first
+		let mut search = vec![&files[i]];
+		search.extend(files.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, f)| f));
+		for model in &models {
+			let prefix = format!("{}.nsbmd.{}", i, safe_name(&model.name));
+			let images: Vec<Option<MaterialImage>> = model.materials.iter().map(|material| {
+				let texture = material.texture.as_ref()?;
+				match write_material_image(helper, dir, &prefix, &search, texture, material.palette.as_deref()) {
+					Ok(image) => image,
+					Err(e) => {
+						println!("Couldn't convert texture {} of {} in {:?}: {}", texture, model.name, dir, e);
+						None
+					}
+				}
+			}).collect();
+			let mut doc = Document::default();
+			let bone_nodes = add_model(&mut doc, model, &images);
+			for (name, animation) in &animations {
+				add_skeletal_animation(&mut doc, name, animation, &model.bones, &bone_nodes);
+			}
+			if let Err(e) = write_document(helper, dir, &prefix, &doc) {
+				println!("Couldn't write {} in {:?} as glTF: {}", model.name, dir, e);
+			}
+		}
+	}
+}
+
+pub fn write_document(helper: &IOHelper, dir: &RelPath, prefix: &str, doc: &Document) -> Result<(), BErr> {
+	let bin_name = format!("{}.bin", prefix);
+	let gltf = serde_json::to_vec_pretty(&doc.to_json(&bin_name))?;
+	let mut path = dir.clone();
+	path.push(bin_name);
+	helper.write_file(&path, &doc.bin)?;
+	path.pop();
+	path.push(format!("{}.gltf", prefix));
+	helper.write_file(&path, &gltf)?;
+	Ok(())
+}
@@ -0,0 +1,559 @@
+use crate::{
+	BErr, PK2D,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	nitro::NitroFile,
+	cells::write_cell_pngs,
+	util::content_hash
+};
+use bytes::{Buf, Bytes};
+
+pub type Color = [u8; 3];
+
+// one palette index per pixel, row by row
+pub struct IndexedImage {
+	pub width: usize,
+	pub height: usize,
+	pub pixels: Vec<u8>
+}
+
+// NCLR: BGR555 colors, 16 per palette for 4bpp graphics or 256 for 8bpp
+pub struct NCLR {
+	pub colors: Vec<Color>,
+	data_start: usize // where the colors are in the file
+}
+
+impl NCLR {
+	pub fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let file = NitroFile::parse(buf)?;
+		if file.magic != NCLR_MAGIC {
+			return Err("not an NCLR".into())
+		}
+		let section = file.get_section(PLTT_MAGIC).ok_or("NCLR has no palette section")?;
+		if section.len() < 0x10 {
+			return Err("palette section too small".into())
+		}
+		let mut header = section;
+		header.advance(8); // bit depth, extended palette flag
+		let size = header.get_u32_le() as usize;
+		let offset = header.get_u32_le() as usize;
+		let data = section.get(offset..offset + size).ok_or("palette data out of range")?;
+		Ok(NCLR {
+			colors: data.chunks_exact(2).map(|c| bgr555_to_rgb(u16::from_le_bytes([c[0], c[1]]))).collect(),
+			data_start: file.get_section_offset(PLTT_MAGIC).unwrap() + offset
+		})
+	}
+
+	// replaces (index, color) pairs in the file this was parsed from
+	pub fn write_colors(&self, buf: &mut [u8], colors: &[(usize, Color)]) {
+		for (i, color) in colors {
+			let pos = self.data_start + i * 2;
+			buf[pos..pos + 2].copy_from_slice(&rgb_to_bgr555(*color).to_le_bytes());
+		}
+	}
+}
+
+pub fn bgr555_to_rgb(c: u16) -> Color {
+	let scale = |v: u16| ((v << 3) | (v >> 2)) as u8;
+	[scale(c & 0x1F), scale((c >> 5) & 0x1F), scale((c >> 10) & 0x1F)]
+}
+
+pub fn rgb_to_bgr555(c: Color) -> u16 {
+	(c[0] >> 3) as u16 | ((c[1] >> 3) as u16) << 5 | ((c[2] >> 3) as u16) << 10
+}
+
+// NCGR, with every pixel unpacked to a byte
+pub struct NCGR {
+	pub bpp: u8,
+	pub width_tiles: usize,
+	pub height_tiles: usize,
+	pub linear: bool, // pixels are stored row by row across the whole image instead of in 8x8 tiles
+	pub pixels: Vec<u8>,
+	data_start: usize
+}
+
+impl NCGR {
+	pub fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let file = NitroFile::parse(buf)?;
+		if file.magic != NCGR_MAGIC {
+			return Err("not an NCGR".into())
+		}
+		let section = file.get_section(CHAR_MAGIC).ok_or("NCGR has no character section")?;
+		if section.len() < 0x18 {
+			return Err("character section too small".into())
+		}
+		let mut header = section;
+		let height_tiles = header.get_u16_le();
+		let width_tiles = header.get_u16_le();
+		let bpp = match header.get_u32_le() {
+			3 => 4,
+			4 => 8,
+			d => return Err(format!("unsupported bit depth {}", d).into())
+		};
+		header.advance(4); // mapping
+		let linear = header.get_u32_le() & 1 != 0;
+		let size = header.get_u32_le() as usize;
+		let offset = header.get_u32_le() as usize;
+		let data = section.get(offset..offset + size).ok_or("character data out of range")?;
+		let pixels: Vec<u8> = if bpp == 4 {
+			data.iter().flat_map(|b| [b & 0xF, b >> 4]).collect()
+		} else {
+			data.to_vec()
+		};
+		let tile_count = pixels.len() / 64;
+		// graphics only ever used through cells don't say how big they are
+		let (width_tiles, height_tiles) = if width_tiles == 0xFFFF || height_tiles == 0xFFFF || width_tiles as usize * height_tiles as usize > tile_count {
+			let width = tile_count.clamp(1, 32);
+			(width, tile_count.div_ceil(width))
+		} else {
+			(width_tiles as usize, height_tiles as usize)
+		};
+		let data_start = file.get_section_offset(CHAR_MAGIC).unwrap() + offset;
+		Ok(NCGR {bpp, width_tiles, height_tiles, linear, pixels, data_start})
+	}
+
+	pub fn tile(&self, index: usize) -> Option<&[u8]> {
+		self.pixels.get(index * 64..index * 64 + 64)
+	}
+
+	pub fn to_image(&self) -> IndexedImage {
+		let (width, height) = (self.width_tiles * 8, self.height_tiles * 8);
+		let mut image = vec![0; width * height];
+		if self.linear {
+			let len = image.len().min(self.pixels.len());
+			image[..len].copy_from_slice(&self.pixels[..len]);
+		} else {
+			for t in 0..self.width_tiles * self.height_tiles {
+				if let Some(tile) = self.tile(t) {
+					blit_tile(&mut image, width, (t % self.width_tiles) * 8, (t / self.width_tiles) * 8, tile, false, false, 0);
+				}
+			}
+		}
+		IndexedImage {width, height, pixels: image}
+	}
+
+	// The reverse of to_image, writing the pixels back into the file this was parsed from.
+	// Tiles past the ones the image shows are left alone.
+	pub fn write_image(&self, buf: &mut [u8], image: &IndexedImage) {
+		let mut pixels = self.pixels.clone();
+		if self.linear {
+			let len = image.pixels.len().min(pixels.len());
+			pixels[..len].copy_from_slice(&image.pixels[..len]);
+		} else {
+			for (t, tile) in pixels.chunks_exact_mut(64).take(self.width_tiles * self.height_tiles).enumerate() {
+				let (x, y) = ((t % self.width_tiles) * 8, (t / self.width_tiles) * 8);
+				for (row, tile_row) in tile.chunks_exact_mut(8).enumerate() {
+					let start = (y + row) * image.width + x;
+					tile_row.copy_from_slice(&image.pixels[start..start + 8]);
+				}
+			}
+		}
+		let data: Vec<u8> = if self.bpp == 4 {
+			pixels.chunks_exact(2).map(|p| p[0] & 0xF | p[1] << 4).collect()
+		} else {
+			pixels
+		};
+		buf[self.data_start..self.data_start + data.len()].copy_from_slice(&data);
+	}
+}
+
+// NSCR: which tile, palette and flips go at each 8x8 spot of a background
+pub struct NSCR {
+	pub width: usize,
+	pub height: usize,
+	pub entries: Vec<u16>
+}
+
+impl NSCR {
+	pub fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let file = NitroFile::parse(buf)?;
+		if file.magic != NSCR_MAGIC {
+			return Err("not an NSCR".into())
+		}
+		let section = file.get_section(SCRN_MAGIC).ok_or("NSCR has no screen section")?;
+		if section.len() < 0xC {
+			return Err("screen section too small".into())
+		}
+		let mut header = section;
+		let width = header.get_u16_le() as usize;
+		let height = header.get_u16_le() as usize;
+		header.advance(4); // format
+		let size = header.get_u32_le() as usize;
+		let data = section.get(0xC..0xC + size).ok_or("screen data out of range")?;
+		Ok(NSCR {
+			width, height,
+			entries: data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect()
+		})
+	}
+
+	// 4bpp tiles index into the full palette by adding 16 times their palette number
+	pub fn render(&self, ncgr: &NCGR) -> IndexedImage {
+		let mut image = vec![0; self.width * self.height];
+		let columns = self.width / 8;
+		for (i, entry) in self.entries.iter().take(columns * (self.height / 8)).enumerate() {
+			let tile = match ncgr.tile((entry & 0x3FF) as usize) {
+				Some(tile) => tile,
+				None => continue
+			};
+			let bank = if ncgr.bpp == 4 {(entry >> 12) as u8 * 16} else {0};
+			blit_tile(&mut image, self.width, (i % columns) * 8, (i / columns) * 8, tile, entry & 0x400 != 0, entry & 0x800 != 0, bank);
+		}
+		IndexedImage {width: self.width, height: self.height, pixels: image}
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
+fn blit_tile(image: &mut [u8], width: usize, x: usize, y: usize, tile: &[u8], hflip: bool, vflip: bool, bank: u8) {
+	for ty in 0..8 {
+		for tx in 0..8 {
+			let sx = if hflip {7 - tx} else {tx};
+			let sy = if vflip {7 - ty} else {ty};
+			image[(y + ty) * width + x + tx] = tile[sy * 8 + sx].wrapping_add(bank);
+		}
+	}
+}
+
+pub fn encode_png(image: &IndexedImage, palette: &[Color]) -> Result<Vec<u8>, BErr> {
+	encode_indexed(&[image], palette, false, None)
+}
+
+// Index 0 can be made transparent, like it is for sprites on the DS. With `timing` (how long each frame stays up
+// in 60ths of a second, and whether it loops) the frames become an APNG, otherwise only the first is used.
+pub fn encode_indexed(frames: &[&IndexedImage], palette: &[Color], transparent: bool, timing: Option<(&[u16], bool)>) -> Result<Vec<u8>, BErr> {
+	let first = frames.first().ok_or("no image to encode")?;
+	let mut out = Vec::new();
+	let mut encoder = png::Encoder::new(&mut out, first.width as u32, first.height as u32);
+	encoder.set_color(png::ColorType::Indexed);
+	encoder.set_depth(png::BitDepth::Eight);
+	// every index the pixels use needs an entry, missing colors come out black
+	let used = frames.iter().flat_map(|f| f.pixels.iter()).copied().max().map_or(1, |m| m as usize + 1);
+	let mut plte: Vec<u8> = palette.iter().take(256).flatten().copied().collect();
+	plte.resize(plte.len().max(used * 3), 0);
+	encoder.set_palette(plte);
+	if transparent {
+		encoder.set_trns(vec![0]);
+	}
+	if let Some((_, looping)) = timing {
+		encoder.set_animated(frames.len() as u32, if looping {0} else {1})?;
+	}
+	let mut writer = encoder.write_header()?;
+	for (i, frame) in frames.iter().enumerate() {
+		if let Some((delays, _)) = timing {
+			writer.set_frame_delay(delays[i], 60)?;
+		}
+		writer.write_image_data(&frame.pixels)?;
+		if timing.is_none() {
+			break;
+		}
+	}
+	drop(writer);
+	Ok(out)
+}
+
+// Indexed images keep their indices and come with their own palette, which aren't checked against `palette`.
+// Anything else has each color looked up in it, and fully transparent pixels become index 0.
+pub fn decode_png(buf: &[u8], palette: &[Color]) -> Result<(IndexedImage, Option<Vec<Color>>), BErr> {
+	let mut reader = png::Decoder::new(buf).read_info()?;
+	if reader.info().color_type == png::ColorType::Indexed {
+		let plte: Vec<Color> = reader.info().palette.as_ref()
+			.map(|p| p.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+			.unwrap_or_default();
+		let mut data = vec![0; reader.output_buffer_size()];
+		let frame = reader.next_frame(&mut data)?;
+		let (width, height) = (frame.width as usize, frame.height as usize);
+		let depth = frame.bit_depth as usize;
+		let mask = (0xFF >> (8 - depth)) as u8;
+		let mut pixels = Vec::with_capacity(width * height);
+		for row in data.chunks(frame.line_size).take(height) {
+			pixels.extend((0..width).map(|x| {
+				let bit = x * depth;
+				(row[bit / 8] >> (8 - depth - bit % 8)) & mask
+			}));
+		}
+		return Ok((IndexedImage {width, height, pixels}, Some(plte)))
+	}
+
+	let (width, height, rgba) = read_rgba(buf)?;
+	let mut pixels = Vec::with_capacity(width * height);
+	let mut missing = Vec::new();
+	for px in rgba.chunks_exact(4) {
+		let (color, alpha) = ([px[0], px[1], px[2]], px[3]);
+		if alpha == 0 {
+			pixels.push(0);
+			continue;
+		}
+		let bgr = rgb_to_bgr555(color);
+		match palette.iter().position(|c| rgb_to_bgr555(*c) == bgr) {
+			Some(i) => pixels.push(i as u8),
+			None => {
+				if !missing.contains(&bgr) {
+					missing.push(bgr);
+				}
+				pixels.push(0);
+			}
+		}
+	}
+	if !missing.is_empty() {
+		return Err(format!("uses {} colors that aren't in the palette, save it as an indexed png to change the palette", missing.len()).into())
+	}
+	Ok((IndexedImage {width, height, pixels}, None))
+}
+
+// Any png as (width, height, RGBA pixels)
+pub fn read_rgba(buf: &[u8]) -> Result<(usize, usize, Vec<u8>), BErr> {
+	let mut decoder = png::Decoder::new(buf);
+	decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+	let mut reader = decoder.read_info()?;
+	let mut data = vec![0; reader.output_buffer_size()];
+	let frame = reader.next_frame(&mut data)?;
+	let (width, height) = (frame.width as usize, frame.height as usize);
+	let channels = frame.color_type.samples();
+	let mut rgba = Vec::with_capacity(width * height * 4);
+	for row in data.chunks(frame.line_size).take(height) {
+		for px in row.chunks_exact(channels).take(width) {
+			rgba.extend_from_slice(&match *px {
+				[g] => [g, g, g, 0xFF],
+				[g, a] => [g, g, g, a],
+				[r, g, b] => [r, g, b, 0xFF],
+				[r, g, b, a] => [r, g, b, a],
+				_ => unreachable!()
+			});
+		}
+	}
+	Ok((width, height, rgba))
+}
+
+// Writes `<n>.ncgr.png` next to each NCGR and `<n>.nscr.png` next to each NSCR, taking the palette and graphics
+// with the same number or the first ones in the bundle. Anything that doesn't decode is skipped with a message.
+// Gives the content hash of each NCGR's png, so packing can tell which were edited.
+pub fn write_pk2d_pngs(helper: &IOHelper, path: &RelPath, pk2d: &PK2D) -> Vec<Option<u64>> {
+	let palettes: Vec<_> = pk2d.nclr.iter().map(|p| NCLR::parse(p)).collect();
+	let graphics: Vec<_> = pk2d.ncgr.iter().map(|g| NCGR::parse(g)).collect();
+	let pick = |i: usize, len: usize| if i < len {i} else {0};
+	let write = |name: String, image: Result<(IndexedImage, &[Color]), BErr>| {
+		let result = image.and_then(|(image, palette)| {
+			let mut png_path = path.clone();
+			png_path.push(name.clone());
+			let png = encode_png(&image, palette)?;
+			helper.write_file(&png_path, &png)?;
+			Ok(content_hash(&png))
+		});
+		result.map_err(|e| println!("Couldn't convert {:?} {} to png: {}", path, name, e)).ok()
+	};
+	let mut hashes = Vec::with_capacity(graphics.len());
+	for (i, ncgr) in graphics.iter().enumerate() {
+		let image = match (ncgr, palettes.get(pick(i, palettes.len()))) {
+			(Ok(ncgr), Some(Ok(nclr))) => Ok((ncgr.to_image(), palette_for(ncgr, nclr))),
+			(Err(e), _) => Err(e.to_string().into()),
+			(_, Some(Err(e))) => Err(e.to_string().into()),
+			(_, None) => Err("no palette in the bundle".into())
+		};
+		hashes.push(write(format!("{}.ncgr.png", i), image));
+	}
+	for (i, nscr) in pk2d.nscr.iter().enumerate() {
+		let g = pick(i, graphics.len());
+		let image = NSCR::parse(nscr).and_then(|screen| {
+			match (graphics.get(g), palettes.get(pick(g, palettes.len()))) {
+				(Some(Ok(ncgr)), Some(Ok(nclr))) => Ok((screen.render(ncgr), &nclr.colors[..])),
+				_ => Err("no usable graphics or palette in the bundle".into())
+			}
+		});
+		write(format!("{}.nscr.png", i), image);
+	}
+	write_cell_pngs(helper, path, pk2d, &graphics, &palettes);
+	hashes
+}
+
+// on its own a 4bpp image can only use the first palette
+fn palette_for<'a>(ncgr: &NCGR, nclr: &'a NCLR) -> &'a [Color] {
+	let len = if ncgr.bpp == 4 {nclr.colors.len().min(16)} else {nclr.colors.len()};
+	&nclr.colors[..len]
+}
+
+// Reads back the `<n>.ncgr.png` images write_pk2d_pngs made. One that was edited since (by `written`, the hashes
+// it gave, or when they weren't recorded by no longer matching its NCGR) is encoded into it, and if it's an indexed
+// image with different colors, those go into the NCLR it was shown with.
+pub fn import_pk2d_pngs(helper: &IOHelper, path: &RelPath, pk2d: &mut PK2D, written: &[Option<u64>]) -> Result<(), BErr> {
+	// palettes are compared with how they were before any image was imported, so that an image sharing
+	// a palette with one whose colors were edited doesn't put the old colors back
+	let original_nclr = pk2d.nclr.clone();
+	for i in 0..pk2d.ncgr.len() {
+		let name = format!("{}.ncgr.png", i);
+		let mut png_path = path.clone();
+		png_path.push(name.clone());
+		if !helper.is_file(&png_path) {
+			continue;
+		}
+		let png = helper.read_file(&png_path)?;
+		// an image still as it was extracted leaves the NCGR alone, even if the NCGR itself was edited
+		if written.get(i).copied().flatten() == Some(content_hash(&png)) {
+			continue;
+		}
+		let p = if i < pk2d.nclr.len() {i} else {0};
+		if p >= pk2d.nclr.len() {
+			return Err(format!("{:?} {}: no palette in the bundle", path, name).into())
+		}
+		let (ncgr, nclr) = import_png(&png, &pk2d.ncgr[i], &original_nclr[p], &pk2d.nclr[p])
+			.map_err(|e| format!("{:?} {}: {}", path, name, e))?;
+		if let Some(ncgr) = ncgr {
+			pk2d.ncgr[i] = ncgr;
+		}
+		if let Some(nclr) = nclr {
+			pk2d.nclr[p] = nclr;
+		}
+	}
+	Ok(())
+}
+
+// the new NCGR and NCLR, for whichever of the two changed
+fn import_png(png: &[u8], ncgr_file: &Bytes, original_nclr: &Bytes, nclr_file: &Bytes) -> Result<(Option<Bytes>, Option<Bytes>), BErr> {
+	let ncgr = NCGR::parse(ncgr_file)?;
+	let nclr = NCLR::parse(nclr_file)?;
+	let original = NCLR::parse(original_nclr)?;
+	let palette = palette_for(&ncgr, &nclr);
+	let (image, new_palette) = decode_png(png, palette)?;
+	let current = ncgr.to_image();
+	if (image.width, image.height) != (current.width, current.height) {
+		return Err(format!("is {}x{} but the graphics are {}x{}", image.width, image.height, current.width, current.height).into())
+	}
+	let new_colors: Vec<(usize, Color)> = new_palette.unwrap_or_default().into_iter()
+		.zip(palette_for(&ncgr, &original))
+		.enumerate()
+		.filter(|(_, (new, old))| rgb_to_bgr555(*new) != rgb_to_bgr555(**old))
+		.map(|(i, (new, _))| (i, new))
+		.collect();
+	// pixels that weren't touched are allowed to keep pointing past the palette, like they do in some of the originals
+	let changed = image.pixels.iter().zip(&current.pixels).filter(|(new, old)| new != old);
+	if let Some((max, _)) = changed.max_by_key(|(new, _)| **new).filter(|(new, _)| **new as usize >= palette.len()) {
+		return Err(format!("uses color {} but the palette only has {}", max, palette.len()).into())
+	}
+	let new_ncgr = if image.pixels != current.pixels {
+		let mut buf = ncgr_file.to_vec();
+		ncgr.write_image(&mut buf, &image);
+		Some(buf.into())
+	} else {
+		None
+	};
+	let new_nclr = if !new_colors.is_empty() {
+		let mut buf = nclr_file.to_vec();
+		nclr.write_colors(&mut buf, &new_colors);
+		Some(buf.into())
+	} else {
+		None
+	};
+	Ok((new_ncgr, new_nclr))
+}
+
+#[cfg(test)]
+pub mod tests {
+	use super::*;
+	use crate::nitro::build;
+
+	// `pixels` one byte each, in tiles unless `linear`
+	pub fn ncgr_file(bpp: u8, width_tiles: u16, height_tiles: u16, linear: bool, pixels: &[u8]) -> Vec<u8> {
+		let data: Vec<u8> = if bpp == 4 {pixels.chunks(2).map(|p| p[0] | p[1] << 4).collect()} else {pixels.to_vec()};
+		let mut section = Vec::new();
+		for field in [height_tiles as u32 | (width_tiles as u32) << 16, if bpp == 4 {3} else {4}, 0, linear as u32, data.len() as u32, 0x18] {
+			section.extend_from_slice(&field.to_le_bytes());
+		}
+		section.extend_from_slice(&data);
+		build(NCGR_MAGIC, &[(CHAR_MAGIC, &section)])
+	}
+
+	// colors that survive the trip through BGR555
+	pub fn nclr_file(count: usize) -> (Vec<u8>, Vec<Color>) {
+		let colors: Vec<Color> = (0..count).map(|i| bgr555_to_rgb((i * 0x0421 + i / 3) as u16 & 0x7FFF)).collect();
+		let mut section = Vec::new();
+		for field in [3, 0, count as u32 * 2, 0x10] {
+			section.extend_from_slice(&field.to_le_bytes());
+		}
+		for color in &colors {
+			section.extend_from_slice(&rgb_to_bgr555(*color).to_le_bytes());
+		}
+		(build(NCLR_MAGIC, &[(PLTT_MAGIC, &section)]), colors)
+	}
+
+	fn nscr_file(width: u16, height: u16, entries: &[u16]) -> Vec<u8> {
+		let mut section = Vec::new();
+		section.extend_from_slice(&width.to_le_bytes());
+		section.extend_from_slice(&height.to_le_bytes());
+		section.extend_from_slice(&[0; 4]);
+		section.extend_from_slice(&(entries.len() as u32 * 2).to_le_bytes());
+		section.extend(entries.iter().flat_map(|e| e.to_le_bytes()));
+		build(NSCR_MAGIC, &[(SCRN_MAGIC, &section)])
+	}
+
+	// every pixel a different index, as far as `bpp` goes
+	fn tiles(count: usize, bpp: u8) -> Vec<u8> {
+		(0..count * 64).map(|p| (p % (1 << bpp)) as u8).collect()
+	}
+
+	#[test]
+	fn tiles_become_images() {
+		for bpp in [4, 8] {
+			let pixels = tiles(2, bpp);
+			let ncgr = NCGR::parse(&ncgr_file(bpp, 2, 1, false, &pixels)).unwrap();
+			let image = ncgr.to_image();
+			assert_eq!((image.width, image.height), (16, 8));
+			for y in 0..8 {
+				assert_eq!(image.pixels[y * 16..y * 16 + 8], pixels[y * 8..y * 8 + 8], "{} bpp", bpp);
+				assert_eq!(image.pixels[y * 16 + 8..y * 16 + 16], pixels[64 + y * 8..64 + y * 8 + 8], "{} bpp", bpp);
+			}
+			// and back
+			let mut file = ncgr_file(bpp, 2, 1, false, &[0; 128]);
+			NCGR::parse(&file).unwrap().write_image(&mut file, &image);
+			assert_eq!(NCGR::parse(&file).unwrap().pixels, pixels);
+		}
+		let linear = NCGR::parse(&ncgr_file(8, 2, 1, true, &tiles(2, 8))).unwrap().to_image();
+		assert_eq!(linear.pixels, tiles(2, 8));
+	}
+
+	#[test]
+	fn screens_place_flip_and_bank_tiles() {
+		let pixels = tiles(2, 4);
+		let ncgr = NCGR::parse(&ncgr_file(4, 2, 1, false, &pixels)).unwrap();
+		// tile 1 with palette 2, then tile 0 flipped both ways with palette 1
+		let screen = NSCR::parse(&nscr_file(16, 8, &[0x2001, 0x1C00])).unwrap();
+		let image = screen.render(&ncgr);
+		for y in 0..8 {
+			for x in 0..8 {
+				assert_eq!(image.pixels[y * 16 + x], pixels[64 + y * 8 + x] + 32);
+				assert_eq!(image.pixels[y * 16 + 8 + x], pixels[(7 - y) * 8 + 7 - x] + 16);
+			}
+		}
+	}
+
+	#[test]
+	fn palettes_decode() {
+		let (file, colors) = nclr_file(16);
+		assert_eq!(NCLR::parse(&file).unwrap().colors, colors);
+		let image = NCGR::parse(&ncgr_file(4, 2, 1, false, &tiles(2, 4))).unwrap().to_image();
+		let (decoded, palette) = decode_png(&encode_png(&image, &colors).unwrap(), &colors).unwrap();
+		assert_eq!(decoded.pixels, image.pixels);
+		assert_eq!(palette.unwrap(), colors);
+	}
+
+	#[test]
+	fn edited_pngs_go_back_in() {
+		let ncgr = Bytes::from(ncgr_file(4, 2, 1, false, &tiles(2, 4)));
+		let (nclr, mut colors) = nclr_file(16);
+		let nclr = Bytes::from(nclr);
+		let mut image = NCGR::parse(&ncgr).unwrap().to_image();
+		let png = encode_png(&image, &colors).unwrap();
+		assert_eq!(import_png(&png, &ncgr, &nclr, &nclr).unwrap(), (None, None));
+
+		image.pixels[0] = 5;
+		colors[3] = bgr555_to_rgb(0x001F);
+		let png = encode_png(&image, &colors).unwrap();
+		let (new_ncgr, new_nclr) = import_png(&png, &ncgr, &nclr, &nclr).unwrap();
+		let new_ncgr = NCGR::parse(&new_ncgr.unwrap()).unwrap();
+		let new_nclr = NCLR::parse(&new_nclr.unwrap()).unwrap();
+		assert_eq!(new_ncgr.to_image().pixels, image.pixels);
+		assert_eq!(new_nclr.colors, colors);
+		assert_eq!(encode_png(&new_ncgr.to_image(), palette_for(&new_ncgr, &new_nclr)).unwrap(), png);
+
+		image.pixels[1] = 16;
+		let error = import_png(&encode_png(&image, &colors).unwrap(), &ncgr, &nclr, &nclr).unwrap_err();
+		assert_eq!(error.to_string(), "uses color 16 but the palette only has 16");
+	}
+}
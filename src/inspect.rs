@@ -0,0 +1,328 @@
+use crate::{
+	BErr, FileType, Options, HPAK, PK2D,
+	compression::{decompress, type_name},
+	nitro,
+	util::Reader
+};
+use bytes::Bytes;
+use serde::Serialize;
+use std::{fs, path::Path};
+
+const P2_ALIGN: usize = 0x200; // P2 offsets count in these
+const PREVIEW: usize = 16; // bytes of padding shown
+
+// One part of a file as it was read: where it is, the fields in it, anything wrong with it and the parts inside it
+#[derive(Serialize, Debug)]
+pub struct Node {
+	pub name: String,
+	pub offset: usize,
+	pub size: usize,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub fields: Vec<Field>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub problems: Vec<String>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub children: Vec<Node>
+}
+
+#[derive(Serialize, Debug)]
+pub struct Field {
+	pub name: String,
+	pub value: String
+}
+
+impl Node {
+	pub fn new(name: impl Into<String>, offset: usize, size: usize) -> Self {
+		Node {name: name.into(), offset, size, fields: Vec::new(), problems: Vec::new(), children: Vec::new()}
+	}
+
+	pub fn field(&mut self, name: &str, value: impl ToString) {
+		self.fields.push(Field {name: name.into(), value: value.to_string()});
+	}
+
+	// every problem in this part and the ones inside it
+	pub fn all_problems(&self) -> Vec<String> {
+		let mut problems: Vec<String> = self.problems.iter().map(|p| format!("{}: {}", self.name, p)).collect();
+		for child in &self.children {
+			problems.extend(child.all_problems());
+		}
+		problems
+	}
+
+	// the tree inspect prints, a line at a time
+	fn print(&self, depth: usize, out: &mut impl FnMut(String)) {
+		let indent = "\t".repeat(depth);
+		out(format!("{}{} at {:#X}, {:#X} bytes", indent, self.name, self.offset, self.size));
+		for field in &self.fields {
+			out(format!("{}\t{}: {}", indent, field.name, field.value));
+		}
+		for problem in &self.problems {
+			out(format!("{}\t!! {}", indent, problem));
+		}
+		for child in &self.children {
+			child.print(depth + 1, out);
+		}
+	}
+}
+
+// Prints how a file is put together, as far as the tool understands it, as a tree or with --json as JSON
+pub fn inspect(target: &Path, options: &Options) -> Result<(), BErr> {
+	let buf = fs::read(target)?;
+	let node = inspect_buf(&buf, true).ok_or_else(|| format!("{:?} isn't a format inspect can read", target))?;
+	if options.json {
+		println!("{}", serde_json::to_string_pretty(&node)?);
+	} else {
+		node.print(0, &mut |line| println!("{}", line));
+	}
+	Ok(())
+}
+
+fn inspect_buf(buf: &[u8], could_be_compressed: bool) -> Option<Node> {
+	let ty = FileType::guess_from(buf, could_be_compressed);
+	Some(match ty {
+		FileType::P2 => inspect_p2(buf),
+		FileType::HPAK | FileType::PK2D | FileType::PKAC => inspect_grouped(buf, ty),
+		FileType::LZ | FileType::RLE | FileType::Huffman => inspect_compressed(buf, 0, true),
+		_ if nitro::is_nitro(ty) => nitro::inspect(buf, ty),
+		_ => return None
+	})
+}
+
+// The bytes in [start, end) that nothing was read from. Rebuilding writes zeros there, anything else only comes back
+// through the patches in the metadata.
+fn add_padding(node: &mut Node, buf: &[u8], mut spans: Vec<(usize, usize)>, end: usize) {
+	spans.sort_unstable();
+	let mut at = 0;
+	let mut gaps = Vec::new();
+	for (start, stop) in spans.into_iter().chain(std::iter::once((end, end))) {
+		if start > at {
+			gaps.push((at, start.min(end)));
+		}
+		at = at.max(stop);
+	}
+	for (start, stop) in gaps.into_iter().filter(|(start, stop)| stop > start) {
+		let bytes = &buf[start..stop];
+		let mut padding = Node::new("padding", start, stop - start);
+		if bytes.iter().all(|b| *b == 0) {
+			padding.field("bytes", "all zero");
+		} else {
+			let preview: Vec<String> = bytes.iter().take(PREVIEW).map(|b| format!("{:02X}", b)).collect();
+			let more = if bytes.len() > PREVIEW {" .."} else {""};
+			padding.field("bytes", format!("{}{}", preview.join(" "), more));
+			padding.field("rebuilt as", "zeros, unless the metadata's patches restore it");
+		}
+		node.children.push(padding);
+	}
+	node.children.sort_by_key(|c| c.offset);
+}
+
+fn inspect_p2(buf: &[u8]) -> Node {
+	let mut node = Node::new("P2", 0, buf.len());
+	if let Err(e) = read_p2(buf, &mut node) {
+		node.problems.push(e.to_string());
+	}
+	node
+}
+
+// Reads what P2File::parse does, without trusting any of it
+fn read_p2(buf: &[u8], node: &mut Node) -> Result<(), BErr> {
+	let mut r = Reader::new(buf, 2);
+	let count_field = r.u16()?;
+	let named = count_field & 0x8000 != 0;
+	let count = (count_field & 0x7FFF) as usize;
+	r.pos = 12;
+	let header_size = r.u32()? as usize;
+	node.field("count field", format!("{:#06X}", count_field));
+	node.field("files", count);
+	node.field("name table", named);
+	node.field("header size", format!("{:#X}", header_size));
+	let mut spans = vec![(0, 4), (12, 16)];
+	let offsets = (0..count).map(|_| r.u16()).collect::<Result<Vec<_>, _>>()?;
+	spans.push((16, r.pos));
+	r.pos += (count & 1) * 2; // padding after an odd number of offsets
+	let table = r.pos;
+	let lengths = (0..count).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+	spans.push((table, r.pos));
+	let mut names = Vec::new();
+	if named {
+		for _ in 0..count {
+			let name = buf.get(r.pos..r.pos + 8).ok_or("the name table is past the end of the file")?;
+			names.push(String::from_utf8_lossy(name).trim_matches(char::from(0)).to_string());
+			r.pos += 8;
+		}
+		spans.push((table + count * 4, r.pos));
+	}
+	if r.pos > header_size {
+		node.problems.push(format!("the header's tables end at {:#X}, past the header size", r.pos));
+	}
+	for (i, (offset_field, length_field)) in offsets.iter().zip(&lengths).enumerate() {
+		let offset = *offset_field as usize * P2_ALIGN + header_size;
+		let len = (length_field & 0xFFFFFF) as usize;
+		let flags = length_field >> 24;
+		let mut file = Node::new(format!("file {}", i), offset, len);
+		if let Some(name) = names.get(i) {
+			file.field("name", name);
+		}
+		file.field("offset field", format!("{:#06X}", offset_field));
+		file.field("length field", format!("{:#010X}", length_field));
+		file.field("compressed", flags == 0x80);
+		if flags != 0 && flags != 0x80 {
+			file.problems.push(format!("flag byte {:#04X} isn't one a rebuild writes", flags));
+		}
+		match buf.get(offset..offset + len) {
+			Some(content) => {
+				if flags == 0x80 && !content.is_empty() {
+					file.children.push(inspect_compressed(content, offset, false));
+				}
+				spans.push((offset, offset + len));
+			}
+			None => file.problems.push(format!("ends at {:#X}, past the end of the file", offset + len))
+		}
+		node.children.push(file);
+	}
+	add_padding(node, buf, spans, buf.len());
+	Ok(())
+}
+
+fn inspect_grouped(buf: &[u8], ty: FileType) -> Node {
+	let mut node = Node::new(format!("{:?}", ty), 0, buf.len());
+	if let Err(e) = read_grouped(buf, ty, &mut node) {
+		node.problems.push(e.to_string());
+	}
+	node
+}
+
+// Reads what GroupedFiles::parse does: 8 groups, each with a count, then its offsets, then its lengths
+fn read_grouped(buf: &[u8], ty: FileType, node: &mut Node) -> Result<(), BErr> {
+	let empty: [Vec<Bytes>; 8] = Default::default();
+	let types: Vec<String> = match ty {
+		FileType::HPAK => HPAK::from(empty).get_type_map().iter().map(|(t, _)| format!("{:?}", t)).collect(),
+		FileType::PK2D => PK2D::from(empty).get_type_map().iter().map(|(t, _)| format!("{:?}", t)).collect(),
+		_ => ["names", "files"].iter().map(|s| s.to_string()).chain((2..8).map(|_| "unused".into())).collect()
+	};
+	let mut r = Reader::new(buf, 8);
+	let info_offsets = (0..8).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+	let mut spans = vec![(0, 4), (8, r.pos)];
+	for (i, info_offset) in info_offsets.iter().enumerate() {
+		if *info_offset == 0xFFFFFFFF {
+			node.field(&format!("group {} ({})", i, types[i]), "empty");
+			continue;
+		}
+		let info_offset = *info_offset as usize;
+		let mut r = Reader::new(buf, info_offset);
+		let count = r.u32()? as usize;
+		let offsets = (0..count).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+		let lengths = (0..count).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+		let mut group = Node::new(format!("group {} ({})", i, types[i]), info_offset, r.pos - info_offset);
+		group.field("files", count);
+		spans.push((info_offset, r.pos));
+		for (j, (offset, len)) in offsets.iter().zip(&lengths).enumerate() {
+			let (offset, len) = (*offset as usize, *len as usize);
+			let mut file = Node::new(format!("file {}", j), offset, len);
+			match buf.get(offset..offset + len) {
+				Some(content) => {
+					spans.push((offset, offset + len));
+					if ty == FileType::PKAC && i == 0 && j == 0 {
+						read_pkac_names(content, &mut file);
+					} else if let Some(magic) = content.get(..4) {
+						file.field("starts with", magic.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "));
+					}
+				}
+				None => file.problems.push(format!("ends at {:#X}, past the end of the file", offset + len))
+			}
+			group.children.push(file);
+		}
+		node.children.push(group);
+	}
+	add_padding(node, buf, spans, buf.len());
+	Ok(())
+}
+
+// The name table PKACs keep as their first file: a count, offsets, then the names
+fn read_pkac_names(buf: &[u8], node: &mut Node) {
+	let mut r = Reader::new(buf, 0);
+	let count = match r.u16() {
+		Ok(count) => count,
+		Err(e) => return node.problems.push(e.to_string())
+	};
+	node.field("names", count);
+	for i in 0..count {
+		let offset = match r.u16() {
+			Ok(offset) => offset as usize,
+			Err(e) => return node.problems.push(e.to_string())
+		};
+		let name = buf.get(offset..).and_then(|n| n.iter().position(|b| *b == 0).map(|end| &n[..end]));
+		match name {
+			Some(name) => node.field(&format!("name {}", i), format!("{:?} at {:#X}", String::from_utf8_lossy(name), offset)),
+			None => node.problems.push(format!("name {} at {:#X} doesn't end inside the table", i, offset))
+		}
+	}
+}
+
+// The compression header of data at `offset`, and with `inner` what the decompressed data holds
+fn inspect_compressed(buf: &[u8], offset: usize, inner: bool) -> Node {
+	let mut node = Node::new("compressed", offset, buf.len());
+	node.field("type", type_name(buf));
+	if buf.len() >= 4 {
+		let size = u32::from_le_bytes([buf[1], buf[2], buf[3], 0]);
+		node.field("decompressed size field", format!("{:#X}", size));
+		if size == 0 && buf.len() >= 8 {
+			node.field("extended size", format!("{:#X}", u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]])));
+		}
+	}
+	match decompress(buf) {
+		Ok(content) => {
+			node.field("decompresses to", format!("{:#X} bytes", content.len()));
+			if inner {
+				if let Some(child) = inspect_buf(&content, false) {
+					node.children.push(child);
+				}
+			}
+		}
+		Err(e) => node.problems.push(format!("doesn't decompress: {}", e))
+	}
+	node
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{P2File, P2Subfile};
+
+	#[test]
+	fn p2_tree_shows_fields_padding_and_problems() {
+		let p2 = P2File {named: false, subfiles: vec![
+			P2Subfile {index: 0, compressed: false, content: Bytes::from_static(b"hello"), name: None}
+		], layout: None};
+		let mut buf = p2.to_bytes().to_vec();
+		buf[0x206] = 0xAB; // something left after the file
+		buf[0x17] = 0x40; // a flag byte a rebuild never writes
+		let node = inspect_buf(&buf, true).unwrap();
+		let mut lines = Vec::new();
+		node.print(0, &mut |line| lines.push(line));
+		assert_eq!(lines, [
+			"P2 at 0x0, 0x400 bytes",
+			"\tcount field: 0x0001",
+			"\tfiles: 1",
+			"\tname table: false",
+			"\theader size: 0x200",
+			"\tpadding at 0x4, 0x8 bytes",
+			"\t\tbytes: all zero",
+			"\tpadding at 0x12, 0x2 bytes",
+			"\t\tbytes: all zero",
+			"\tpadding at 0x18, 0x1E8 bytes",
+			"\t\tbytes: all zero",
+			"\tfile 0 at 0x200, 0x5 bytes",
+			"\t\toffset field: 0x0000",
+			"\t\tlength field: 0x40000005",
+			"\t\tcompressed: false",
+			"\t\t!! flag byte 0x40 isn't one a rebuild writes",
+			"\tpadding at 0x205, 0x1FB bytes",
+			"\t\tbytes: 00 AB 00 00 00 00 00 00 00 00 00 00 00 00 00 00 ..",
+			"\t\trebuilt as: zeros, unless the metadata's patches restore it"
+		]);
+		assert_eq!(node.all_problems(), ["file 0: flag byte 0x40 isn't one a rebuild writes"]);
+		let json = serde_json::to_value(&node).unwrap();
+		assert_eq!(json["children"][3]["fields"][1], serde_json::json!({"name": "length field", "value": "0x40000005"}));
+	}
+}
@@ -1,18 +1,16 @@
 mod threads;
 use threads::*;
 use crate::{
-	FileType, BErr,
+	FileType, BErr, Options,
 	meta::{MetaRef, FileMeta}
 };
 use std::{
-	io::{prelude::*, BufWriter},
-	ffi::{OsString, OsStr},
+	io::prelude::*,
 	io,
 	path::PathBuf,
 	fs::{read_dir, create_dir_all, File},
 };
 use bytes::Bytes;
-use crossbeam_channel::{Sender, Receiver};
 
 pub struct IOManager {
 	helper: IOHelper,
@@ -20,20 +18,20 @@ pub struct IOManager {
 }
 
 impl IOManager {
-	pub fn new<T>(in_root: PathBuf, out_root: PathBuf, setup_fn: impl Fn(IOHelper) -> T + Sync + 'static + Send + Clone, file_handler: impl Fn(FileQueueEntry, MetaRef<FileMeta>, &T) + Send + 'static + Sync + Clone) -> Self {
-		let (ic, oc) = (in_root.clone(), out_root.clone());
+	pub fn new<T>(in_root: PathBuf, out_root: PathBuf, options: Options, setup_fn: impl Fn(IOHelper) -> T + Sync + 'static + Send + Clone, file_handler: impl Fn(FileQueueEntry, MetaRef<FileMeta>, &T) + Send + 'static + Sync + Clone) -> Self {
+		let (ic, oc, opts) = (in_root.clone(), out_root.clone(), options.clone());
 		let pool = JankyThreadPool::new(4, move |iqe: FileQueueEntryInternal, hlp| {
 			let entry = iqe.entry;
 			let mref = iqe.meta_ref;
 			file_handler(entry, mref, hlp)
 		}, move |s| {
 			setup_fn(IOHelper {
-				in_root: in_root.clone(), out_root: out_root.clone(), file_tx: Some(s)
+				in_root: in_root.clone(), out_root: out_root.clone(), options: opts.clone(), file_tx: Some(s)
 			})
 		});
 		IOManager {
 			helper: IOHelper {
-				in_root: ic, out_root: oc,
+				in_root: ic, out_root: oc, options,
 				file_tx: Some(pool.task_sender())
 			},
 			pool
@@ -53,6 +51,7 @@ impl IOManager {
 pub struct IOHelper {
 	in_root: PathBuf,
 	out_root: PathBuf,
+	options: Options,
 	file_tx: Option<TaskSender<FileQueueEntryInternal>>
 }
 
@@ -94,24 +93,29 @@ impl IOHelper {
 		create_dir_all(path.resolve(self.out_root.clone()))
 	}
 	
-	pub fn queue_or_write(&self, entry: FileQueueEntry, meta_ref: MetaRef<FileMeta>) -> Result<(), BErr> {
-		if entry.get_or_guess_type().still_packed() {
-			self.file_tx.as_ref().unwrap().send(FileQueueEntryInternal{entry, meta_ref})?;
-			Ok(())
-		} else {
-			meta_ref.submit(FileMeta::OtherFile(entry.path.peek()));
-			Ok(self.write_file(&entry.path, &entry.content)?)
-		}
+	// Hands a file to the worker threads, which extract it if it's an archive and write it out otherwise
+	pub fn queue_file(&self, entry: FileQueueEntry, meta_ref: MetaRef<FileMeta>) -> Result<(), BErr> {
+		self.file_tx.as_ref().unwrap().send(FileQueueEntryInternal{entry, meta_ref})?;
+		Ok(())
 	}
 	
 	pub fn is_dir(&self, path: &RelPath) -> bool {
 		path.resolve(self.in_root.clone()).is_dir()
 	}
 	
-	pub fn new(in_path: PathBuf, out_path: PathBuf) -> Self {
+	pub fn is_file(&self, path: &RelPath) -> bool {
+		path.resolve(self.in_root.clone()).is_file()
+	}
+	
+	pub fn get_options(&self) -> &Options {
+		&self.options
+	}
+	
+	pub fn new(in_path: PathBuf, out_path: PathBuf, options: Options) -> Self {
 		IOHelper {
 			in_root: in_path,
 			out_root: out_path,
+			options,
 			file_tx: None
 		}
 	}
@@ -129,15 +133,6 @@ impl RelPath {
 		}
 	}
 	
-	pub fn prepend(self, e: String) -> Self {
-		let mut p = RelPath::new();
-		p.push(e);
-		for s in self.path {
-			p.push(s);
-		}
-		p
-	}
-	
 	pub fn pop(&mut self) -> Option<String> {
 		self.path.pop()
 	}
@@ -3,9 +3,10 @@ use std::{
 		Arc,
 		atomic::{AtomicU32, AtomicBool, Ordering}
 	},
-	thread::{JoinHandle, spawn, sleep},
+	thread::{JoinHandle, spawn},
 	time::Duration,
-	mem::replace
+	mem::take,
+	panic::{catch_unwind, AssertUnwindSafe}
 };
 use crossbeam_channel::{
 	Sender, Receiver, unbounded, SendError
@@ -59,7 +60,8 @@ impl<T: Send + 'static> JankyThreadPool<T> {
 				});
 				while live.load(Ordering::Relaxed) || pending_task_count.load(Ordering::Relaxed) > 0 {
 					if let Ok(t) = rx.recv_timeout(Duration::new(5, 0)) {
-						tc(t, &tx);
+						// a task that panics is done too, or the pool would wait for it forever
+						let _ = catch_unwind(AssertUnwindSafe(|| tc(t, &tx)));
 						pending_task_count.fetch_sub(1, Ordering::Relaxed);
 					}
 				}
@@ -75,20 +77,14 @@ impl<T: Send + 'static> JankyThreadPool<T> {
 		}
 	}
 	
-	pub fn wait_for_tasks(&self) {
-		while self.pending_task_count.load(Ordering::Relaxed) > 0 {
-			sleep(Duration::new(5, 0))
-		}
-	}
-	
 	pub fn shutdown(&self) {
 		self.live.store(false, Ordering::Relaxed);
 	}
 	
 	pub fn join(mut self) {
 		self.shutdown();
-		for t in replace(&mut self.threads, Vec::new()) {
-			t.join();
+		for t in take(&mut self.threads) {
+			let _ = t.join(); // a worker that panicked has already printed why
 		}
 	}
 }
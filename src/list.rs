@@ -0,0 +1,76 @@
+use crate::{
+	BErr, FileType,
+	walk::{Entry, Visitor, walk}
+};
+use std::path::Path;
+
+// Prints each line of the tree as it comes
+struct Lister<F: FnMut(String)> {
+	out: F
+}
+
+impl<F: FnMut(String)> Visitor for Lister<F> {
+	fn dir(&mut self, entry: &Entry) {
+		(self.out)(format!("{}{}/", "\t".repeat(entry.depth), entry.name));
+	}
+
+	// "<index> <name> (<type>, <size>)", with the sizes before and after decompressing if it was compressed
+	fn file(&mut self, entry: &Entry, ty: FileType, size: usize, compressed: Option<(&str, usize)>) {
+		let index = if entry.index.is_empty() {String::new()} else {format!("{} ", entry.index)};
+		let size = match compressed {
+			Some((kind, compressed_size)) => format!("{} {} -> {} bytes", kind, compressed_size, size),
+			None => format!("{} bytes", size)
+		};
+		(self.out)(format!("{}{}{} ({:?}, {})", "\t".repeat(entry.depth), index, entry.name, ty, size));
+	}
+
+	fn failed(&mut self, entry: &Entry, problem: String) {
+		(self.out)(format!("{}\t{}", "\t".repeat(entry.depth), problem));
+	}
+}
+
+// Prints what is in a directory, a rom or a single file, going into every archive in memory and naming
+// everything the way extracting would
+pub fn list(target: &Path) -> Result<(), BErr> {
+	walk(&mut Lister {out: |line| println!("{}", line)}, target)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{P2File, P2Subfile, PKAC, GroupedFiles, magic::*, compression::compress_rle, pack::write_grouped};
+	use bytes::Bytes;
+
+	fn subfile(index: u16, compressed: bool, content: Bytes) -> P2Subfile {
+		P2Subfile {index, compressed, content, name: None}
+	}
+
+	#[test]
+	fn archives_inside_archives_are_listed() {
+		let mut hpak: GroupedFiles = Default::default();
+		hpak[5].push(Bytes::from_static(b"unk5data"));
+		let hpak = write_grouped(HPAK_MAGIC, &hpak, None);
+		let pkac = PKAC {files: vec![("x".into(), Bytes::from_static(b"hello"))]};
+		let pkac = write_grouped(PKAC_MAGIC, &GroupedFiles::from(pkac), None);
+		let p2 = P2File {named: false, subfiles: vec![
+			subfile(0, true, Bytes::from(compress_rle(&hpak).unwrap())),
+			subfile(1, false, pkac),
+			subfile(2, true, Bytes::from_static(&[0x30, 0xFF, 0xFF, 0xFF])) // says it's bigger than it is
+		], layout: None};
+		let dir = std::env::temp_dir().join(format!("kh358extractor-list-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("game.p2"), p2.to_bytes()).unwrap();
+		let mut lines = Vec::new();
+		walk(&mut Lister {out: |line| lines.push(line)}, &dir).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(lines, [
+			"game.p2 (P2, 2048 bytes)",
+			"\t0 0.hpak (HPAK, RLE 40 -> 60 bytes)",
+			"\t\t5.0 0.5.bin (Unknown5, 8 bytes)",
+			"\t1 1.pkac (PKAC, 75 bytes)",
+			"\t\t0 x.bin (OtherOrNotGuessable, 5 bytes)",
+			"\t2 2 (RLE, 4 bytes)",
+			"\t\tdoesn't decompress: compressed data ends early"
+		]);
+	}
+}
@@ -15,4 +15,30 @@ pub const NSCR_MAGIC: u32 = 0x4E534352;
 pub const NFTR_MAGIC: u32 = 0x4E465452;
 pub const NCER_MAGIC: u32 = 0x4E434552;
 pub const NANR_MAGIC: u32 = 0x4E414E52;
-pub const SDAT_MAGIC: u32 = 0x54414453;
\ No newline at end of file
+pub const SDAT_MAGIC: u32 = 0x54414453;
+pub const SSEQ_MAGIC: u32 = 0x51455353;
+pub const SSAR_MAGIC: u32 = 0x52415353;
+pub const SBNK_MAGIC: u32 = 0x4B4E4253;
+pub const SWAR_MAGIC: u32 = 0x52415753;
+pub const STRM_MAGIC: u32 = 0x4D525453;
+// sections inside the Nitro files
+pub const PLTT_MAGIC: u32 = 0x504C5454;
+pub const CHAR_MAGIC: u32 = 0x43484152;
+pub const SCRN_MAGIC: u32 = 0x5343524E;
+pub const CEBK_MAGIC: u32 = 0x4345424B;
+pub const ABNK_MAGIC: u32 = 0x41424E4B;
+pub const TEX0_MAGIC: u32 = 0x30584554;
+pub const MDL0_MAGIC: u32 = 0x304C444D;
+pub const JNT0_MAGIC: u32 = 0x30544E4A;
+pub const PAT0_MAGIC: u32 = 0x30544150;
+pub const SRT0_MAGIC: u32 = 0x30545253;
+pub const MAT0_MAGIC: u32 = 0x3054414D;
+pub const VIS0_MAGIC: u32 = 0x30534956;
+pub const FAT_MAGIC: u32 = 0x20544146;
+pub const FILE_MAGIC: u32 = 0x454C4946;
+pub const HEAD_MAGIC: u32 = 0x44414548;
+pub const DATA_MAGIC: u32 = 0x41544144;
+pub const FINF_MAGIC: u32 = 0x46494E46;
+pub const CGLP_MAGIC: u32 = 0x43474C50;
+pub const CWDH_MAGIC: u32 = 0x43574448;
+pub const CMAP_MAGIC: u32 = 0x434D4150;
@@ -1,3 +1,4 @@
+#![allow(clippy::upper_case_acronyms)] // format names
 mod iohelper;
 mod util;
 mod magic;
@@ -5,76 +6,174 @@ mod extract;
 mod meta;
 mod pack;
 mod compression;
+mod nds;
+mod nitro;
+mod graphics;
+mod cells;
+mod textures;
+mod model;
+mod gltf;
+mod animation;
+mod anims;
+mod sdat;
+mod audio;
+mod midi;
+mod fonts;
+mod text;
+mod analyze;
+mod inspect;
+mod list;
+mod walk;
 use std::{
 	env::args,
-	io,
 	io::Write,
 	fs::File,
 	fs,
 	path::PathBuf,
 	str,
-	mem::replace,
-	ffi::OsString,
-	convert::{TryFrom, TryInto},
 	fmt::{Debug},
 };
 use bytes::{
 	Buf, Bytes
 };
-use nintendo_lz::decompress;
+use compression::decompress;
 use iohelper::{
 	IOHelper, IOManager, FileQueueEntry, RelPath
 };
-use crate::util::*;
 use crate::magic::*;
-use crate::meta::{DirectoryMeta, FileMeta, MetaRef};
-use crate::pack::pack_file;
+use crate::meta::{DirectoryMeta, FileMeta, MetaRef, P2Layout, CompressionLevel};
+use crate::nds::{NDSRom, NitroDir, NitroEntry};
+use crate::pack::{pack_file, pack_rom};
+use crate::text::TextTable;
 use ron::{ser, ser::PrettyConfig, de};
 
 type BErr = Box<dyn std::error::Error + 'static>;
 type GroupedFiles = [Vec<Bytes>; 8];
 
+// settings from --flags on the command line, available to everything through the IOHelper
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+	pub compression_level: CompressionLevel,
+	pub png: bool, // write pngs of the graphics and textures next to them when extracting
+	pub gltf: bool, // write the models in HPAK bundles as glTF when extracting
+	pub json: bool, // write the material and visibility animations as JSON when extracting, and inspect as JSON
+	pub wav: bool, // write the sound in wave archives and streams as WAV when extracting
+	pub midi: bool, // write the sequences as MIDI when extracting
+	pub text: Option<TextTable>, // the character table for converting message files, both ways
+	pub po: bool // write messages as PO instead of JSON
+}
+
+impl Options {
+	fn set(&mut self, flag: &str) -> Result<(), BErr> {
+		let (key, value) = match flag.find('=') {
+			Some(i) => (&flag[..i], &flag[i + 1..]),
+			None => (flag, "")
+		};
+		match key {
+			"--level" => {
+				self.compression_level = CompressionLevel::from_name(value)
+					.ok_or_else(|| format!("unknown compression level {}, expected fast or optimal", value))?;
+			}
+			"--png" => self.png = true,
+			"--gltf" => self.gltf = true,
+			"--json" => self.json = true,
+			"--wav" => self.wav = true,
+			"--midi" => self.midi = true,
+			"--text" => self.text = Some(TextTable::load(value)?),
+			"--po" => self.po = true,
+			_ => return Err(format!("unknown option {}", flag).into())
+		}
+		Ok(())
+	}
+}
+
 fn main() -> Result<(), BErr> {
-	let args = &args().collect::<Vec<String>>();
-	let action = String::from(&args[1]);
-	let target = PathBuf::from(&args[2]);
-	let out = PathBuf::from(&args[3]);
-	let meta = String::from(&args[4]);
+	let mut options = Options::default();
+	let mut positional = Vec::new();
+	for arg in args() {
+		if arg.starts_with("--") {
+			options.set(&arg)?;
+		} else {
+			positional.push(arg);
+		}
+	}
+	let args = &positional;
+	let arg = |i: usize| args.get(i).ok_or("missing arguments, see the README for usage");
+	let action = String::from(arg(1)?);
 	match &action[..] {
 		"pack" => {
-			let meta: FileMeta = de::from_str(&fs::read_to_string(&meta)?)?;
-			repack(target, out, &meta)?;
+			let (target, out) = (PathBuf::from(arg(2)?), PathBuf::from(arg(3)?));
+			let meta: FileMeta = de::from_str(&fs::read_to_string(arg(4)?)?)?;
+			if let Some(base_rom) = args.get(5) {
+				repack_rom(target, PathBuf::from(base_rom), out, &meta, options)?;
+			} else {
+				repack(target, out, &meta, options)?;
+			}
 		}
 		"extract" => {
-			extract_tree(target, out, meta)?;
+			extract_tree(PathBuf::from(arg(2)?), PathBuf::from(arg(3)?), arg(4)?.clone(), options)?;
+		}
+		"analyze" => {
+			analyze::analyze(&PathBuf::from(arg(2)?))?;
+		}
+		"inspect" => {
+			inspect::inspect(&PathBuf::from(arg(2)?), &options)?;
+		}
+		"list" => {
+			list::list(&PathBuf::from(arg(2)?))?;
 		}
 		_ => {
 			println!("invalid action {}", action)
 		}
 	}
-	//extract_tree(target, out)?;
 	
 	Ok(())
 }
 
-fn repack(target: PathBuf, out: PathBuf, meta: &FileMeta) -> Result<(), BErr> {
-	let helper = IOHelper::new(target, out);
+fn repack(target: PathBuf, out: PathBuf, meta: &FileMeta, options: Options) -> Result<(), BErr> {
+	let helper = IOHelper::new(target, out, options);
 	let path = RelPath::new();
 	pack_file(&path, meta, &helper)?;
 	Ok(())
 }
 
-fn extract_tree(target: PathBuf, out: PathBuf, meta: String) -> Result<(), BErr> {
-	let manager = IOManager::new(target, out, |i| i, |f, m, h| {extract::handle_file(f, m, h).unwrap()});
+fn repack_rom(target: PathBuf, base_rom: PathBuf, out: PathBuf, meta: &FileMeta, options: Options) -> Result<(), BErr> {
+	let nds_meta = match meta {
+		FileMeta::NDS(m) => m,
+		_ => return Err("metadata was not extracted from a rom, can't rebuild one".into())
+	};
+	let base = NDSRom::parse(Bytes::from(fs::read(base_rom)?))?;
+	let helper = IOHelper::new(target, PathBuf::new(), options);
+	let rom = pack_rom(nds_meta, &base, &helper)?;
+	fs::write(out, &rom)?;
+	Ok(())
+}
+
+fn extract_tree(target: PathBuf, out: PathBuf, meta: String, options: Options) -> Result<(), BErr> {
+	let is_rom = target.is_file();
+	let manager = IOManager::new(target, out, options, |i| i, |f, m, h| {
+		let path = f.path.clone();
+		if let Err(e) = extract::handle_file(f, m, h) {
+			println!("Couldn't extract {:?}: {}", path, e);
+		}
+	});
 	let mut meta_root = Box::new(FileMeta::Uninitialized);
 	let meta_root_ref = unsafe{MetaRef::new(&mut *meta_root)};
-	handle_extract_dir(manager.get_helper(), &RelPath::new(), meta_root_ref)?;
+	if is_rom {
+		let rom = NDSRom::parse(manager.get_helper().read_file(&RelPath::new())?)?;
+		let data_ref = meta_root_ref.submit(extract::extract_rom_code(manager.get_helper(), &rom)?);
+		let mut path = RelPath::new();
+		path.push("data".into());
+		handle_extract_rom_dir(manager.get_helper(), &rom, rom.get_root(), &path, data_ref)?;
+	} else {
+		handle_extract_dir(manager.get_helper(), &RelPath::new(), meta_root_ref)?;
+	}
 	manager.join();
 	let config = PrettyConfig::new()
 		.with_indentor("\t".into());
 	let serialized = ser::to_string_pretty(&*meta_root, config)?;
 	let mut metafile = File::create(meta)?;
-	write!(metafile, "{}", serialized);
+	write!(metafile, "{}", serialized)?;
 	Ok(())
 }
 
@@ -91,7 +190,7 @@ fn handle_extract_dir(helper: &IOHelper, in_path: &RelPath, meta_ref: MetaRef<Fi
 		if helper.is_dir(&path) {
 			handle_extract_dir(helper, &path, meta_ref)
 		} else {
-			helper.queue_or_write(FileQueueEntry {
+			helper.queue_file(FileQueueEntry {
 				path: path.clone(),
 				content: helper.read_file(&path)?,
 				type_hint: None, compression_hint: None
@@ -101,10 +200,43 @@ fn handle_extract_dir(helper: &IOHelper, in_path: &RelPath, meta_ref: MetaRef<Fi
 	Ok(())
 }
 
+fn handle_extract_rom_dir(helper: &IOHelper, rom: &NDSRom, dir: &NitroDir, path: &RelPath, meta_ref: MetaRef<FileMeta>) -> Result<(), BErr> {
+	let mut meta = DirectoryMeta::create(path.peek());
+	for entry in &dir.entries {
+		match entry {
+			NitroEntry::File(name, _) => meta.add(name.clone()),
+			NitroEntry::Dir(subdir) => meta.add(subdir.name.clone())
+		}
+	}
+	let mut meta_refs = meta_ref.submit(meta);
+	helper.create_dir(path)?;
+	for entry in &dir.entries {
+		match entry {
+			NitroEntry::File(name, id) => {
+				let mut file_path = path.clone();
+				file_path.push(name.clone());
+				helper.queue_file(FileQueueEntry {
+					path: file_path,
+					content: rom.get_file(*id)?,
+					type_hint: None, compression_hint: None
+				}, meta_refs.remove(name).unwrap())?
+			},
+			NitroEntry::Dir(subdir) => {
+				let mut dir_path = path.clone();
+				dir_path.push(subdir.name.clone());
+				handle_extract_rom_dir(helper, rom, subdir, &dir_path, meta_refs.remove(&subdir.name).unwrap())?
+			}
+		}
+	}
+	Ok(())
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FileType {
 	P2,
 	LZ,
+	RLE,
+	Huffman,
 	HPAK,
 	PK2D,
 	PKAC,
@@ -130,7 +262,12 @@ pub enum FileType {
 	NSCR,
 	NFTR,
 	Unknown7,
-	SDAT
+	SDAT,
+	SSEQ,
+	SSAR,
+	SBNK,
+	SWAR,
+	STRM
 }
 
 impl FileType {
@@ -159,11 +296,21 @@ impl FileType {
 			NCER_MAGIC => Self::NCER,
 			NANR_MAGIC => Self::NANR,
 			SDAT_MAGIC => Self::SDAT,
+			SSEQ_MAGIC => Self::SSEQ,
+			SSAR_MAGIC => Self::SSAR,
+			SBNK_MAGIC => Self::SBNK,
+			SWAR_MAGIC => Self::SWAR,
+			STRM_MAGIC => Self::STRM,
 			_ => {
 				if magic_16 == P2_MAGIC as u32 {
 					Self::P2
-				} else if (magic_8 == 0x10 || magic_8 == 0x11) && could_be_compressed {
-					Self::LZ
+				} else if could_be_compressed {
+					match magic_8 {
+						0x10 | 0x11 | 0x40 => Self::LZ,
+						0x30 => Self::RLE,
+						0x24 | 0x28 => Self::Huffman,
+						_ => Self::OtherOrNotGuessable
+					}
 				} else {
 					Self::OtherOrNotGuessable
 				}
@@ -174,8 +321,15 @@ impl FileType {
 	fn get_extension(&self) -> &'static str {
 		match self {
 			Self::SDAT => "sdat",
+			Self::SSEQ => "sseq",
+			Self::SSAR => "ssar",
+			Self::SBNK => "sbnk",
+			Self::SWAR => "swar",
+			Self::STRM => "strm",
 			Self::P2 => "p2",
 			Self::LZ => "lz",
+			Self::RLE => "rle",
+			Self::Huffman => "huff",
 			Self::HPAK => "hpak",
 			Self::PK2D => "pk2d",
 			Self::PKAC => "pkac",
@@ -205,16 +359,14 @@ impl FileType {
 	}
 	
 	fn still_packed(&self) -> bool {
-		match self {
-			Self::P2 | Self::LZ | Self::HPAK | Self::PK2D | Self::PKAC => true,
-			_ => false 
-		}
+		matches!(self, Self::P2 | Self::LZ | Self::RLE | Self::Huffman | Self::HPAK | Self::PK2D | Self::PKAC | Self::SDAT)
 	}
 }
 
 pub struct P2File {
 	named: bool,
-	subfiles: Vec<P2Subfile>
+	subfiles: Vec<P2Subfile>,
+	layout: Option<P2Layout>
 }
 
 struct P2Subfile {
@@ -282,7 +434,7 @@ impl PK2D {
 
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PKAC {
 	files: Vec<(String, Bytes)>
 }
@@ -290,21 +442,12 @@ pub struct PKAC {
 impl P2Subfile {
 	fn get_decompressed(&self) -> Result<Bytes, BErr> {
 		if self.compressed {
-			decompress(&mut self.content.clone().reader()).map(Bytes::from)
+			Ok(Bytes::from(decompress(&self.content)?))
 		} else {
 			Ok(self.content.clone())
 		}
 	}
 	
-	fn decompress(&mut self) -> Result<(), BErr> {
-		if self.compressed {
-			let decompressed = decompress(&mut self.content.clone().reader()).map(Bytes::from).unwrap();
-			self.compressed = false;
-			self.content = decompressed;
-		}
-		Ok(())
-	}
-	
 	fn suggest_name(&self) -> String {
 		if let Some(name) = &self.name {
 			name.clone()
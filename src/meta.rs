@@ -1,9 +1,12 @@
 use std::{
 	collections::HashMap,
+	convert::TryFrom
 };
 use serde::{Serialize, Deserialize};
 use crate::{
-	P2File, PKAC, PK2D, HPAK
+	P2File, PKAC, PK2D, HPAK,
+	sdat::SDAT,
+	util::from_hex
 };
 // Metadata needed to properly re-pack things.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -11,11 +14,15 @@ pub enum FileMeta {
 	Directory(DirectoryMeta),
 	P2(P2Meta),
 	LZ(LZMeta),
+	RLE(RLEMeta),
+	Huffman(HuffmanMeta),
 	NamedP2(NamedP2Meta),
 	OtherFile(String), // unpacked name of the file
 	HPAK(HPAKMeta),
 	PK2D(PK2DMeta),
 	PKAC(PKACMeta),
+	NDS(NDSMeta),
+	SDAT(SDATMeta),
 	EmptyFile,
 	Uninitialized
 }
@@ -66,23 +73,12 @@ impl From<DirectoryMeta> for FileMeta {
 	}
 }
 
-impl DirectoryMeta {
-	pub fn from(other: &[String], unpacked_name: String) -> Self {
-		let mut map = HashMap::new();
-		for f in other {
-			map.insert(f.clone(), FileMeta::Uninitialized);
-		}
-		DirectoryMeta {
-			files: map,
-			unpacked_name
-		}
-	}
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct P2Meta {
 	unpacked_name: String,
-	files: Vec<P2SubfileMeta>
+	files: Vec<P2SubfileMeta>,
+	#[serde(default)]
+	layout: Option<P2Layout>
 }
 
 impl MetaSubmit for P2Meta {
@@ -104,11 +100,14 @@ impl P2Meta {
 		for subfile in &other.subfiles {
 			files.push(P2SubfileMeta {
 				compressed: subfile.compressed,
-				file: FileMeta::Uninitialized
+				lz_type: if subfile.compressed {subfile.content.first().copied().and_then(LZType::from_magic)} else {None},
+				file: FileMeta::Uninitialized,
+				original: None, level: None
 			});
 		}
 		P2Meta {
-			files, unpacked_name
+			files, unpacked_name,
+			layout: other.layout.clone()
 		}
 	}
 	
@@ -116,6 +115,14 @@ impl P2Meta {
 		&self.files
 	}
 	
+	pub fn set_original(&mut self, index: usize, original: OriginalPayload) {
+		self.files[index].original = Some(original);
+	}
+	
+	pub fn get_layout(&self) -> Option<&P2Layout> {
+		self.layout.as_ref()
+	}
+	
 	pub fn get_unpacked_name(&self) -> &str {
 		&self.unpacked_name
 	}
@@ -124,7 +131,13 @@ impl P2Meta {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct P2SubfileMeta {
 	compressed: bool,
-	file: FileMeta
+	#[serde(default)]
+	lz_type: Option<LZType>,
+	file: FileMeta,
+	#[serde(default)]
+	original: Option<OriginalPayload>,
+	#[serde(default)]
+	level: Option<CompressionLevel>
 }
 
 impl P2SubfileMeta {
@@ -132,6 +145,18 @@ impl P2SubfileMeta {
 		self.compressed
 	}
 	
+	pub fn get_original(&self) -> Option<&OriginalPayload> {
+		self.original.as_ref()
+	}
+	
+	pub fn get_lz_type(&self) -> LZType {
+		self.lz_type.unwrap_or(LZType::LZ11) // metadata from before this was recorded, LZ11 is what we always wrote
+	}
+	
+	pub fn get_level(&self) -> Option<CompressionLevel> {
+		self.level
+	}
+	
 	pub fn get_file(&self) -> &FileMeta {
 		&self.file
 	}
@@ -140,7 +165,9 @@ impl P2SubfileMeta {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NamedP2Meta {
 	unpacked_name: String,
-	files: Vec<(String, P2SubfileMeta)>
+	files: Vec<(String, P2SubfileMeta)>,
+	#[serde(default)]
+	layout: Option<P2Layout>
 }
 
 impl MetaSubmit for NamedP2Meta {
@@ -162,14 +189,25 @@ impl NamedP2Meta {
 		for subfile in &other.subfiles {
 			files.push((subfile.name.as_ref().unwrap().into(), P2SubfileMeta {
 				compressed: subfile.compressed,
-				file: FileMeta::Uninitialized
+				lz_type: if subfile.compressed {subfile.content.first().copied().and_then(LZType::from_magic)} else {None},
+				file: FileMeta::Uninitialized,
+				original: None, level: None
 			}));
 		}
 		NamedP2Meta {
-			files, unpacked_name
+			files, unpacked_name,
+			layout: other.layout.clone()
 		}
 	}
 	
+	pub fn set_original(&mut self, index: usize, original: OriginalPayload) {
+		self.files[index].1.original = Some(original);
+	}
+	
+	pub fn get_layout(&self) -> Option<&P2Layout> {
+		self.layout.as_ref()
+	}
+	
 	pub fn get_unpacked_name(&self) -> &str {
 		&self.unpacked_name
 	}
@@ -189,7 +227,9 @@ pub struct HPAKMeta {
 	nsbta_files: Vec<FileMeta>,
 	unknown5_files: Vec<FileMeta>,
 	unknown6_files: Vec<FileMeta>,
-	nsbmd_files: Vec<FileMeta>
+	nsbmd_files: Vec<FileMeta>,
+	#[serde(default)]
+	layout: Option<GroupLayout>
 }
 
 impl MetaSubmit for HPAKMeta {
@@ -226,6 +266,7 @@ impl HPAKMeta {
 			unknown5_files: vec![FileMeta::Uninitialized; other.unknown5.len()],
 			unknown6_files: vec![FileMeta::Uninitialized; other.unknown6.len()],
 			nsbmd_files: vec![FileMeta::Uninitialized; other.nsbmd.len()],
+			layout: None
 		}
 	}
 	
@@ -254,12 +295,20 @@ impl HPAKMeta {
 		&self.nsbmd_files
 	}
 	
+	pub fn set_layout(&mut self, layout: GroupLayout) {
+		self.layout = Some(layout);
+	}
+	
+	pub fn get_layout(&self) -> Option<&GroupLayout> {
+		self.layout.as_ref()
+	}
+	
 	pub fn get_unpacked_name(&self) -> &str {
 		&self.unpacked_name
 	}
 }
 
-unsafe fn get_refs_vec(vec: &mut Vec<FileMeta>) -> Vec<MetaRef<FileMeta>> {
+unsafe fn get_refs_vec(vec: &mut [FileMeta]) -> Vec<MetaRef<FileMeta>> {
 	vec.iter_mut().map(|m| MetaRef::new(m)).collect()
 }
 
@@ -273,7 +322,12 @@ pub struct PK2DMeta {
 	unknown4_files: Vec<FileMeta>,
 	nanr_files: Vec<FileMeta>,
 	nscr_files: Vec<FileMeta>,
-	unknown7_files: Vec<FileMeta>
+	unknown7_files: Vec<FileMeta>,
+	#[serde(default)]
+	layout: Option<GroupLayout>,
+	// content hashes of the `<n>.ncgr.png` images extraction wrote, by NCGR, to tell the ones that were edited since
+	#[serde(default)]
+	ncgr_pngs: Vec<Option<u64>>
 }
 
 impl MetaSubmit for PK2DMeta {
@@ -309,7 +363,9 @@ impl PK2DMeta {
 			unknown4_files: vec![FileMeta::Uninitialized; other.unknown4.len()],
 			nanr_files: vec![FileMeta::Uninitialized; other.nanr.len()],
 			nscr_files: vec![FileMeta::Uninitialized; other.nscr.len()],
-			unknown7_files: vec![FileMeta::Uninitialized; other.unknown7.len()]
+			unknown7_files: vec![FileMeta::Uninitialized; other.unknown7.len()],
+			layout: None,
+			ncgr_pngs: Vec::new()
 		}
 	}
 	
@@ -338,6 +394,22 @@ impl PK2DMeta {
 		&self.unknown7_files
 	}
 	
+	pub fn set_layout(&mut self, layout: GroupLayout) {
+		self.layout = Some(layout);
+	}
+	
+	pub fn get_layout(&self) -> Option<&GroupLayout> {
+		self.layout.as_ref()
+	}
+	
+	pub fn set_ncgr_pngs(&mut self, hashes: Vec<Option<u64>>) {
+		self.ncgr_pngs = hashes;
+	}
+	
+	pub fn get_ncgr_pngs(&self) -> &[Option<u64>] {
+		&self.ncgr_pngs
+	}
+	
 	pub fn get_unpacked_name(&self) -> &str {
 		&self.unpacked_name
 	}
@@ -346,7 +418,9 @@ impl PK2DMeta {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PKACMeta {
 	unpacked_name: String,
-	files: Vec<(String, FileMeta)>
+	files: Vec<(String, FileMeta)>,
+	#[serde(default)]
+	layout: Option<GroupLayout>
 }
 
 impl MetaSubmit for PKACMeta {
@@ -366,7 +440,55 @@ impl PKACMeta {
 	pub fn from(other: &PKAC, unpacked_name: String) -> Self {
 		PKACMeta {
 			unpacked_name,
-			files: other.files.iter().map(|(n, _)| (n.into(), FileMeta::Uninitialized)).collect()
+			files: other.files.iter().map(|(n, _)| (n.into(), FileMeta::Uninitialized)).collect(),
+			layout: None
+		}
+	}
+	
+	pub fn set_layout(&mut self, layout: GroupLayout) {
+		self.layout = Some(layout);
+	}
+	
+	pub fn get_layout(&self) -> Option<&GroupLayout> {
+		self.layout.as_ref()
+	}
+	
+	pub fn get_unpacked_name(&self) -> &str {
+		&self.unpacked_name
+	}
+	
+	pub fn get_files(&self) -> &[(String, FileMeta)] {
+		&self.files
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SDATMeta {
+	unpacked_name: String,
+	files: Vec<(String, FileMeta)>, // by file id
+	headers: OriginalPayload, // everything before the file data, stashed with the originals
+	layout: SDATLayout
+}
+
+impl MetaSubmit for SDATMeta {
+	type MetaRefCollection = Vec<(String, MetaRef<FileMeta>)>;
+	unsafe fn on_submit(&mut self) -> Self::MetaRefCollection {
+		self.files.iter_mut().map(|(n, m)| (n.clone(), MetaRef::new(m))).collect()
+	}
+}
+
+impl From<SDATMeta> for FileMeta {
+	fn from(other: SDATMeta) -> Self {
+		Self::SDAT(other)
+	}
+}
+
+impl SDATMeta {
+	pub fn from(other: &SDAT, unpacked_name: String, headers: OriginalPayload, layout: SDATLayout) -> Self {
+		SDATMeta {
+			unpacked_name,
+			files: other.files.iter().map(|(n, _)| (n.clone(), FileMeta::Uninitialized)).collect(),
+			headers, layout
 		}
 	}
 	
@@ -377,17 +499,59 @@ impl PKACMeta {
 	pub fn get_files(&self) -> &[(String, FileMeta)] {
 		&self.files
 	}
+	
+	pub fn get_headers(&self) -> &OriginalPayload {
+		&self.headers
+	}
+	
+	pub fn get_layout(&self) -> &SDATLayout {
+		&self.layout
+	}
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LZMeta {
 	lz_type: LZType,
 	file: Box<FileMeta>,
+	#[serde(default)]
+	original: Option<OriginalPayload>,
+	#[serde(default)]
+	level: Option<CompressionLevel>
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum LZType {
-	LZ10, LZ11
+	LZ10, LZ11, LZ40
+}
+
+impl LZType {
+	pub fn from_magic(magic: u8) -> Option<Self> {
+		match magic {
+			0x10 => Some(Self::LZ10),
+			0x11 => Some(Self::LZ11),
+			0x40 => Some(Self::LZ40),
+			_ => None
+		}
+	}
+}
+
+// Fast is a greedy parse, Optimal a shortest path over the longest back-reference at each position: usually smaller, but not the smallest possible.
+// Files can set their own level in the metadata, otherwise the one given to pack is used.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompressionLevel {
+	#[default]
+	Fast,
+	Optimal
+}
+
+impl CompressionLevel {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"fast" => Some(Self::Fast),
+			"optimal" => Some(Self::Optimal),
+			_ => None
+		}
+	}
 }
 
 impl MetaSubmit for LZMeta {
@@ -403,10 +567,106 @@ impl From<LZMeta> for FileMeta {
 	}
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RLEMeta {
+	file: Box<FileMeta>,
+	#[serde(default)]
+	original: Option<OriginalPayload>
+}
+
+impl RLEMeta {
+	pub fn new(original: OriginalPayload) -> Self {
+		RLEMeta {
+			file: Box::new(FileMeta::Uninitialized),
+			original: Some(original)
+		}
+	}
+	
+	pub fn get_file(&self) -> &FileMeta {
+		&self.file
+	}
+	
+	pub fn get_original(&self) -> Option<&OriginalPayload> {
+		self.original.as_ref()
+	}
+}
+
+impl MetaSubmit for RLEMeta {
+	type MetaRefCollection = MetaRef<FileMeta>;
+	unsafe fn on_submit(&mut self) -> Self::MetaRefCollection {
+		MetaRef::new(&mut *self.file)
+	}
+}
+
+impl From<RLEMeta> for FileMeta {
+	fn from(other: RLEMeta) -> Self {
+		Self::RLE(other)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HuffmanMeta {
+	huffman_type: HuffmanType,
+	file: Box<FileMeta>,
+	#[serde(default)]
+	original: Option<OriginalPayload>
+}
+
+// how many bits each symbol in the tree stands for
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HuffmanType {
+	Huffman4, Huffman8
+}
+
+impl HuffmanType {
+	pub fn from_magic(magic: u8) -> Option<Self> {
+		match magic {
+			0x24 => Some(Self::Huffman4),
+			0x28 => Some(Self::Huffman8),
+			_ => None
+		}
+	}
+}
+
+impl HuffmanMeta {
+	pub fn new(ty: HuffmanType, original: OriginalPayload) -> Self {
+		HuffmanMeta {
+			huffman_type: ty, file: Box::new(FileMeta::Uninitialized),
+			original: Some(original)
+		}
+	}
+	
+	pub fn get_huffman_type(&self) -> HuffmanType {
+		self.huffman_type
+	}
+	
+	pub fn get_file(&self) -> &FileMeta {
+		&self.file
+	}
+	
+	pub fn get_original(&self) -> Option<&OriginalPayload> {
+		self.original.as_ref()
+	}
+}
+
+impl MetaSubmit for HuffmanMeta {
+	type MetaRefCollection = MetaRef<FileMeta>;
+	unsafe fn on_submit(&mut self) -> Self::MetaRefCollection {
+		MetaRef::new(&mut *self.file)
+	}
+}
+
+impl From<HuffmanMeta> for FileMeta {
+	fn from(other: HuffmanMeta) -> Self {
+		Self::Huffman(other)
+	}
+}
+
 impl LZMeta {
-	pub fn new(ty: LZType) -> Self {
+	pub fn new(ty: LZType, original: OriginalPayload) -> Self {
 		LZMeta {
-			lz_type: ty, file: Box::new(FileMeta::Uninitialized)
+			lz_type: ty, file: Box::new(FileMeta::Uninitialized),
+			original: Some(original), level: None
 		}
 	}
 	
@@ -414,9 +674,169 @@ impl LZMeta {
 		self.lz_type
 	}
 	
+	pub fn get_level(&self) -> Option<CompressionLevel> {
+		self.level
+	}
+	
 	pub fn get_file(&self) -> &FileMeta {
 		&self.file
 	}
+	
+	pub fn get_original(&self) -> Option<&OriginalPayload> {
+		self.original.as_ref()
+	}
+}
+
+// Where the compressed form of a file was stashed during extraction, so it can be written back as-is if the file
+// is still the same when packing. The payload lives under ORIGINALS_DIR in the unpacked directory.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OriginalPayload {
+	pub content_hash: u64,
+	pub payload: String
+}
+
+pub const ORIGINALS_DIR: &str = ".orig";
+
+// Bytes of a container the packer doesn't generate by itself (padding, flags it doesn't know about...).
+// Worked out at extraction time by diffing the container against what the packer makes of it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(try_from = "UncheckedPatches")]
+pub struct Patches {
+	pub len: u32,
+	pub patches: Vec<(u32, String)> // offset, hex bytes
+}
+
+// Patches as the metadata file has them, checked before anything gets applied
+#[derive(Deserialize)]
+struct UncheckedPatches {
+	len: u32,
+	patches: Vec<(u32, String)>
+}
+
+impl TryFrom<UncheckedPatches> for Patches {
+	type Error = String;
+
+	fn try_from(unchecked: UncheckedPatches) -> Result<Self, String> {
+		for (offset, bytes) in &unchecked.patches {
+			let bytes = from_hex(bytes).map_err(|e| format!("patch at {:#X}: {}", offset, e))?;
+			if *offset as usize + bytes.len() > unchecked.len as usize {
+				return Err(format!("patch at {:#X} is past the end of the file", offset))
+			}
+		}
+		Ok(Patches {len: unchecked.len, patches: unchecked.patches})
+	}
+}
+
+// Where everything was in the original P2, reused if every subfile still has the same length.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct P2Layout {
+	pub header_size: u32,
+	pub offsets: Vec<u32>,
+	pub lengths: Vec<u32>,
+	pub patches: Patches
+}
+
+// Same idea as P2Layout, for HPAK/PK2D/PKAC.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupLayout {
+	pub info_offsets: [u32; 8],
+	pub offsets: Vec<Vec<u32>>,
+	pub lengths: Vec<Vec<u32>>,
+	pub patches: Patches
+}
+
+// Where the files of an SDAT were, reused if none of them changed size.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SDATLayout {
+	pub offsets: Vec<u32>,
+	pub lengths: Vec<u32>,
+	pub patches: Patches
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NDSMeta {
+	data: Box<FileMeta>, // the NitroFS root, always a directory
+	#[serde(default)]
+	arm9: Option<CodeMeta>,
+	#[serde(default)]
+	overlays9: Vec<CodeMeta>, // in overlay table order
+	#[serde(default)]
+	overlays7: Vec<CodeMeta>
+}
+
+// arm9 or an overlay, unpacked outside the filesystem
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CodeMeta {
+	unpacked_name: String,
+	compressed: bool, // BLZ
+	#[serde(default)]
+	original: Option<OriginalPayload>,
+	#[serde(default)]
+	level: Option<CompressionLevel>
+}
+
+impl CodeMeta {
+	pub fn new(unpacked_name: String, original: Option<OriginalPayload>) -> Self {
+		CodeMeta {
+			unpacked_name,
+			compressed: original.is_some(),
+			original, level: None
+		}
+	}
+	
+	pub fn get_unpacked_name(&self) -> &str {
+		&self.unpacked_name
+	}
+	
+	pub fn is_compressed(&self) -> bool {
+		self.compressed
+	}
+	
+	pub fn get_original(&self) -> Option<&OriginalPayload> {
+		self.original.as_ref()
+	}
+	
+	pub fn get_level(&self) -> Option<CompressionLevel> {
+		self.level
+	}
+}
+
+impl MetaSubmit for NDSMeta {
+	type MetaRefCollection = MetaRef<FileMeta>;
+	unsafe fn on_submit(&mut self) -> Self::MetaRefCollection {
+		MetaRef::new(&mut *self.data)
+	}
+}
+
+impl From<NDSMeta> for FileMeta {
+	fn from(other: NDSMeta) -> Self {
+		Self::NDS(other)
+	}
+}
+
+impl NDSMeta {
+	pub fn new(arm9: Option<CodeMeta>, overlays9: Vec<CodeMeta>, overlays7: Vec<CodeMeta>) -> Self {
+		NDSMeta {
+			data: Box::new(FileMeta::Uninitialized),
+			arm9, overlays9, overlays7
+		}
+	}
+	
+	pub fn get_data(&self) -> &FileMeta {
+		&self.data
+	}
+	
+	pub fn get_arm9(&self) -> Option<&CodeMeta> {
+		self.arm9.as_ref()
+	}
+	
+	pub fn get_overlays9(&self) -> &[CodeMeta] {
+		&self.overlays9
+	}
+	
+	pub fn get_overlays7(&self) -> &[CodeMeta] {
+		&self.overlays7
+	}
 }
 
 // this isn't thread safe but rustc won't put Send or Sync on native pointers. this matters so we can't send them out of a handler.
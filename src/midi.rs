@@ -0,0 +1,403 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	nitro::NitroFile,
+	util::Reader
+};
+use bytes::BufMut;
+use std::collections::HashMap;
+
+// Sequences (SSEQ, and the ones in SSARs) converted to Standard MIDI Files, one MIDI track per sequence track.
+// Loops become "loopStart" and "loopEnd" markers and are only played once. Only for listening and documentation,
+// nothing is converted back.
+
+const TICKS_PER_BEAT: u16 = 48; // what sequences count in
+const MAX_EVENTS: usize = 100_000; // per track, in case a sequence never ends
+
+// What each command is followed by, besides the prefixes (0xA0-0xA2) which read the next command
+#[derive(Clone, Copy, PartialEq)]
+enum Arg {
+	U8,
+	U16,
+	S16,
+	U24,
+	VarLen
+}
+
+fn args_of(command: u8) -> &'static [Arg] {
+	match command {
+		0x00..=0x7F => &[Arg::U8, Arg::VarLen], // velocity, duration
+		0x80 | 0x81 => &[Arg::VarLen], // rest, program
+		0x93 => &[Arg::U8, Arg::U24], // open track
+		0x94 | 0x95 => &[Arg::U24], // jump, call
+		0xB0..=0xBD => &[Arg::U8, Arg::S16], // variable operations
+		0xC0..=0xD6 => &[Arg::U8],
+		0xE0 | 0xE1 | 0xFE => &[Arg::U16],
+		0xE3 => &[Arg::S16],
+		_ => &[]
+	}
+}
+
+fn read_arg(r: &mut Reader, arg: Arg) -> Result<i32, BErr> {
+	Ok(match arg {
+		Arg::U8 => r.u8()? as i32,
+		Arg::U16 => r.u16()? as i32,
+		Arg::S16 => r.u16()? as i16 as i32,
+		Arg::U24 => r.u16()? as i32 | (r.u8()? as i32) << 16,
+		Arg::VarLen => {
+			let mut value = 0;
+			loop {
+				let b = r.u8()?;
+				value = (value << 7) | (b & 0x7F) as i32;
+				if b & 0x80 == 0 {
+					break value
+				}
+			}
+		}
+	})
+}
+
+// Everything a track does while playing, as MIDI events
+struct Track {
+	channel: u8,
+	events: Vec<(u32, u8, Vec<u8>)> // tick, order within the tick, event
+}
+
+impl Track {
+	fn event(&mut self, tick: u32, event: Vec<u8>) {
+		let order = match event[0] {
+			0x80..=0x8F => 0,
+			0xFF => 1, // so loop markers come before the notes at the same time
+			_ => 2
+		};
+		self.events.push((tick, order, event));
+	}
+
+	fn control(&mut self, tick: u32, controller: u8, value: i32) {
+		let event = vec![0xB0 | self.channel, controller, value.clamp(0, 127) as u8];
+		self.event(tick, event);
+	}
+
+	fn meta(&mut self, tick: u32, ty: u8, data: &[u8]) {
+		let mut event = vec![0xFF, ty];
+		put_var_len(&mut event, data.len() as u32);
+		event.extend_from_slice(data);
+		self.event(tick, event);
+	}
+}
+
+// Plays a track from `start` and records what it does. Tracks it opens are added to `opened`.
+fn play_track(data: &[u8], start: usize, channel: u8, vars: &mut [i32; 256], opened: &mut Vec<(u8, usize)>) -> Result<Track, BErr> {
+	let mut track = Track {channel, events: Vec::new()};
+	track.meta(0, 0x03, format!("Track {}", channel).as_bytes());
+	let mut r = Reader::new(data, start);
+	let mut tick = 0;
+	let mut visited = HashMap::new(); // where the track was at each tick, to find where loops go back to
+	let mut calls = Vec::new();
+	let mut loops: Vec<(usize, u32, i32)> = Vec::new(); // where the loop starts, when it started, how many times left
+	let mut condition = true;
+	let (mut transpose, mut note_wait, mut tie) = (0, true, false);
+	let mut held: Option<u8> = None; // the note still playing in tie mode
+	for _ in 0..MAX_EVENTS {
+		visited.entry(r.pos).or_insert(tick);
+		let mut command = r.u8()?;
+		// prefixes replace the last argument with a random number or a variable, or skip the command
+		let (mut random, mut variable, mut conditional) = (false, false, false);
+		loop {
+			match command {
+				0xA0 => random = true,
+				0xA1 => variable = true,
+				0xA2 => conditional = true,
+				_ => break
+			}
+			command = r.u8()?;
+		}
+		let types = args_of(command);
+		let mut args = Vec::with_capacity(types.len());
+		for (i, arg) in types.iter().enumerate() {
+			args.push(if i + 1 == types.len() && random {
+				let (min, max) = (r.u16()? as i16 as i32, r.u16()? as i16 as i32);
+				(min + max) / 2
+			} else if i + 1 == types.len() && variable {
+				vars[r.u8()? as usize]
+			} else {
+				read_arg(&mut r, *arg)?
+			});
+		}
+		if conditional && !condition {
+			continue;
+		}
+		match command {
+			0x00..=0x7F => {
+				let key = (command as i32 + transpose).clamp(0, 127) as u8;
+				let (velocity, duration) = (args[0].clamp(0, 127) as u8, args[1].max(0) as u32);
+				if let Some(held) = held.take() {
+					track.event(tick, vec![0x80 | channel, held, 0]);
+				}
+				track.event(tick, vec![0x90 | channel, key, velocity.max(1)]);
+				if tie {
+					held = Some(key);
+				} else {
+					track.event(tick.saturating_add(duration.max(1)), vec![0x80 | channel, key, 0]);
+				}
+				if note_wait {
+					tick = tick.saturating_add(duration);
+				}
+			}
+			0x80 => tick = tick.saturating_add(args[0].max(0) as u32),
+			0x81 => {
+				if args[0] >> 7 != 0 {
+					track.control(tick, 0, args[0] >> 7); // bank
+				}
+				track.event(tick, vec![0xC0 | channel, (args[0] & 0x7F) as u8]);
+			}
+			0x93 => opened.push((args[0] as u8 & 0xF, args[1] as usize)),
+			0x94 => {
+				let target = args[0] as usize;
+				if let Some(&loop_tick) = visited.get(&target) {
+					// jumping back to somewhere already played is how songs loop forever
+					track.meta(loop_tick, 0x06, b"loopStart");
+					track.meta(tick, 0x06, b"loopEnd");
+					break;
+				}
+				r.pos = target;
+			}
+			0x95 => {
+				calls.push(r.pos);
+				r.pos = args[0] as usize;
+			}
+			0xFD => match calls.pop() {
+				Some(pos) => r.pos = pos,
+				None => break
+			},
+			0xB0..=0xBD => {
+				let (var, value) = (&mut vars[args[0] as usize], args[1]);
+				match command {
+					0xB0 => *var = value,
+					0xB1 => *var = var.wrapping_add(value),
+					0xB2 => *var = var.wrapping_sub(value),
+					0xB3 => *var = var.wrapping_mul(value),
+					0xB4 => *var = if value != 0 {var.wrapping_div(value)} else {*var},
+					0xB5 => *var = if value >= 0 {*var << value.min(31)} else {*var >> value.unsigned_abs().min(31)},
+					0xB6 => *var = value / 2, // random, this takes the middle of the range
+					0xB8 => condition = *var == value,
+					0xB9 => condition = *var >= value,
+					0xBA => condition = *var > value,
+					0xBB => condition = *var <= value,
+					0xBC => condition = *var < value,
+					0xBD => condition = *var != value,
+					_ => ()
+				}
+			}
+			0xC0 => track.control(tick, 10, args[0]), // pan
+			0xC1 => track.control(tick, 7, args[0]), // volume
+			0xC2 => {
+				// master volume, as the universal SysEx for it
+				let mut event = vec![0xF0];
+				put_var_len(&mut event, 7);
+				event.extend_from_slice(&[0x7F, 0x7F, 0x04, 0x01, 0x00, args[0].clamp(0, 127) as u8, 0xF7]);
+				track.event(tick, event);
+			}
+			0xC3 => transpose = args[0] as u8 as i8 as i32,
+			0xC4 => {
+				let bend = (8192 + (args[0] as u8 as i8 as i32) * 64).clamp(0, 16383);
+				track.event(tick, vec![0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+			}
+			0xC5 => {
+				// pitch bend range is RPN 0
+				track.control(tick, 101, 0);
+				track.control(tick, 100, 0);
+				track.control(tick, 6, args[0]);
+				track.control(tick, 38, 0);
+			}
+			0xC7 => note_wait = args[0] != 0,
+			0xC8 => {
+				tie = args[0] != 0;
+				if let Some(held) = held.take_if(|_| !tie) {
+					track.event(tick, vec![0x80 | channel, held, 0]);
+				}
+			}
+			0xC9 => track.control(tick, 84, args[0].saturating_add(transpose).clamp(0, 127)), // portamento from this key
+			0xCA => track.control(tick, 1, args[0]), // modulation depth
+			0xCB => track.control(tick, 76, args[0]), // modulation speed, as vibrato rate
+			0xCE => track.control(tick, 65, if args[0] != 0 {127} else {0}), // portamento
+			0xCF => track.control(tick, 5, args[0]), // portamento time
+			0xD0 => track.control(tick, 73, args[0]), // attack
+			0xD1 => track.control(tick, 75, args[0]), // decay
+			0xD2 => track.control(tick, 70, args[0]), // sustain
+			0xD3 => track.control(tick, 72, args[0]), // release
+			0xD4 => loops.push((r.pos, tick, args[0])),
+			0xD5 => track.control(tick, 11, args[0]), // expression
+			0xE1 => {
+				let tempo = 60_000_000 / args[0].max(1) as u32;
+				track.meta(tick, 0x51, &tempo.to_be_bytes()[1..]);
+			}
+			0xFC => match loops.last_mut() {
+				Some((_, loop_tick, 0)) => {
+					// a loop that never ends
+					let loop_tick = *loop_tick;
+					track.meta(loop_tick, 0x06, b"loopStart");
+					track.meta(tick, 0x06, b"loopEnd");
+					break;
+				}
+				Some((pos, _, count)) => {
+					*count -= 1;
+					if *count > 0 {
+						r.pos = *pos;
+					} else {
+						loops.pop();
+					}
+				}
+				None => ()
+			},
+			0xFF => break,
+			_ => () // priority, modulation type, range and delay, sweep pitch, allocating tracks...
+		}
+	}
+	if let Some(held) = held {
+		track.event(tick, vec![0x80 | channel, held, 0]);
+	}
+	Ok(track)
+}
+
+// Plays the sequence starting at `start` in `data`, with the tracks it opens
+fn convert_sequence(data: &[u8], start: usize) -> Result<Vec<u8>, BErr> {
+	let mut vars = [0; 256];
+	let mut opened = Vec::new();
+	let mut tracks = vec![play_track(data, start, 0, &mut vars, &mut opened)?];
+	for (channel, offset) in std::mem::take(&mut opened) {
+		tracks.push(play_track(data, offset, channel, &mut vars, &mut Vec::new())?);
+	}
+	Ok(write_smf(tracks))
+}
+
+fn put_var_len(buf: &mut Vec<u8>, value: u32) {
+	let mut bytes = vec![(value & 0x7F) as u8];
+	let mut value = value >> 7;
+	while value != 0 {
+		bytes.push((value & 0x7F) as u8 | 0x80);
+		value >>= 7;
+	}
+	buf.extend(bytes.iter().rev());
+}
+
+fn write_smf(tracks: Vec<Track>) -> Vec<u8> {
+	let mut buf = Vec::new();
+	buf.extend_from_slice(b"MThd");
+	buf.put_u32(6);
+	buf.put_u16(1); // one song, tracks played together
+	buf.put_u16(tracks.len() as u16);
+	buf.put_u16(TICKS_PER_BEAT);
+	for mut track in tracks {
+		track.events.sort_by_key(|(tick, order, _)| (*tick, *order));
+		let mut data = Vec::new();
+		let mut last = 0;
+		for (tick, _, event) in &track.events {
+			put_var_len(&mut data, tick - last);
+			data.extend_from_slice(event);
+			last = *tick;
+		}
+		data.extend_from_slice(&[0, 0xFF, 0x2F, 0]); // end of track
+		buf.extend_from_slice(b"MTrk");
+		buf.put_u32(data.len() as u32);
+		buf.extend_from_slice(&data);
+	}
+	buf
+}
+
+// The sequence data of an SSEQ or SSAR and where their sequences start in it
+fn read_sequences(buf: &[u8]) -> Result<(&[u8], Vec<Option<usize>>), BErr> {
+	let file = NitroFile::parse(buf)?;
+	let section = file.get_section_offset(DATA_MAGIC).ok_or("no DATA section")?;
+	let mut r = Reader::new(buf, section);
+	let data = buf.get(r.u32()? as usize..).ok_or("sequence data is past the end of the file")?;
+	if file.magic == SSEQ_MAGIC {
+		return Ok((data, vec![Some(0)]))
+	}
+	let count = r.u32()? as usize;
+	let starts = (0..count).map(|_| {
+		let offset = r.u32()?;
+		r.pos += 8; // bank, volume, priorities and player
+		Ok(if offset == 0xFFFFFFFF {None} else {Some(offset as usize)})
+	}).collect::<Result<_, BErr>>()?;
+	Ok((data, starts))
+}
+
+// Writes `<file>.mid` next to an SSEQ, and `<file>.<n>.mid` for each sequence of an SSAR
+pub fn write_midi(helper: &IOHelper, path: &RelPath, buf: &[u8]) {
+	let mut dir = path.clone();
+	let name = dir.pop().unwrap_or_default();
+	let result = read_sequences(buf).and_then(|(data, starts)| {
+		let single = starts.len() == 1 && buf.get(..4) == Some(b"SSEQ");
+		for (i, start) in starts.iter().enumerate() {
+			if let Some(start) = start {
+				let mut midi_path = dir.clone();
+				midi_path.push(if single {format!("{}.mid", name)} else {format!("{}.{}.mid", name, i)});
+				helper.write_file(&midi_path, &convert_sequence(data, *start)?)?;
+			}
+		}
+		Ok(())
+	});
+	if let Err(e) = result {
+		println!("Couldn't convert {:?} to MIDI: {}", path, e);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// an SSEQ whose DATA section holds `commands`
+	fn sseq(commands: &[u8]) -> Vec<u8> {
+		let size = 0x1C + commands.len();
+		let mut buf = b"SSEQ".to_vec();
+		buf.extend_from_slice(&[0xFF, 0xFE, 0x00, 0x01]);
+		buf.extend_from_slice(&(size as u32).to_le_bytes());
+		buf.extend_from_slice(&[0x10, 0, 1, 0]);
+		buf.extend_from_slice(b"DATA");
+		buf.extend_from_slice(&(size as u32 - 0x10).to_le_bytes());
+		buf.extend_from_slice(&0x1Cu32.to_le_bytes()); // where the commands start
+		buf.extend_from_slice(commands);
+		buf
+	}
+
+	fn convert(sseq: &[u8]) -> Vec<u8> {
+		let (data, starts) = read_sequences(sseq).unwrap();
+		assert_eq!(starts, [Some(0)]);
+		convert_sequence(data, 0).unwrap()
+	}
+
+	#[test]
+	fn sequences_become_midi() {
+		let midi = convert(&sseq(&[
+			0xC1, 100, // volume
+			0x3C, 100, 0x30, // a note for a beat
+			0x80, 0x18, // half a beat of rest
+			0x40, 80, 0x0C, // a quarter beat note
+			0xFF
+		]));
+		let mut expected = b"MThd".to_vec();
+		expected.extend_from_slice(&[0, 0, 0, 6, 0, 1, 0, 1, 0, 48]);
+		let track = [
+			&[0x00, 0xFF, 0x03, 7][..], b"Track 0",
+			&[0x00, 0xB0, 7, 100],
+			&[0x00, 0x90, 0x3C, 100],
+			&[0x30, 0x80, 0x3C, 0],
+			&[0x18, 0x90, 0x40, 80],
+			&[0x0C, 0x80, 0x40, 0],
+			&[0x00, 0xFF, 0x2F, 0]
+		].concat();
+		expected.extend_from_slice(b"MTrk");
+		expected.extend_from_slice(&(track.len() as u32).to_be_bytes());
+		expected.extend_from_slice(&track);
+		assert_eq!(midi, expected);
+	}
+
+	#[test]
+	fn huge_durations_dont_overflow() {
+		// three notes of the longest duration a variable length number holds
+		let note = [0x3C, 100, 0x87, 0xFF, 0xFF, 0xFF, 0x7F];
+		convert(&sseq(&[&note[..], &note, &note, &[0xFF]].concat()));
+	}
+}
@@ -0,0 +1,530 @@
+use crate::{
+	BErr,
+	magic::*,
+	nitro::{NitroFile, read_dict},
+	util::Reader
+};
+
+// 4x4, indexed [row][column], for column vectors
+pub type Matrix = [[f32; 4]; 4];
+
+pub type Rotation = [[f32; 3]; 3];
+
+// translation, rotation, scale
+pub type Transform = ([f32; 3], Rotation, [f32; 3]);
+
+pub const IDENTITY: Matrix = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+pub fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+	let mut out = [[0.0; 4]; 4];
+	for (r, row) in out.iter_mut().enumerate() {
+		for (c, v) in row.iter_mut().enumerate() {
+			*v = (0..4).map(|i| a[r][i] * b[i][c]).sum();
+		}
+	}
+	out
+}
+
+pub fn transform_point(m: &Matrix, p: [f32; 3]) -> [f32; 3] {
+	[0, 1, 2].map(|r| m[r][0] * p[0] + m[r][1] * p[1] + m[r][2] * p[2] + m[r][3])
+}
+
+fn transform_direction(m: &Matrix, d: [f32; 3]) -> [f32; 3] {
+	let v = [0, 1, 2].map(|r| m[r][0] * d[0] + m[r][1] * d[1] + m[r][2] * d[2]);
+	let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+	if len > 0.0 {v.map(|x| x / len)} else {v}
+}
+
+fn scale_matrix(s: [f32; 3]) -> Matrix {
+	[[s[0], 0.0, 0.0, 0.0], [0.0, s[1], 0.0, 0.0], [0.0, 0.0, s[2], 0.0], [0.0, 0.0, 0.0, 1.0]]
+}
+
+fn translation_matrix(t: [f32; 3]) -> Matrix {
+	[[1.0, 0.0, 0.0, t[0]], [0.0, 1.0, 0.0, t[1]], [0.0, 0.0, 1.0, t[2]], [0.0, 0.0, 0.0, 1.0]]
+}
+
+// for affine matrices only, which is all these files have
+pub fn inverse(m: &Matrix) -> Matrix {
+	let a = |r: usize, c: usize| m[r][c];
+	let det = a(0, 0) * (a(1, 1) * a(2, 2) - a(1, 2) * a(2, 1)) - a(0, 1) * (a(1, 0) * a(2, 2) - a(1, 2) * a(2, 0))
+		+ a(0, 2) * (a(1, 0) * a(2, 1) - a(1, 1) * a(2, 0));
+	if det == 0.0 {
+		return IDENTITY
+	}
+	let mut out = IDENTITY;
+	for (r, row) in out.iter_mut().take(3).enumerate() {
+		for (c, v) in row.iter_mut().take(3).enumerate() {
+			// cofactor of the transposed element
+			let (r1, r2) = ((c + 1) % 3, (c + 2) % 3);
+			let (c1, c2) = ((r + 1) % 3, (r + 2) % 3);
+			*v = (a(r1, c1) * a(r2, c2) - a(r1, c2) * a(r2, c1)) / det;
+		}
+		row[3] = -(0..3).map(|c| row[c] * m[c][3]).sum::<f32>();
+	}
+	out
+}
+
+pub fn trs_matrix(translation: [f32; 3], rotation: &Rotation, scale: [f32; 3]) -> Matrix {
+	let mut r = IDENTITY;
+	for (row, values) in rotation.iter().enumerate() {
+		r[row][..3].copy_from_slice(values);
+	}
+	mul(&mul(&translation_matrix(translation), &r), &scale_matrix(scale))
+}
+
+// a bone, called an object in the file
+pub struct Bone {
+	pub name: String,
+	pub translation: [f32; 3],
+	pub rotation: Rotation,
+	pub scale: [f32; 3],
+	pub parent: Option<usize>
+}
+
+impl Bone {
+	fn parse(buf: &[u8], at: usize, name: String) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, at);
+		let flags = r.u16()?;
+		let m0 = r.fx16()?;
+		let (translation, rotation, scale) = read_trs(&mut r, flags, m0)?;
+		Ok(Bone {name, translation, rotation, scale, parent: None})
+	}
+
+	pub fn matrix(&self) -> Matrix {
+		trs_matrix(self.translation, &self.rotation, self.scale)
+	}
+}
+
+// where each bone ends up in the rest pose, following its parents
+pub fn rest_matrices(bones: &[Bone]) -> Vec<Matrix> {
+	let mut world: Vec<Option<Matrix>> = vec![None; bones.len()];
+	fn resolve(bones: &[Bone], world: &mut Vec<Option<Matrix>>, i: usize, depth: usize) -> Matrix {
+		if let Some(m) = world[i] {
+			return m
+		}
+		let parent = match bones[i].parent {
+			Some(p) if p < bones.len() && depth < bones.len() => resolve(bones, world, p, depth + 1),
+			_ => IDENTITY
+		};
+		let m = mul(&parent, &bones[i].matrix());
+		world[i] = Some(m);
+		m
+	}
+	(0..bones.len()).map(|i| resolve(bones, &mut world, i, 0)).collect()
+}
+
+// Transforms stored as flags saying which parts are left out, with the rotation either as a full 3x3 matrix
+// or packed into two numbers and a choice of one of nine pivot layouts.
+pub fn read_trs(r: &mut Reader, flags: u16, m0: f32) -> Result<Transform, BErr> {
+	let translation = if flags & 1 == 0 {[r.fx32()?, r.fx32()?, r.fx32()?]} else {[0.0; 3]};
+	let rotation = if flags & 2 != 0 {
+		[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+	} else if flags & 8 != 0 {
+		let (a, b) = (r.fx16()?, r.fx16()?);
+		pivot_matrix((flags >> 4) & 0xF, (flags >> 8) & 0xF, a, b)
+	} else {
+		let mut m = [m0; 9];
+		for v in m.iter_mut().skip(1) {
+			*v = r.fx16()?;
+		}
+		// stored for row vectors
+		[[m[0], m[3], m[6]], [m[1], m[4], m[7]], [m[2], m[5], m[8]]]
+	};
+	let scale = if flags & 4 == 0 {[r.fx32()?, r.fx32()?, r.fx32()?]} else {[1.0; 3]};
+	Ok((translation, rotation, scale))
+}
+
+pub fn pivot_matrix(select: u16, neg: u16, a: f32, b: f32) -> Rotation {
+	let o = if neg & 1 == 0 {1.0} else {-1.0};
+	let c = if neg & 2 == 0 {b} else {-b};
+	let d = if neg & 4 == 0 {a} else {-a};
+	match select {
+		0 => [[o, 0.0, 0.0], [0.0, a, c], [0.0, b, d]],
+		1 => [[0.0, o, 0.0], [a, 0.0, c], [b, 0.0, d]],
+		2 => [[0.0, 0.0, o], [a, c, 0.0], [b, d, 0.0]],
+		3 => [[0.0, a, c], [o, 0.0, 0.0], [0.0, b, d]],
+		4 => [[a, 0.0, c], [0.0, o, 0.0], [b, 0.0, d]],
+		5 => [[a, c, 0.0], [0.0, 0.0, o], [b, d, 0.0]],
+		6 => [[0.0, a, c], [0.0, b, d], [o, 0.0, 0.0]],
+		7 => [[a, 0.0, c], [b, 0.0, d], [0.0, o, 0.0]],
+		_ => [[a, c, 0.0], [b, d, 0.0], [0.0, 0.0, o]]
+	}
+}
+
+pub struct Material {
+	pub name: String,
+	pub diffuse: [f32; 3],
+	pub alpha: f32,
+	pub double_sided: bool,
+	pub repeat: [bool; 2],
+	pub mirror: [bool; 2],
+	pub texture: Option<String>,
+	pub palette: Option<String>
+}
+
+// which bones a vertex follows, and how much
+pub type Binding = Vec<(usize, f32)>;
+
+#[derive(Clone)]
+pub struct Vertex {
+	pub position: [f32; 3],
+	pub normal: [f32; 3],
+	pub uv: [f32; 2], // in texels
+	pub color: [f32; 3],
+	pub binding: Binding
+}
+
+pub struct Primitive {
+	pub material: Option<usize>,
+	pub vertices: Vec<Vertex>,
+	pub indices: Vec<u32>,
+	pub has_normals: bool,
+	pub has_colors: bool
+}
+
+// A model in its rest pose, with every vertex already moved to where its bones put it
+pub struct Model {
+	pub name: String,
+	pub bones: Vec<Bone>,
+	pub materials: Vec<Material>,
+	pub primitives: Vec<Primitive>
+}
+
+pub fn parse_nsbmd(buf: &[u8]) -> Result<Vec<Model>, BErr> {
+	let file = NitroFile::parse_g3d(buf)?;
+	if file.magic != NSBMD_MAGIC {
+		return Err("not an NSBMD".into())
+	}
+	let start = file.get_section_offset(MDL0_MAGIC).ok_or("NSBMD has no model section")? - 8;
+	read_dict(buf, start + 8)?.into_iter().map(|(name, entry)| {
+		let offset = Reader::new(entry, 0).u32()? as usize;
+		parse_model(buf, start + offset, name)
+	}).collect()
+}
+
+fn parse_model(buf: &[u8], at: usize, name: String) -> Result<Model, BErr> {
+	let mut r = Reader::new(buf, at + 4);
+	let commands = at + r.u32()? as usize;
+	let materials_at = at + r.u32()? as usize;
+	let meshes_at = at + r.u32()? as usize;
+	let inverse_binds_at = at + r.u32()? as usize;
+	r.pos += 3;
+	let bone_count = r.u8()? as usize;
+	r.pos += 4; // material and mesh counts, unknown
+	let up_scale = r.fx32()?;
+
+	let bones_at = at + 0x40;
+	let mut bones = read_dict(buf, bones_at)?.into_iter()
+		.map(|(name, entry)| Bone::parse(buf, bones_at + Reader::new(entry, 0).u32()? as usize, name))
+		.collect::<Result<Vec<_>, _>>()?;
+	let materials = read_materials(buf, materials_at)?;
+	let meshes = read_dict(buf, meshes_at)?.into_iter().map(|(_, entry)| {
+		let mesh = meshes_at + Reader::new(entry, 0).u32()? as usize;
+		let mut r = Reader::new(buf, mesh + 8);
+		let offset = r.u32()? as usize;
+		let len = r.u32()? as usize;
+		buf.get(mesh + offset..mesh + offset + len).ok_or_else(|| "display list out of range".into())
+	}).collect::<Result<Vec<&[u8]>, BErr>>()?;
+	let mut inverse_binds = Vec::with_capacity(bone_count);
+	for i in 0..bone_count {
+		// a 4x3 matrix for positions, then a 3x3 one for normals that isn't needed
+		let mut r = Reader::new(buf, inverse_binds_at + i * 0x54);
+		let mut m = IDENTITY;
+		for column in 0..4 {
+			for row in m.iter_mut().take(3) {
+				row[column] = r.fx32()?;
+			}
+		}
+		inverse_binds.push(m);
+	}
+
+	let mut state = RenderState {
+		bones: &mut bones,
+		inverse_binds: &inverse_binds,
+		meshes: &meshes,
+		up_scale,
+		stack: vec![None; 32],
+		current: (IDENTITY, Vec::new()),
+		material: None,
+		primitives: Vec::new()
+	};
+	state.run(buf, commands)?;
+	let primitives = state.primitives;
+	Ok(Model {name, bones, materials, primitives})
+}
+
+fn read_materials(buf: &[u8], at: usize) -> Result<Vec<Material>, BErr> {
+	let mut r = Reader::new(buf, at);
+	let texture_pairs = at + r.u16()? as usize;
+	let palette_pairs = at + r.u16()? as usize;
+	let mut materials = read_dict(buf, at + 4)?.into_iter().map(|(name, entry)| {
+		let mut r = Reader::new(buf, at + Reader::new(entry, 0).u32()? as usize + 4);
+		let dif_amb = r.u32()?;
+		r.pos += 4; // specular and emission
+		let polygon_attr = r.u32()?;
+		r.pos += 4; // polygon attribute mask
+		let teximage_param = r.u32()?;
+		let diffuse = [0, 5, 10].map(|s| ((dif_amb >> s) & 0x1F) as f32 / 31.0);
+		Ok(Material {
+			name, diffuse,
+			alpha: ((polygon_attr >> 16) & 0x1F) as f32 / 31.0,
+			double_sided: polygon_attr & 0xC0 == 0xC0,
+			repeat: [teximage_param & 0x10000 != 0, teximage_param & 0x20000 != 0],
+			mirror: [teximage_param & 0x40000 != 0, teximage_param & 0x80000 != 0],
+			texture: None,
+			palette: None
+		})
+	}).collect::<Result<Vec<_>, BErr>>()?;
+	// textures and palettes each list the materials that use them
+	for (pairs, is_texture) in [(texture_pairs, true), (palette_pairs, false)] {
+		for (name, entry) in read_dict(buf, pairs)? {
+			let mut r = Reader::new(entry, 0);
+			let list = at + r.u16()? as usize;
+			let count = r.u8()? as usize;
+			for id in buf.get(list..list + count).ok_or("material list out of range")? {
+				if let Some(material) = materials.get_mut(*id as usize) {
+					if is_texture {
+						material.texture = Some(name.clone());
+					} else {
+						material.palette = Some(name.clone());
+					}
+				}
+			}
+		}
+	}
+	Ok(materials)
+}
+
+struct RenderState<'a> {
+	bones: &'a mut [Bone],
+	inverse_binds: &'a [Matrix],
+	meshes: &'a [&'a [u8]],
+	up_scale: f32,
+	stack: Vec<Option<(Matrix, Binding)>>,
+	current: (Matrix, Binding),
+	material: Option<usize>,
+	primitives: Vec<Primitive>
+}
+
+impl<'a> RenderState<'a> {
+	// The render commands set up the matrix stack from the bones, pick materials and draw meshes
+	fn run(&mut self, buf: &[u8], at: usize) -> Result<(), BErr> {
+		let mut r = Reader::new(buf, at);
+		loop {
+			let op = r.u8()?;
+			match op {
+				0x00 => (),
+				0x01 => return Ok(()),
+				0x02 => r.pos += 2, // visibility
+				0x03 => {
+					let slot = r.u8()? as usize;
+					self.current = self.stack.get(slot).cloned().flatten().unwrap_or((IDENTITY, Vec::new()));
+				}
+				0x04 | 0x24 | 0x44 => self.material = Some(r.u8()? as usize),
+				0x05 => {
+					let mesh = r.u8()? as usize;
+					let list = *self.meshes.get(mesh).ok_or_else(|| format!("mesh {} doesn't exist", mesh))?;
+					self.draw(list)?;
+				}
+				0x06 | 0x26 | 0x46 | 0x66 => {
+					let bone = r.u8()? as usize;
+					let parent = r.u8()? as usize;
+					r.pos += 1;
+					let store = if op & 0x20 != 0 {Some(r.u8()? as usize)} else {None};
+					if op & 0x40 != 0 {
+						let slot = r.u8()? as usize;
+						self.current = self.stack.get(slot).cloned().flatten().unwrap_or((IDENTITY, Vec::new()));
+					}
+					if let Some(b) = self.bones.get_mut(bone) {
+						if parent != bone {
+							b.parent = Some(parent);
+						}
+						self.current = (mul(&self.current.0, &b.matrix()), vec![(bone, 1.0)]);
+					}
+					if let Some(slot) = store {
+						self.store(slot);
+					}
+				}
+				0x07 | 0x08 => r.pos += 1, // billboards
+				0x47 | 0x48 => r.pos += 2,
+				0x09 => {
+					// a skinned matrix, blending bones by weight
+					let slot = r.u8()? as usize;
+					let count = r.u8()? as usize;
+					let mut matrix = [[0.0; 4]; 4];
+					let mut binding = Vec::new();
+					for _ in 0..count {
+						let source = r.u8()? as usize;
+						let inverse_bind = r.u8()? as usize;
+						let weight = r.u8()? as f32 / 256.0;
+						let (m, _) = self.stack.get(source).cloned().flatten().unwrap_or((IDENTITY, Vec::new()));
+						let m = mul(&m, self.inverse_binds.get(inverse_bind).unwrap_or(&IDENTITY));
+						for (row, values) in matrix.iter_mut().zip(m.iter()) {
+							for (v, x) in row.iter_mut().zip(values) {
+								*v += x * weight;
+							}
+						}
+						binding.push((inverse_bind, weight));
+					}
+					self.current = (matrix, binding);
+					self.store(slot);
+				}
+				0x0B | 0x2B => (), // scaling by the model's scale, done to every vertex instead
+				0x0C | 0x0D => r.pos += 2, // environment and projection mapping
+				_ => return Err(format!("unknown render command {:#04X}", op).into())
+			}
+		}
+	}
+
+	fn store(&mut self, slot: usize) {
+		if slot >= self.stack.len() {
+			self.stack.resize(slot + 1, None);
+		}
+		self.stack[slot] = Some(self.current.clone());
+	}
+
+	// Runs a mesh's geometry commands. Four command bytes are packed in a word, followed by all their parameters.
+	fn draw(&mut self, list: &[u8]) -> Result<(), BErr> {
+		let mut r = Reader::new(list, 0);
+		let mut current = self.current.clone();
+		let mut vertex = Vertex {position: [0.0; 3], normal: [0.0, 0.0, 1.0], uv: [0.0; 2], color: [1.0; 3], binding: Vec::new()};
+		let mut kind = 0;
+		let mut group: Vec<Vertex> = Vec::new();
+		let mut primitive = Primitive {material: self.material, vertices: Vec::new(), indices: Vec::new(), has_normals: false, has_colors: false};
+		while r.pos + 4 <= list.len() {
+			let ops = r.bytes::<4>()?;
+			for op in ops {
+				let params: Vec<u32> = (0..param_count(op)).map(|_| r.u32()).collect::<Result<_, _>>()?;
+				let p = params.first().copied().unwrap_or(0);
+				let mut emit = true;
+				match op {
+					0x14 => current = self.stack.get(p as usize & 0x1F).cloned().flatten().unwrap_or((IDENTITY, Vec::new())),
+					0x15 => current.0 = IDENTITY,
+					0x1B => current.0 = mul(&current.0, &scale_matrix([0, 1, 2].map(|i| params[i] as i32 as f32 / 4096.0))),
+					0x1C => current.0 = mul(&current.0, &translation_matrix([0, 1, 2].map(|i| params[i] as i32 as f32 / 4096.0))),
+					0x20 => {
+						vertex.color = [0, 5, 10].map(|s| ((p >> s) & 0x1F) as f32 / 31.0);
+						primitive.has_colors = true;
+					}
+					0x21 => {
+						vertex.normal = [0, 10, 20].map(|s| (((p >> s) & 0x3FF) << 6) as u16 as i16 as f32 / 32768.0);
+						primitive.has_normals = true;
+					}
+					0x22 => vertex.uv = [p as u16 as i16 as f32 / 16.0, (p >> 16) as u16 as i16 as f32 / 16.0],
+					0x23 => vertex.position = [p as u16, (p >> 16) as u16, params[1] as u16].map(|v| v as i16 as f32 / 4096.0),
+					0x24 => vertex.position = [0, 10, 20].map(|s| (((p >> s) & 0x3FF) << 6) as u16 as i16 as f32 / 4096.0),
+					0x25 => vertex.position = [p as u16 as i16 as f32 / 4096.0, (p >> 16) as u16 as i16 as f32 / 4096.0, vertex.position[2]],
+					0x26 => vertex.position = [p as u16 as i16 as f32 / 4096.0, vertex.position[1], (p >> 16) as u16 as i16 as f32 / 4096.0],
+					0x27 => vertex.position = [vertex.position[0], p as u16 as i16 as f32 / 4096.0, (p >> 16) as u16 as i16 as f32 / 4096.0],
+					0x28 => {
+						let diff = [0, 10, 20].map(|s| ((((p >> s) & 0x3FF) << 6) as u16 as i16 >> 6) as f32 / 4096.0);
+						vertex.position = [0, 1, 2].map(|i| vertex.position[i] + diff[i]);
+					}
+					0x40 => {
+						kind = p & 3;
+						group.clear();
+					}
+					0x41 => {
+						assemble(&mut primitive, kind, &group);
+						group.clear();
+					}
+					_ => (), // the rest don't change the shape
+				}
+				if !(0x23..=0x28).contains(&op) {
+					emit = false;
+				}
+				if emit {
+					let matrix = current.0;
+					group.push(Vertex {
+						position: transform_point(&matrix, vertex.position.map(|v| v * self.up_scale)),
+						normal: transform_direction(&matrix, vertex.normal),
+						binding: current.1.clone(),
+						..vertex.clone()
+					});
+				}
+			}
+		}
+		// lists don't have to end their last group
+		assemble(&mut primitive, kind, &group);
+		if !primitive.indices.is_empty() {
+			self.primitives.push(primitive);
+		}
+		Ok(())
+	}
+}
+
+// Turns a group of vertices into triangles: 0 is separate triangles, 1 separate quads, 2 triangle strips, 3 quad strips
+fn assemble(primitive: &mut Primitive, kind: u32, group: &[Vertex]) {
+	let base = primitive.vertices.len() as u32;
+	let n = group.len() as u32;
+	let triangles: Vec<[u32; 3]> = match kind {
+		0 => (0..n / 3).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect(),
+		1 => (0..n / 4).flat_map(|i| [[i * 4, i * 4 + 1, i * 4 + 2], [i * 4, i * 4 + 2, i * 4 + 3]]).collect(),
+		2 => (0..n.saturating_sub(2)).map(|i| if i % 2 == 0 {[i, i + 1, i + 2]} else {[i + 1, i, i + 2]}).collect(),
+		_ => (0..n.saturating_sub(2) / 2).flat_map(|i| {
+			let q = i * 2;
+			[[q, q + 1, q + 3], [q, q + 3, q + 2]]
+		}).collect()
+	};
+	if triangles.is_empty() {
+		return
+	}
+	primitive.vertices.extend_from_slice(group);
+	primitive.indices.extend(triangles.iter().flatten().map(|i| base + i));
+}
+
+fn param_count(op: u8) -> usize {
+	match op {
+		0x10 | 0x12 | 0x13 | 0x14 => 1,
+		0x16 | 0x18 => 16,
+		0x17 | 0x19 => 12,
+		0x1A => 9,
+		0x1B | 0x1C | 0x70 => 3,
+		0x23 | 0x71 => 2,
+		0x20..=0x2B | 0x30..=0x33 | 0x40 | 0x50 | 0x60 | 0x72 => 1,
+		0x34 => 32,
+		_ => 0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn display_lists_make_triangles() {
+		let ops = |ops: [u8; 4]| u32::from_le_bytes(ops);
+		let list: Vec<u8> = [
+			// a strip in red, at (1, 2) in the texture, starting with a full position
+			ops([0x40, 0x20, 0x22, 0x23]), 2, 0x001F, 0x0020_0010, 0xF000_1000, 0x0800,
+			// then one moved by a small step, and three with two coordinates each
+			ops([0x28, 0x25, 0x26, 0x27]), 0x000F_0040, 0x1000_2000, 0x1000_0000, 0xF800_0800,
+			// a triangle, its first vertex in 10 bit coordinates and moved up by a translation
+			ops([0x41, 0x1C, 0x40, 0x24]), 0, 0, 0x1000, 0, 0x3C01_0040,
+			// the others without it, and no end to the group
+			ops([0x15, 0x24, 0x24, 0x00]), 0, 0x40
+		].iter().flat_map(|w| w.to_le_bytes()).collect();
+		let mut state = RenderState {
+			bones: &mut [],
+			inverse_binds: &[],
+			meshes: &[],
+			up_scale: 1.0,
+			stack: vec![None; 32],
+			current: (IDENTITY, Vec::new()),
+			material: Some(0),
+			primitives: Vec::new()
+		};
+		state.draw(&list).unwrap();
+
+		let primitive = &state.primitives[0];
+		let positions: Vec<[f32; 3]> = primitive.vertices.iter().map(|v| v.position).collect();
+		assert_eq!(positions, [
+			[1.0, -1.0, 0.5], [1.0 + 1.0 / 64.0, -1.0 - 1.0 / 64.0, 0.5], [2.0, 1.0, 0.5], [0.0, 1.0, 1.0], [0.0, 0.5, -0.5],
+			[1.0, 1.0, 0.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]
+		]);
+		assert_eq!(primitive.indices, [0, 1, 2, 2, 1, 3, 2, 3, 4, 5, 6, 7]);
+		assert!(primitive.vertices.iter().all(|v| v.color == [1.0, 0.0, 0.0] && v.uv == [1.0, 2.0]));
+		assert!(primitive.has_colors && !primitive.has_normals);
+		assert_eq!(primitive.material, Some(0));
+	}
+}
@@ -0,0 +1,509 @@
+use crate::{
+	BErr,
+	compression::blz,
+	meta::CompressionLevel
+};
+use bytes::{Bytes, BytesMut, Buf, BufMut};
+use std::{
+	collections::{HashMap, HashSet},
+	str
+};
+
+// offsets of the header fields we care about
+pub const CAPACITY: usize = 0x14;
+pub const ARM9_OFFSET: usize = 0x20;
+pub const ARM9_RAM_ADDRESS: usize = 0x28;
+pub const ARM9_SIZE: usize = 0x2C;
+pub const ARM7_OFFSET: usize = 0x30;
+pub const ARM7_SIZE: usize = 0x3C;
+pub const FNT_OFFSET: usize = 0x40;
+pub const FNT_SIZE: usize = 0x44;
+pub const FAT_OFFSET: usize = 0x48;
+pub const FAT_SIZE: usize = 0x4C;
+pub const OVT9_OFFSET: usize = 0x50;
+pub const OVT9_SIZE: usize = 0x54;
+pub const OVT7_OFFSET: usize = 0x58;
+pub const OVT7_SIZE: usize = 0x5C;
+pub const BANNER_OFFSET: usize = 0x68;
+pub const ROM_SIZE: usize = 0x80;
+pub const HEADER_CRC: usize = 0x15E;
+pub const HEADER_LEN: usize = 0x200;
+
+// where the code outside the filesystem gets unpacked to
+pub const ARM9_FILE: &str = "arm9.bin";
+pub const OVERLAY9_DIR: &str = "overlay9";
+pub const OVERLAY7_DIR: &str = "overlay7";
+
+const NITROCODE: u32 = 0xDEC00621; // marks the 12 byte footer some arm9 binaries carry
+const OVERLAY_ENTRY_LEN: usize = 0x20;
+const OVERLAY_RAM_SIZE: usize = 0x08; // what the overlay takes loaded, the BSS follows it
+const OVERLAY_BSS_SIZE: usize = 0x0C;
+const OVERLAY_FILE_ID: usize = 0x18;
+const OVERLAY_COMPRESSION: usize = 0x1C; // compressed size in the low 24 bits, bit 24 set if compressed
+const MODULE_PARAMS_COMPRESSED_END: usize = 0x14; // ram address the compressed arm9 ends at, 0 if it isn't
+const ARM9_SECURE_AREA: usize = 0x4000; // has to stay uncompressed
+const ROM_ALIGN: usize = 0x200;
+const FFS: [u8; ROM_ALIGN] = [0xFF; ROM_ALIGN];
+
+pub struct NDSRom {
+	rom: Bytes,
+	root: NitroDir,
+	fat: Vec<(u32, u32)>
+}
+
+#[derive(Clone, Debug)]
+pub struct NitroDir {
+	pub id: u16,
+	pub name: String,
+	pub first_file_id: u16,
+	pub entries: Vec<NitroEntry>
+}
+
+#[derive(Clone, Debug)]
+pub enum NitroEntry {
+	File(String, u16),
+	Dir(NitroDir)
+}
+
+// what we need from an overlay table entry
+#[derive(Clone, Copy, Debug)]
+pub struct Overlay {
+	pub file_id: u16,
+	pub compressed: bool,
+	pub ram_size: u32,
+	pub bss_size: u32
+}
+
+// Replacements for the code outside the filesystem, anything left None is taken from the base rom.
+// Overlays themselves are regular files by id.
+#[derive(Default)]
+pub struct RomCode {
+	pub arm9: Option<Bytes>,
+	pub ovt9: Option<Bytes>,
+	pub ovt7: Option<Bytes>
+}
+
+impl NDSRom {
+	pub fn parse(rom: Bytes) -> Result<Self, BErr> {
+		if rom.len() < HEADER_LEN {
+			return Err("file too small to be an NDS rom".into())
+		}
+		let header = rom.slice(..HEADER_LEN);
+		let fnt = section(&rom, &header, FNT_OFFSET, FNT_SIZE)?;
+		let mut fat_buf = section(&rom, &header, FAT_OFFSET, FAT_SIZE)?;
+		let mut fat = Vec::with_capacity(fat_buf.len() / 8);
+		while fat_buf.remaining() >= 8 {
+			let start = fat_buf.get_u32_le();
+			let end = fat_buf.get_u32_le();
+			if start > end || end as usize > rom.len() {
+				return Err(format!("FAT entry {} points outside the rom", fat.len()).into())
+			}
+			fat.push((start, end));
+		}
+		let root = read_fnt_dir(&fnt, 0xF000, String::new(), &mut HashSet::new())?;
+		Ok(NDSRom {rom, root, fat})
+	}
+
+	pub fn get_root(&self) -> &NitroDir {
+		&self.root
+	}
+
+	pub fn get_header(&self) -> &[u8] {
+		&self.rom[..HEADER_LEN]
+	}
+
+	pub fn get_file(&self, id: u16) -> Result<Bytes, BErr> {
+		let (start, end) = *self.fat.get(id as usize).ok_or_else(|| format!("file id {} not in FAT", id))?;
+		Ok(self.rom.slice(start as usize..end as usize))
+	}
+
+	pub fn get_section(&self, offset_field: usize, size_field: usize) -> Result<Bytes, BErr> {
+		section(&self.rom, self.get_header(), offset_field, size_field)
+	}
+
+	pub fn get_arm9(&self) -> Result<Bytes, BErr> {
+		self.get_section(ARM9_OFFSET, ARM9_SIZE)
+	}
+	
+	// the nitrocode footer right after arm9, if there is one
+	pub fn get_arm9_footer(&self) -> Bytes {
+		let end = read_header_u32(self.get_header(), ARM9_OFFSET) as usize + read_header_u32(self.get_header(), ARM9_SIZE) as usize;
+		if self.rom.len() >= end + 12 && (&self.rom[end..end + 4]).get_u32_le() == NITROCODE {
+			self.rom.slice(end..end + 12)
+		} else {
+			Bytes::new()
+		}
+	}
+
+	pub fn get_banner(&self) -> Result<Bytes, BErr> {
+		let offset = read_header_u32(self.get_header(), BANNER_OFFSET) as usize;
+		if offset == 0 {
+			return Ok(Bytes::new())
+		}
+		let version = self.rom.get(offset..offset + 2).ok_or("banner offset out of range")?;
+		let len = match (&version[..]).get_u16_le() {
+			0x0002 => 0x940,
+			0x0003 => 0xA40,
+			0x0103 => 0x23C0,
+			_ => 0x840
+		};
+		self.rom.get(offset..offset + len).ok_or("banner truncated")?;
+		Ok(self.rom.slice(offset..offset + len))
+	}
+
+	pub fn get_overlays(&self, offset_field: usize, size_field: usize) -> Result<Vec<Overlay>, BErr> {
+		Ok(read_overlays(&self.get_section(offset_field, size_field)?))
+	}
+
+	pub fn get_fat_len(&self) -> usize {
+		self.fat.len()
+	}
+}
+
+pub fn read_overlays(table: &[u8]) -> Vec<Overlay> {
+	table.chunks_exact(OVERLAY_ENTRY_LEN).map(|e| Overlay {
+		file_id: read_header_u32(e, OVERLAY_FILE_ID) as u16,
+		compressed: e[OVERLAY_COMPRESSION + 3] & 1 != 0,
+		ram_size: read_header_u32(e, OVERLAY_RAM_SIZE),
+		bss_size: read_header_u32(e, OVERLAY_BSS_SIZE)
+	}).collect()
+}
+
+// keeps the entry's other flag bits as they were
+pub fn set_overlay_compression(table: &mut [u8], index: usize, compressed_size: Option<u32>) {
+	let field = index * OVERLAY_ENTRY_LEN + OVERLAY_COMPRESSION;
+	let flags = read_header_u32(table, field) & 0xFE000000;
+	let value = match compressed_size {
+		Some(size) => flags | 0x01000000 | (size & 0xFFFFFF),
+		None => flags
+	};
+	write_header_u32(table, field, value);
+}
+
+pub fn set_overlay_ram_size(table: &mut [u8], index: usize, size: u32) {
+	write_header_u32(table, index * OVERLAY_ENTRY_LEN + OVERLAY_RAM_SIZE, size);
+}
+
+// The module params sit somewhere near the start of arm9 and end with the nitrocode in both byte orders.
+fn find_module_params(arm9: &[u8]) -> Option<usize> {
+	let marker = [0x21, 0x06, 0xC0, 0xDE, 0xDE, 0xC0, 0x06, 0x21];
+	arm9.windows(8).position(|w| w == marker).and_then(|p| p.checked_sub(0x1C))
+}
+
+// None if arm9 isn't compressed. The module params in the result say it isn't anymore.
+pub fn decompress_arm9(arm9: &[u8], ram_address: u32) -> Result<Option<Vec<u8>>, BErr> {
+	let params = match find_module_params(arm9) {
+		Some(p) => p,
+		None => return Ok(None)
+	};
+	let compressed_end = read_header_u32(arm9, params + MODULE_PARAMS_COMPRESSED_END);
+	if compressed_end == 0 {
+		return Ok(None)
+	}
+	let len = compressed_end.checked_sub(ram_address)
+		.filter(|l| *l as usize <= arm9.len())
+		.ok_or("arm9 compressed end is outside of arm9")? as usize;
+	let mut out = blz::decompress(&arm9[..len])?;
+	out.extend_from_slice(&arm9[len..]);
+	write_header_u32(&mut out, params + MODULE_PARAMS_COMPRESSED_END, 0);
+	Ok(Some(out))
+}
+
+// None if compressing doesn't make it smaller
+pub fn compress_arm9(arm9: &[u8], ram_address: u32, level: CompressionLevel) -> Result<Option<Vec<u8>>, BErr> {
+	let params = find_module_params(arm9).ok_or("can't find the module params in arm9")?;
+	let mut arm9 = arm9.to_vec();
+	write_header_u32(&mut arm9, params + MODULE_PARAMS_COMPRESSED_END, 0);
+	let min_raw = ARM9_SECURE_AREA.max(params + 0x24).min(arm9.len());
+	Ok(blz::compress(&arm9, level, min_raw).map(|mut out| {
+		let end = ram_address + out.len() as u32;
+		write_header_u32(&mut out, params + MODULE_PARAMS_COMPRESSED_END, end);
+		out
+	}))
+}
+
+pub fn read_header_u32(header: &[u8], offset: usize) -> u32 {
+	(&header[offset..offset + 4]).get_u32_le()
+}
+
+fn write_header_u32(header: &mut [u8], offset: usize, value: u32) {
+	(&mut header[offset..offset + 4]).put_u32_le(value);
+}
+
+fn section(rom: &Bytes, header: &[u8], offset_field: usize, size_field: usize) -> Result<Bytes, BErr> {
+	let offset = read_header_u32(header, offset_field) as usize;
+	let size = read_header_u32(header, size_field) as usize;
+	if offset + size > rom.len() {
+		Err(format!("header field at {:#X} points outside the rom", offset_field).into())
+	} else {
+		Ok(rom.slice(offset..offset + size))
+	}
+}
+
+// `visited` has every directory read so far, so one pointing back at itself can't recurse forever
+fn read_fnt_dir(fnt: &[u8], id: u16, name: String, visited: &mut HashSet<u16>) -> Result<NitroDir, BErr> {
+	if !visited.insert(id) {
+		return Err(format!("directory {:#X} appears more than once in the FNT", id).into())
+	}
+	let main_entry = (id & 0xFFF) as usize * 8;
+	if main_entry + 8 > fnt.len() {
+		return Err(format!("directory {:#X} not in FNT", id).into())
+	}
+	let mut main_buf = &fnt[main_entry..];
+	let sub_offset = main_buf.get_u32_le() as usize;
+	let first_file_id = main_buf.get_u16_le();
+	let mut buf = fnt.get(sub_offset..).ok_or("FNT subtable offset out of range")?;
+	let mut entries = Vec::new();
+	let mut file_id = Some(first_file_id); // None once the ids have run out
+	loop {
+		if !buf.has_remaining() {
+			return Err("FNT subtable not terminated".into())
+		}
+		let len_byte = buf.get_u8();
+		if len_byte == 0 {
+			break;
+		}
+		let len = (len_byte & 0x7F) as usize;
+		if buf.remaining() < len {
+			return Err("FNT subtable entry truncated".into())
+		}
+		let entry_name = str::from_utf8(&buf[..len])?.to_string();
+		buf.advance(len);
+		if len_byte & 0x80 != 0 {
+			if buf.remaining() < 2 {
+				return Err("FNT subtable entry truncated".into())
+			}
+			let sub_id = buf.get_u16_le();
+			entries.push(NitroEntry::Dir(read_fnt_dir(fnt, sub_id, entry_name, visited)?));
+		} else {
+			let id = file_id.ok_or("too many files in an FNT directory")?;
+			entries.push(NitroEntry::File(entry_name, id));
+			file_id = id.checked_add(1);
+		}
+	}
+	Ok(NitroDir {id, name, first_file_id, entries})
+}
+
+fn write_fnt(root: &NitroDir) -> Bytes {
+	// main table is indexed by directory id, so gather everything with its parent first
+	let mut dirs = Vec::new();
+	collect_dirs(root, root.id, &mut dirs);
+	dirs.sort_by_key(|(d, _)| d.id);
+	let mut main_buf = BytesMut::new();
+	let mut sub_buf = BytesMut::new();
+	let main_len = dirs.len() * 8;
+	for (dir, parent) in &dirs {
+		main_buf.put_u32_le((main_len + sub_buf.len()) as u32);
+		main_buf.put_u16_le(dir.first_file_id);
+		main_buf.put_u16_le(if dir.id == root.id {dirs.len() as u16} else {*parent});
+		for entry in &dir.entries {
+			match entry {
+				NitroEntry::File(name, _) => {
+					sub_buf.put_u8(name.len() as u8);
+					sub_buf.put(name.as_bytes());
+				},
+				NitroEntry::Dir(subdir) => {
+					sub_buf.put_u8(subdir.name.len() as u8 | 0x80);
+					sub_buf.put(subdir.name.as_bytes());
+					sub_buf.put_u16_le(subdir.id);
+				}
+			}
+		}
+		sub_buf.put_u8(0);
+	}
+	main_buf.put(sub_buf);
+	main_buf.freeze()
+}
+
+fn collect_dirs<'a>(dir: &'a NitroDir, parent: u16, dirs: &mut Vec<(&'a NitroDir, u16)>) {
+	dirs.push((dir, parent));
+	for entry in &dir.entries {
+		if let NitroEntry::Dir(subdir) = entry {
+			collect_dirs(subdir, dir.id, dirs);
+		}
+	}
+}
+
+fn put_aligned(buf: &mut BytesMut, data: &[u8]) -> usize {
+	let dist = next_multiple_of_align(buf.len()) - buf.len();
+	buf.put(&FFS[..dist]);
+	let offset = buf.len();
+	buf.put(data);
+	offset
+}
+
+fn next_multiple_of_align(from: usize) -> usize {
+	(from + ROM_ALIGN - 1) & !(ROM_ALIGN - 1)
+}
+
+// CRC-16/MODBUS, which is what the header checksum uses
+pub fn crc16(data: &[u8]) -> u16 {
+	let mut crc = 0xFFFFu16;
+	for b in data {
+		crc ^= *b as u16;
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 {(crc >> 1) ^ 0xA001} else {crc >> 1};
+		}
+	}
+	crc
+}
+
+// Lays out a new rom with the base rom's header, code binaries, overlays and banner,
+// taking filesystem files from `files` and code from `code` where present and from the base rom otherwise.
+pub fn build_rom(base: &NDSRom, files: &HashMap<u16, Bytes>, code: &RomCode) -> Result<Bytes, BErr> {
+	let get = |id: u16| -> Result<Bytes, BErr> {
+		match files.get(&id) {
+			Some(f) => Ok(f.clone()),
+			None => base.get_file(id)
+		}
+	};
+	let mut header = base.get_header().to_vec();
+	let arm9_offset = read_header_u32(&header, ARM9_OFFSET) as usize;
+	let mut buf = BytesMut::new();
+	buf.put(&base.rom[..arm9_offset]); // header and whatever sits between it and the arm9 binary
+	let mut fat = vec![(0u32, 0u32); base.get_fat_len()];
+	let mut placed = vec![false; fat.len()];
+	let mut place = |buf: &mut BytesMut, id: u16| -> Result<(), BErr> {
+		if placed[id as usize] {
+			return Ok(())
+		}
+		let data = get(id)?;
+		let offset = put_aligned(buf, &data);
+		fat[id as usize] = (offset as u32, (offset + data.len()) as u32);
+		placed[id as usize] = true;
+		Ok(())
+	};
+
+	let arm9 = match &code.arm9 {
+		Some(arm9) => arm9.clone(),
+		None => base.get_arm9()?
+	};
+	buf.put(&arm9[..]);
+	buf.put(base.get_arm9_footer());
+	write_header_u32(&mut header, ARM9_SIZE, arm9.len() as u32);
+	let ovt9 = match &code.ovt9 {
+		Some(table) => table.clone(),
+		None => base.get_section(OVT9_OFFSET, OVT9_SIZE)?
+	};
+	if !ovt9.is_empty() {
+		write_header_u32(&mut header, OVT9_OFFSET, put_aligned(&mut buf, &ovt9) as u32);
+		for overlay in read_overlays(&ovt9) {
+			place(&mut buf, overlay.file_id)?;
+		}
+	}
+	let arm7 = base.get_section(ARM7_OFFSET, ARM7_SIZE)?;
+	write_header_u32(&mut header, ARM7_OFFSET, put_aligned(&mut buf, &arm7) as u32);
+	let ovt7 = match &code.ovt7 {
+		Some(table) => table.clone(),
+		None => base.get_section(OVT7_OFFSET, OVT7_SIZE)?
+	};
+	if !ovt7.is_empty() {
+		write_header_u32(&mut header, OVT7_OFFSET, put_aligned(&mut buf, &ovt7) as u32);
+		for overlay in read_overlays(&ovt7) {
+			place(&mut buf, overlay.file_id)?;
+		}
+	}
+
+	let fnt = write_fnt(base.get_root());
+	write_header_u32(&mut header, FNT_OFFSET, put_aligned(&mut buf, &fnt) as u32);
+	write_header_u32(&mut header, FNT_SIZE, fnt.len() as u32);
+	let fat_offset = put_aligned(&mut buf, &vec![0; base.get_fat_len() * 8]);
+	write_header_u32(&mut header, FAT_OFFSET, fat_offset as u32);
+	let banner = base.get_banner()?;
+	if !banner.is_empty() {
+		write_header_u32(&mut header, BANNER_OFFSET, put_aligned(&mut buf, &banner) as u32);
+	}
+
+	for id in 0..base.get_fat_len() {
+		place(&mut buf, id as u16)?; // overlays were already placed and get skipped
+	}
+	let mut fat_buf = &mut buf[fat_offset..fat_offset + fat.len() * 8];
+	for (start, end) in &fat {
+		fat_buf.put_u32_le(*start);
+		fat_buf.put_u32_le(*end);
+	}
+
+	let rom_size = buf.len();
+	write_header_u32(&mut header, ROM_SIZE, rom_size as u32);
+	let mut capacity = 0;
+	while (0x20000usize << capacity) < rom_size {
+		capacity += 1;
+	}
+	header[CAPACITY] = capacity;
+	let crc = crc16(&header[..HEADER_CRC]);
+	(&mut header[HEADER_CRC..HEADER_CRC + 2]).put_u16_le(crc);
+	buf[..HEADER_LEN].copy_from_slice(&header);
+	Ok(buf.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the logo every cartridge header carries at 0xC0, whose CRC the header stores at 0x15C
+	const NINTENDO_LOGO: [u8; 156] = [
+		0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09, 0xAD,
+		0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20,
+		0x10, 0x46, 0x4A, 0x4A, 0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF,
+		0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0, 0x13, 0x72, 0xA7, 0xFC,
+		0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61, 0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76,
+		0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38, 0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD,
+		0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85, 0x60, 0xD6, 0x80, 0x25,
+		0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2, 0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44,
+		0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+		0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07
+	];
+
+	#[test]
+	fn crc16_matches_known_values() {
+		assert_eq!(crc16(b"123456789"), 0x4B37);
+		assert_eq!(crc16(&NINTENDO_LOGO), 0xCF56);
+		assert_eq!(crc16(&[]), 0xFFFF);
+	}
+
+	// A small rom: arm9, arm7, an FNT with a subdirectory, the FAT and a file for each of `files`, "a", "b" and "d/c"
+	fn rom(files: &[&[u8]; 3]) -> Bytes {
+		let mut rom = vec![0; 0x1000];
+		let mut put = |at: usize, data: &[u8]| rom[at..at + data.len()].copy_from_slice(data);
+		let fnt = [
+			&[16, 0, 0, 0, 0, 0, 2, 0][..], // root: subtable, first file id, directory count
+			&[25, 0, 0, 0, 2, 0, 0x00, 0xF0], // d: subtable, first file id, parent
+			&[1, b'a', 1, b'b', 0x81, b'd', 0x01, 0xF0, 0],
+			&[1, b'c', 0]
+		].concat();
+		put(0x600, &fnt);
+		for (field, value) in [(ARM9_OFFSET, 0x200), (ARM9_SIZE, 0x40), (ARM7_OFFSET, 0x400), (ARM7_SIZE, 0x20),
+			(FNT_OFFSET, 0x600), (FNT_SIZE, fnt.len()), (FAT_OFFSET, 0x800), (FAT_SIZE, 3 * 8)] {
+			put(field, &(value as u32).to_le_bytes());
+		}
+		let mut end = 0xA00;
+		for (i, file) in files.iter().enumerate() {
+			put(0x800 + i * 8, &(end as u32).to_le_bytes());
+			put(0x804 + i * 8, &((end + file.len()) as u32).to_le_bytes());
+			put(end, file);
+			end = next_multiple_of_align(end + file.len());
+		}
+		Bytes::from(rom)
+	}
+
+	#[test]
+	fn rebuilt_roms_parse_back() {
+		let base = NDSRom::parse(rom(&[b"first", &[2; 0x10], b"third"])).unwrap();
+		let grown = Bytes::from(vec![3; 0x20001]); // enough to need the next capacity up
+		let built = build_rom(&base, &HashMap::from([(1, grown.clone())]), &RomCode::default()).unwrap();
+		let rebuilt = NDSRom::parse(built.clone()).unwrap();
+
+		assert_eq!(rebuilt.get_file(0).unwrap(), &b"first"[..]);
+		assert_eq!(rebuilt.get_file(1).unwrap(), grown);
+		assert_eq!(rebuilt.get_file(2).unwrap(), &b"third"[..]);
+		assert_eq!(format!("{:?}", rebuilt.get_root()), format!("{:?}", base.get_root()));
+		assert!(rebuilt.fat.iter().all(|(start, _)| (*start as usize).is_multiple_of(ROM_ALIGN)));
+		assert!(rebuilt.fat.windows(2).all(|w| w[0].1 <= w[1].0));
+		assert_eq!(rebuilt.get_arm9().unwrap(), base.get_arm9().unwrap());
+
+		let header = rebuilt.get_header();
+		assert_eq!(read_header_u32(header, ROM_SIZE) as usize, built.len());
+		assert_eq!(header[CAPACITY], 1); // 256KB
+		assert_eq!((&header[HEADER_CRC..]).get_u16_le(), crc16(&header[..HEADER_CRC]));
+	}
+}
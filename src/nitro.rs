@@ -0,0 +1,378 @@
+use crate::{
+	BErr, FileType,
+	inspect::Node,
+	magic::*,
+	util::Reader
+};
+use bytes::Buf;
+
+// The header every Nitro format (NCGR, NCLR, NSCR...) starts with, followed by its sections one after another.
+// Magics are read as little endian ints, so they match the constants in magic.rs.
+pub struct NitroFile<'a> {
+	pub magic: u32,
+	sections: Vec<(u32, usize, &'a [u8])> // magic, where the contents start in the file, contents
+}
+
+impl<'a> NitroFile<'a> {
+	pub fn parse(buf: &'a [u8]) -> Result<Self, BErr> {
+		let (magic, header_size, section_count) = read_header(buf)?;
+		let mut sections = Vec::with_capacity(section_count);
+		let mut offset = header_size;
+		for i in 0..section_count {
+			let size = read_section(buf, offset, i, &mut sections)?;
+			offset += size;
+		}
+		Ok(NitroFile {magic, sections})
+	}
+
+	// The 3D formats (NSBMD, NSBTX...) list where each section starts right after the header instead
+	pub fn parse_g3d(buf: &'a [u8]) -> Result<Self, BErr> {
+		let (magic, header_size, section_count) = read_header(buf)?;
+		let mut sections = Vec::with_capacity(section_count);
+		for i in 0..section_count {
+			let at = header_size + i * 4;
+			let offset = buf.get(at..at + 4).ok_or("section offsets are past the end of the file")?;
+			read_section(buf, u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize, i, &mut sections)?;
+		}
+		Ok(NitroFile {magic, sections})
+	}
+
+	// contents of the first section with this magic, without its 8 byte header
+	pub fn get_section(&self, magic: u32) -> Option<&'a [u8]> {
+		self.sections.iter().find(|(m, _, _)| *m == magic).map(|(_, _, s)| *s)
+	}
+
+	// where get_section's slice starts in the file, for writing changes back in place
+	pub fn get_section_offset(&self, magic: u32) -> Option<usize> {
+		self.sections.iter().find(|(m, _, _)| *m == magic).map(|(_, o, _)| *o)
+	}
+}
+
+// (magic, header size, section count)
+fn read_header(buf: &[u8]) -> Result<(u32, usize, usize), BErr> {
+	if buf.len() < 0x10 {
+		return Err("too small for a Nitro header".into())
+	}
+	let mut header = buf;
+	let magic = header.get_u32_le();
+	let bom = header.get_u16_le();
+	if bom != 0xFEFF {
+		return Err(format!("bad byte order mark {:#06X}", bom).into())
+	}
+	header.advance(6); // version and file size
+	let header_size = header.get_u16_le() as usize;
+	let section_count = header.get_u16_le() as usize;
+	Ok((magic, header_size, section_count))
+}
+
+// adds the section at `offset` and returns its size
+fn read_section<'a>(buf: &'a [u8], offset: usize, i: usize, sections: &mut Vec<(u32, usize, &'a [u8])>) -> Result<usize, BErr> {
+	let mut section = buf.get(offset..offset + 8).ok_or_else(|| format!("section {} is past the end of the file", i))?;
+	let section_magic = section.get_u32_le();
+	let size = section.get_u32_le() as usize;
+	if size < 8 || offset + size > buf.len() {
+		return Err(format!("section {} has a bad size {:#X}", i, size).into())
+	}
+	sections.push((section_magic, offset + 8, &buf[offset + 8..offset + size]));
+	Ok(size)
+}
+
+// How each Nitro format lays out its sections and the section it can't do without
+fn layout_of(ty: FileType) -> Option<(bool, u32)> {
+	Some(match ty {
+		FileType::NCGR => (false, CHAR_MAGIC),
+		FileType::NCLR => (false, PLTT_MAGIC),
+		FileType::NSCR => (false, SCRN_MAGIC),
+		FileType::NCER => (false, CEBK_MAGIC),
+		FileType::NANR => (false, ABNK_MAGIC),
+		FileType::NFTR => (false, FINF_MAGIC),
+		FileType::SSEQ | FileType::SSAR | FileType::SBNK | FileType::SWAR => (false, DATA_MAGIC),
+		FileType::STRM => (false, HEAD_MAGIC),
+		FileType::NSBMD => (true, MDL0_MAGIC),
+		FileType::NSBTX => (true, TEX0_MAGIC),
+		FileType::NSBCA => (true, JNT0_MAGIC),
+		FileType::NSBTP => (true, PAT0_MAGIC),
+		FileType::NSBTA => (true, SRT0_MAGIC),
+		FileType::NSBMA => (true, MAT0_MAGIC),
+		FileType::NSBVA => (true, VIS0_MAGIC),
+		_ => return None
+	})
+}
+
+pub fn is_nitro(ty: FileType) -> bool {
+	layout_of(ty).is_some()
+}
+
+// The 2D formats store their magics backwards ("RGCN" for NCGR), the rest the right way round
+fn magic_name(magic: u32, backwards: bool) -> String {
+	let bytes = if backwards {magic.to_be_bytes()} else {magic.to_le_bytes()};
+	bytes.iter().map(|b| if b.is_ascii_graphic() {*b as char} else {'?'}).collect()
+}
+
+// Reads the header and sections the way the format says they are, checking every size and count on the way
+pub fn inspect(buf: &[u8], ty: FileType) -> Node {
+	let (g3d, required) = layout_of(ty).unwrap_or((false, 0));
+	let mut node = Node::new(format!("{:?}", ty), 0, buf.len());
+	if buf.len() < 0x10 {
+		node.problems.push(format!("only {:#X} bytes, too small for a Nitro header", buf.len()));
+		return node
+	}
+	let mut header = buf;
+	let magic = header.get_u32_le();
+	let backwards = magic.to_be_bytes()[0] == b'N';
+	let bom = header.get_u16_le();
+	let version = header.get_u16_le();
+	let file_size = header.get_u32_le() as usize;
+	let header_size = header.get_u16_le() as usize;
+	let section_count = header.get_u16_le() as usize;
+	node.field("magic", magic_name(magic, backwards));
+	node.field("byte order mark", format!("{:#06X}", bom));
+	node.field("version", format!("{}.{}", version >> 8, version & 0xFF));
+	node.field("file size", format!("{:#X}", file_size));
+	node.field("header size", format!("{:#X}", header_size));
+	node.field("sections", section_count);
+	if bom != 0xFEFF {
+		node.problems.push(format!("bad byte order mark {:#06X}", bom));
+	}
+	if file_size > buf.len() {
+		node.problems.push(format!("truncated, the header says {:#X} bytes but there are {:#X}", file_size, buf.len()));
+	} else if buf[file_size..].iter().any(|b| *b != 0) {
+		node.problems.push(format!("{:#X} bytes of data past the end the header gives", buf.len() - file_size));
+	} else if file_size < buf.len() {
+		node.field("padding after the file", format!("{:#X}", buf.len() - file_size));
+	}
+	if header_size < 0x10 || header_size > buf.len() {
+		node.problems.push(format!("bad header size {:#X}", header_size));
+		return node
+	}
+	let end = file_size.min(buf.len());
+	let mut offsets = Vec::with_capacity(section_count);
+	if g3d {
+		for i in 0..section_count {
+			match buf.get(header_size + i * 4..header_size + i * 4 + 4) {
+				Some(offset) => offsets.push(u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize),
+				None => {
+					node.problems.push(format!("the table of {} sections runs past the end of the file", section_count));
+					break;
+				}
+			}
+		}
+	}
+	let mut at = header_size;
+	let mut found = Vec::new();
+	for i in 0..section_count {
+		let offset = if g3d {
+			match offsets.get(i) {
+				Some(offset) => *offset,
+				None => break
+			}
+		} else {
+			at
+		};
+		let section = match buf.get(offset..offset + 8).filter(|_| offset + 8 <= end) {
+			Some(section) => section,
+			None => {
+				node.problems.push(format!("section {} at {:#X} is past the end of the file", i, offset));
+				break;
+			}
+		};
+		let section_magic = u32::from_le_bytes([section[0], section[1], section[2], section[3]]);
+		let size = u32::from_le_bytes([section[4], section[5], section[6], section[7]]) as usize;
+		let mut child = Node::new(magic_name(section_magic, backwards), offset, size);
+		if size < 8 || offset + size > end {
+			child.problems.push(format!("bad size {:#X}, the file ends at {:#X}", size, end));
+			node.children.push(child);
+			break;
+		}
+		// only sections that read fine count, so one that didn't isn't blamed again for where the sections end
+		found.push(section_magic);
+		node.children.push(child);
+		// some writers pad sections to 4 without counting it in their size
+		at = offset + size;
+		if !at.is_multiple_of(4) && buf[at..end.min(at.next_multiple_of(4))].iter().all(|b| *b == 0) {
+			at = at.next_multiple_of(4).min(end);
+		}
+	}
+	// the 2D and sound formats have nothing after their last section
+	if !g3d && found.len() == section_count && at != end {
+		node.problems.push(format!("the sections end at {:#X} but the file at {:#X}", at, end));
+	}
+	if g3d {
+		let mut spans: Vec<(usize, usize)> = node.children.iter().map(|c| (c.offset, c.offset + c.size)).collect();
+		spans.sort_unstable();
+		if spans.windows(2).any(|w| w[0].1 > w[1].0) {
+			node.problems.push("sections overlap".into());
+		}
+	}
+	if required != 0 && found.len() == section_count && !found.contains(&required) {
+		node.problems.push(format!("no {} section", magic_name(required, backwards)));
+	}
+	node
+}
+
+// What's wrong with a Nitro file, if anything
+pub fn check(buf: &[u8], ty: FileType) -> Vec<String> {
+	if !is_nitro(ty) {
+		return Vec::new()
+	}
+	inspect(buf, ty).all_problems()
+}
+
+// The name lookup tables the 3D formats use: a count, a search tree that isn't needed when reading all of them,
+// then fixed size entries followed by 16 byte names.
+pub fn read_dict(block: &[u8], at: usize) -> Result<Vec<(String, &[u8])>, BErr> {
+	let count = *block.get(at + 1).ok_or("dictionary out of range")? as usize;
+	let entries = at + Reader::new(block, at + 6).u16()? as usize;
+	let entry_size = Reader::new(block, entries).u16()? as usize;
+	let names = entries + 4 + entry_size * count;
+	let mut dict = Vec::with_capacity(count);
+	for i in 0..count {
+		let entry = block.get(entries + 4 + entry_size * i..entries + 4 + entry_size * (i + 1)).ok_or("dictionary out of range")?;
+		let name = block.get(names + i * 16..names + i * 16 + 16).ok_or("dictionary out of range")?;
+		let len = name.iter().position(|b| *b == 0).unwrap_or(16);
+		dict.push((String::from_utf8_lossy(&name[..len]).into_owned(), entry));
+	}
+	Ok(dict)
+}
+
+// Builds a dictionary with its search tree, a patricia tree over the bits of the names, which the game
+// walks from the highest bit down. The entries all have to be the same size.
+pub fn write_dict(names: &[String], entries: &[Vec<u8>]) -> Vec<u8> {
+	let keys: Vec<[u8; 16]> = names.iter().map(|name| {
+		let mut key = [0; 16];
+		for (k, b) in key.iter_mut().zip(name.bytes()) {
+			*k = b;
+		}
+		key
+	}).collect();
+	let bit = |key: &[u8; 16], bit: u8| (key[bit as usize >> 3] >> (bit & 7)) & 1 != 0;
+	// (reference bit, left, right, entry), starting with the root
+	let mut nodes: Vec<(u8, u8, u8, u8)> = vec![(0x7F, 0, 0, 0)];
+	for (i, key) in keys.iter().enumerate() {
+		let closest = if nodes.len() == 1 {
+			[0; 16]
+		} else {
+			let (mut node, mut next) = (0, nodes[0].1 as usize);
+			while nodes[next].0 < nodes[node].0 {
+				node = next;
+				next = if bit(key, nodes[node].0) {nodes[node].2} else {nodes[node].1} as usize;
+			}
+			keys[nodes[next].3 as usize]
+		};
+		let differing = (0..0x7F).rev().find(|b| bit(key, *b) != bit(&closest, *b)).unwrap_or(0);
+		let new = nodes.len() as u8;
+		let (mut node, mut next, mut right) = (0, nodes[0].1 as usize, false);
+		while nodes[next].0 < nodes[node].0 && nodes[next].0 > differing {
+			node = next;
+			right = bit(key, nodes[node].0);
+			next = if right {nodes[node].2} else {nodes[node].1} as usize;
+		}
+		nodes.push(if bit(key, differing) {(differing, next as u8, new, i as u8)} else {(differing, new, next as u8, i as u8)});
+		if right {
+			nodes[node].2 = new;
+		} else {
+			nodes[node].1 = new;
+		}
+	}
+	let entry_size = entries.first().map_or(4, |e| e.len());
+	let entries_at = 8 + nodes.len() * 4;
+	let size = entries_at + 4 + (entry_size + 16) * names.len();
+	let mut out = Vec::with_capacity(size);
+	out.extend_from_slice(&[0, names.len() as u8]);
+	out.extend_from_slice(&(size as u16).to_le_bytes());
+	out.extend_from_slice(&8u16.to_le_bytes());
+	out.extend_from_slice(&(entries_at as u16).to_le_bytes());
+	for (reference, left, right, entry) in nodes {
+		out.extend_from_slice(&[reference, left, right, entry]);
+	}
+	out.extend_from_slice(&(entry_size as u16).to_le_bytes());
+	out.extend_from_slice(&((4 + entry_size * names.len()) as u16).to_le_bytes());
+	for entry in entries {
+		out.extend_from_slice(entry);
+	}
+	for key in keys {
+		out.extend_from_slice(&key);
+	}
+	out
+}
+
+// A Nitro file with these sections one after the other, for the tests of the formats built on it
+#[cfg(test)]
+pub fn build(magic: u32, sections: &[(u32, &[u8])]) -> Vec<u8> {
+	let mut buf = magic.to_le_bytes().to_vec();
+	buf.extend_from_slice(&[0xFF, 0xFE, 0x00, 0x01]);
+	buf.extend_from_slice(&[0; 4]); // the file size, once it's known
+	buf.extend_from_slice(&0x10u16.to_le_bytes());
+	buf.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+	for (magic, data) in sections {
+		buf.extend_from_slice(&magic.to_le_bytes());
+		buf.extend_from_slice(&(data.len() as u32 + 8).to_le_bytes());
+		buf.extend_from_slice(data);
+	}
+	let size = buf.len() as u32;
+	buf[8..12].copy_from_slice(&size.to_le_bytes());
+	buf
+}
+
+// The same for the 3D formats, whose header lists where each section starts
+#[cfg(test)]
+pub fn build_g3d(magic: u32, sections: &[(u32, &[u8])]) -> Vec<u8> {
+	let mut buf = build(magic, &[]);
+	buf[14..16].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+	let mut offset = buf.len() + sections.len() * 4;
+	for (_, data) in sections {
+		buf.extend_from_slice(&(offset as u32).to_le_bytes());
+		offset += data.len() + 8;
+	}
+	for (magic, data) in sections {
+		buf.extend_from_slice(&magic.to_le_bytes());
+		buf.extend_from_slice(&(data.len() as u32 + 8).to_le_bytes());
+		buf.extend_from_slice(data);
+	}
+	let size = buf.len() as u32;
+	buf[8..12].copy_from_slice(&size.to_le_bytes());
+	buf
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the palette compression section, which NCLRs can have after their palette
+	const PCMP_MAGIC: u32 = u32::from_be_bytes(*b"PCMP");
+
+	fn nclr(sections: &[(u32, &[u8])]) -> Vec<u8> {
+		build(NCLR_MAGIC, sections)
+	}
+
+	#[test]
+	fn well_formed_files_pass() {
+		assert!(check(&nclr(&[(PLTT_MAGIC, &[0; 0x18])]), FileType::NCLR).is_empty());
+		assert!(check(&build_g3d(NSBTX_MAGIC, &[(TEX0_MAGIC, &[0; 0x34])]), FileType::NSBTX).is_empty());
+	}
+
+	#[test]
+	fn truncated_files_are_caught() {
+		let mut file = nclr(&[(PLTT_MAGIC, &[0; 0x18])]);
+		file.truncate(0x20);
+		assert_eq!(check(&file, FileType::NCLR), [
+			"NCLR: truncated, the header says 0x30 bytes but there are 0x20",
+			"PLTT: bad size 0x20, the file ends at 0x20"
+		]);
+		assert_eq!(check(&file[..8], FileType::NCLR), ["NCLR: only 0x8 bytes, too small for a Nitro header"]);
+	}
+
+	#[test]
+	fn wrong_section_counts_are_caught() {
+		let mut file = nclr(&[(PLTT_MAGIC, &[0; 0x18]), (PCMP_MAGIC, &[0; 8])]);
+		file[14] = 3;
+		assert_eq!(check(&file, FileType::NCLR), ["NCLR: section 2 at 0x40 is past the end of the file"]);
+		file[14] = 1;
+		assert_eq!(check(&file, FileType::NCLR), ["NCLR: the sections end at 0x30 but the file at 0x40"]);
+		// the section it can't do without
+		let file = nclr(&[(PCMP_MAGIC, &[0; 8])]);
+		assert_eq!(check(&file, FileType::NCLR), ["NCLR: no PLTT section"]);
+		let file = build_g3d(NSBTX_MAGIC, &[(MDL0_MAGIC, &[0; 8])]);
+		assert_eq!(check(&file, FileType::NSBTX), ["NSBTX: no TEX0 section"]);
+	}
+}
@@ -1,44 +1,75 @@
 use crate::{
-	meta::{FileMeta, LZType},
+	meta::{FileMeta, P2SubfileMeta, OriginalPayload, Patches, GroupLayout, ORIGINALS_DIR},
 	iohelper::{IOHelper, RelPath},
 	P2File, PKAC, PK2D, HPAK, BErr, P2Subfile, GroupedFiles,
 	magic::*,
-	compression::safe_compress
+	compression::{compress_as, compress_rle, compress_huffman},
+	meta::{DirectoryMeta, NDSMeta, CodeMeta, CompressionLevel},
+	nds::{
+		NDSRom, NitroDir, NitroEntry, RomCode, build_rom, read_overlays, set_overlay_compression, set_overlay_ram_size, compress_arm9, read_header_u32,
+		ARM9_RAM_ADDRESS, ARM9_FILE, OVERLAY9_DIR, OVERLAY7_DIR, OVT9_OFFSET, OVT9_SIZE, OVT7_OFFSET, OVT7_SIZE
+	},
+	compression::blz,
+	graphics::import_pk2d_pngs,
+	textures::import_texture_pngs,
+	anims::import_animation_json,
+	sdat::write_sdat,
+	audio::import_wavs,
+	fonts::import_font,
+	text::import_messages,
+	util::{content_hash, to_hex, from_hex}
 };
 use bytes::{Bytes, BytesMut, BufMut};
-//use nintendo_lz::{CompressionLevel, compress};
+use std::collections::HashMap;
 
 const NULS: [u8; 2048] = [0; 2048]; // bunch of nul to copy
-const FFS: [u8; 32] = [0xFF; 32];
 
 pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHelper) -> Result<Bytes, BErr> {
 	let mut path = parent_unpacked_path.clone();
 	match meta {
 		FileMeta::OtherFile(name) => {
 			path.push(name.clone());
-			Ok(helper.read_file(&path)?)
+			let file = helper.read_file(&path)?;
+			path.pop();
+			let file = import_animation_json(helper, &path, name, file)?;
+			let file = import_wavs(helper, &path, name, file)?;
+			let file = import_font(helper, &path, name, file)?;
+			let file = import_messages(helper, &path, name, file)?;
+			import_texture_pngs(helper, &path, name, file)
 		},
 		FileMeta::EmptyFile => {
 			Ok(Bytes::new())
 		},
 		FileMeta::LZ(lzm) => {
-			let file = pack_file(&path, &*lzm.get_file(), helper)?;
-			let compressed = safe_compress(&file)?;
+			let file = pack_file(&path, lzm.get_file(), helper)?;
+			if let Some(original) = reuse_original(lzm.get_original(), &file, helper) {
+				return Ok(original)
+			}
+			let compressed = compress_as(&file, lzm.get_lz_type(), lzm.get_level().unwrap_or(helper.get_options().compression_level))?;
 			let bytes = Bytes::copy_from_slice(&compressed);
 			Ok(bytes)
 		}
+		FileMeta::RLE(rlem) => {
+			let file = pack_file(&path, rlem.get_file(), helper)?;
+			if let Some(original) = reuse_original(rlem.get_original(), &file, helper) {
+				return Ok(original)
+			}
+			Ok(Bytes::from(compress_rle(&file)?))
+		}
+		FileMeta::Huffman(hm) => {
+			let file = pack_file(&path, hm.get_file(), helper)?;
+			if let Some(original) = reuse_original(hm.get_original(), &file, helper) {
+				return Ok(original)
+			}
+			Ok(Bytes::from(compress_huffman(&file, hm.get_huffman_type())?))
+		}
 		FileMeta::P2(p2m) => {
 			path.push(p2m.get_unpacked_name().into());
 			let mut subfiles = Vec::with_capacity(p2m.get_files().len());
 			let p2_files = p2m.get_files();
 			for (i, file) in p2_files.iter().enumerate() {
-				let mut buf = pack_file(&path, file.get_file(), helper)?;
-				if file.is_compressed() && !buf.is_empty() {
-					// TODO parallelize this 
-					let compressed = safe_compress(&buf)?;
-					buf = Bytes::copy_from_slice(&compressed);
-				}
-				
+				// TODO parallelize this 
+				let buf = pack_p2_subfile(&path, file, helper)?;
 				subfiles.push(P2Subfile {
 					index: i as u16,
 					compressed: file.is_compressed(),
@@ -48,19 +79,16 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 			}
 			Ok(P2File {
 				named: false,
-				subfiles
-			}.into())
+				subfiles,
+				layout: p2m.get_layout().cloned()
+			}.to_bytes())
 		}
 		FileMeta::NamedP2(p2m) => {
 			path.push(p2m.get_unpacked_name().into());
 			let mut subfiles = Vec::with_capacity(p2m.get_files().len());
 			let p2_files = p2m.get_files();
 			for (i, (name, file)) in p2_files.iter().enumerate() {
-				let mut buf = pack_file(&path, file.get_file(), helper)?;
-				if file.is_compressed() && !buf.is_empty() {
-					let compressed = safe_compress(&buf)?;
-					buf = Bytes::copy_from_slice(&compressed);
-				}
+				let buf = pack_p2_subfile(&path, file, helper)?;
 				subfiles.push(P2Subfile {
 					index: i as u16,
 					compressed: file.is_compressed(),
@@ -70,8 +98,9 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 			}
 			Ok(P2File {
 				named: true,
-				subfiles
-			}.into())
+				subfiles,
+				layout: p2m.get_layout().cloned()
+			}.to_bytes())
 		},
 		FileMeta::PKAC(pkac_meta) => {
 			path.push(pkac_meta.get_unpacked_name().into());
@@ -79,12 +108,7 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 			for (name, file) in pkac_meta.get_files() {
 				files.push((name.into(), pack_file(&path, file, helper)?));
 			}
-			let bytes: Bytes = GFWrapper(PKAC{files}.into()).into();
-			let mut buf = BytesMut::new();
-			buf.put_u32_le(PKAC_MAGIC);
-			buf.put_u32_le(0);
-			buf.put(bytes);
-			Ok(buf.freeze())
+			Ok(write_grouped(PKAC_MAGIC, &PKAC{files}.into(), pkac_meta.get_layout()))
 		}
 		FileMeta::HPAK(hpak_meta) => {
 			path.push(hpak_meta.get_unpacked_name().into());
@@ -98,16 +122,11 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 				unknown6: load_metas(&path, hpak_meta.get_unknown6(), helper)?,
 				nsbmd: load_metas(&path, hpak_meta.get_nsbmd(), helper)?
 			};
-			let bytes: Bytes = GFWrapper(hpak.into()).into();
-			let mut buf = BytesMut::new();
-			buf.put_u32_le(HPAK_MAGIC);
-			buf.put_u32_le(0);
-			buf.put(bytes);
-			Ok(buf.freeze())
+			Ok(write_grouped(HPAK_MAGIC, &hpak.into(), hpak_meta.get_layout()))
 		},
 		FileMeta::PK2D(pk2d_meta) => {
 			path.push(pk2d_meta.get_unpacked_name().into());
-			let pk2d = PK2D {
+			let mut pk2d = PK2D {
 				nclr: load_metas(&path, pk2d_meta.get_nclr(), helper)?,
 				ncgr: load_metas(&path, pk2d_meta.get_ncgr(), helper)?,
 				unknown2: load_metas(&path, pk2d_meta.get_unknown2(), helper)?,
@@ -117,12 +136,8 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 				nscr: load_metas(&path, pk2d_meta.get_nscr(), helper)?,
 				unknown7: load_metas(&path, pk2d_meta.get_unknown7(), helper)?,
 			};
-			let bytes: Bytes = GFWrapper(pk2d.into()).into();
-			let mut buf = BytesMut::new();
-			buf.put_u32_le(PK2D_MAGIC);
-			buf.put_u32_le(0);
-			buf.put(bytes);
-			Ok(buf.freeze())
+			import_pk2d_pngs(helper, &path, &mut pk2d, pk2d_meta.get_ncgr_pngs())?;
+			Ok(write_grouped(PK2D_MAGIC, &pk2d.into(), pk2d_meta.get_layout()))
 		},
 		FileMeta::Directory(dir_meta) => {
 			path.push(dir_meta.get_unpacked_name().into());
@@ -138,6 +153,22 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 			}
 			Ok(Bytes::new()) // directories return empty, since they are side-effect based rather than pure parsing/serializing
 		},
+		FileMeta::SDAT(sdat_meta) => {
+			let mut headers_path = RelPath::new();
+			headers_path.push(ORIGINALS_DIR.into());
+			headers_path.push(sdat_meta.get_headers().payload.clone());
+			let headers = helper.read_file(&headers_path)?;
+			path.push(sdat_meta.get_unpacked_name().into());
+			let mut files = Vec::with_capacity(sdat_meta.get_files().len());
+			for (_, file) in sdat_meta.get_files() {
+				files.push(pack_file(&path, file, helper)?);
+			}
+			write_sdat(&headers, &files, Some(sdat_meta.get_layout()))
+		},
+		FileMeta::NDS(nds_meta) => {
+			// without a base rom to rebuild, the best we can do is write the filesystem out loose
+			pack_file(&path, nds_meta.get_data(), helper)
+		},
 		FileMeta::Uninitialized => {
 			println!("Uninitialized metadata at {:?}", path);
 			Ok(Bytes::new())
@@ -145,6 +176,114 @@ pub fn pack_file(parent_unpacked_path: &RelPath, meta: &FileMeta, helper: &IOHel
 	}
 }
 
+pub fn pack_rom(meta: &NDSMeta, base: &NDSRom, helper: &IOHelper) -> Result<Bytes, BErr> {
+	let dir_meta = match meta.get_data() {
+		FileMeta::Directory(d) => d,
+		_ => return Err("rom metadata has no filesystem directory".into())
+	};
+	let mut path = RelPath::new();
+	path.push(dir_meta.get_unpacked_name().into());
+	let mut files = HashMap::new();
+	collect_rom_files(&path, base.get_root(), dir_meta, helper, &mut files)?;
+	let mut code = RomCode::default();
+	if let Some(arm9) = meta.get_arm9() {
+		let ram_address = read_header_u32(base.get_header(), ARM9_RAM_ADDRESS);
+		let mut path = RelPath::new();
+		path.push(ARM9_FILE.into());
+		code.arm9 = Some(pack_code(&path, arm9, helper, |buf, level| compress_arm9(buf, ram_address, level))?.0);
+	}
+	code.ovt9 = pack_overlays(meta.get_overlays9(), OVERLAY9_DIR, base.get_section(OVT9_OFFSET, OVT9_SIZE)?, helper, &mut files)?;
+	code.ovt7 = pack_overlays(meta.get_overlays7(), OVERLAY7_DIR, base.get_section(OVT7_OFFSET, OVT7_SIZE)?, helper, &mut files)?;
+	build_rom(base, &files, &code)
+}
+
+// Overlays go into the filesystem by id, the returned table has their compression fields updated.
+fn pack_overlays(metas: &[CodeMeta], dir: &str, table: Bytes, helper: &IOHelper, files: &mut HashMap<u16, Bytes>) -> Result<Option<Bytes>, BErr> {
+	if metas.is_empty() {
+		return Ok(None)
+	}
+	let mut table = table.to_vec();
+	for (i, (meta, overlay)) in metas.iter().zip(read_overlays(&table)).enumerate() {
+		let mut path = RelPath::new();
+		path.push(dir.into());
+		path.push(meta.get_unpacked_name().into());
+		let (content, compressed, ram_size) = pack_code(&path, meta, helper, |buf, level| Ok(blz::compress(buf, level, 0)))?;
+		if meta.is_compressed() {
+			set_overlay_compression(&mut table, i, if compressed {Some(content.len() as u32)} else {None});
+		}
+		// the game clears the BSS right after the overlay, so growing it moves the BSS to addresses its code doesn't expect
+		if ram_size as u32 > overlay.ram_size && overlay.bss_size != 0 {
+			println!("{:?} grew from {:#X} to {:#X} bytes, over the start of its BSS", path, overlay.ram_size, ram_size);
+		}
+		set_overlay_ram_size(&mut table, i, ram_size as u32);
+		files.insert(overlay.file_id, content);
+	}
+	Ok(Some(Bytes::from(table)))
+}
+
+// BLZ compresses the code again if it was, unless that doesn't make it any smaller.
+// Also says if it ended up compressed, and how long it is decompressed.
+fn pack_code(path: &RelPath, meta: &CodeMeta, helper: &IOHelper, compress: impl Fn(&[u8], CompressionLevel) -> Result<Option<Vec<u8>>, BErr>) -> Result<(Bytes, bool, usize), BErr> {
+	let content = helper.read_file(path)?;
+	let len = content.len();
+	if !meta.is_compressed() {
+		return Ok((content, false, len))
+	}
+	if let Some(original) = reuse_original(meta.get_original(), &content, helper) {
+		return Ok((original, true, len))
+	}
+	let level = meta.get_level().unwrap_or(helper.get_options().compression_level);
+	Ok(match compress(&content, level)? {
+		Some(compressed) => (Bytes::from(compressed), true, len),
+		None => (content, false, len)
+	})
+}
+
+fn collect_rom_files(path: &RelPath, dir: &NitroDir, dir_meta: &DirectoryMeta, helper: &IOHelper, files: &mut HashMap<u16, Bytes>) -> Result<(), BErr> {
+	for entry in &dir.entries {
+		match entry {
+			NitroEntry::File(name, id) => {
+				// anything missing from the metadata is left as it was in the base rom
+				if let Some(file) = dir_meta.get_files().get(name) {
+					files.insert(*id, pack_file(path, file, helper)?);
+				}
+			},
+			NitroEntry::Dir(subdir) => {
+				if let Some(FileMeta::Directory(sub_meta)) = dir_meta.get_files().get(&subdir.name) {
+					let mut sub_path = path.clone();
+					sub_path.push(sub_meta.get_unpacked_name().into());
+					collect_rom_files(&sub_path, subdir, sub_meta, helper, files)?;
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+fn pack_p2_subfile(path: &RelPath, file: &P2SubfileMeta, helper: &IOHelper) -> Result<Bytes, BErr> {
+	let buf = pack_file(path, file.get_file(), helper)?;
+	if !file.is_compressed() || buf.is_empty() {
+		return Ok(buf)
+	}
+	if let Some(original) = reuse_original(file.get_original(), &buf, helper) {
+		return Ok(original)
+	}
+	let compressed = compress_as(&buf, file.get_lz_type(), file.get_level().unwrap_or(helper.get_options().compression_level))?;
+	Ok(Bytes::copy_from_slice(&compressed))
+}
+
+// the compressed payload from extraction, if the content it decompresses to hasn't changed since
+fn reuse_original(original: Option<&OriginalPayload>, content: &[u8], helper: &IOHelper) -> Option<Bytes> {
+	let original = original?;
+	if content_hash(content) != original.content_hash {
+		return None
+	}
+	let mut path = RelPath::new();
+	path.push(ORIGINALS_DIR.into());
+	path.push(original.payload.clone());
+	helper.read_file(&path).ok()
+}
+
 fn load_metas(parent_path: &RelPath, metas: &[FileMeta], helper: &IOHelper) -> Result<Vec<Bytes>, BErr> {
 	let mut res = Vec::with_capacity(metas.len());
 	for file in metas.iter().map(|m| pack_file(parent_path, m, helper)) {
@@ -161,24 +300,74 @@ fn next_multiple_of_512(from: usize) -> usize {
 	}
 }
 
-impl Into<Bytes> for P2File {
-	fn into(self) -> Bytes {
+pub fn make_patches(original: &[u8], rebuilt: &[u8]) -> Patches {
+	let mut patches: Vec<(u32, Vec<u8>)> = Vec::new();
+	for (i, b) in original.iter().enumerate() {
+		if rebuilt.get(i) == Some(b) {
+			continue;
+		}
+		match patches.last_mut() {
+			// only bytes that differ, anything in between could be a file that gets edited
+			Some((start, bytes)) if *start as usize + bytes.len() == i => bytes.push(*b),
+			_ => patches.push((i as u32, vec![*b]))
+		}
+	}
+	Patches {
+		len: original.len() as u32,
+		patches: patches.iter().map(|(o, b)| (*o, to_hex(b))).collect()
+	}
+}
+
+pub fn apply_patches(buf: &mut BytesMut, patches: &Patches) {
+	// the rebuild can come out longer or shorter than the original
+	buf.resize(patches.len as usize, 0);
+	for (offset, bytes) in &patches.patches {
+		let bytes = from_hex(bytes).expect("checked when the metadata was read");
+		buf[*offset as usize..*offset as usize + bytes.len()].copy_from_slice(&bytes);
+	}
+}
+
+fn lengths_match<'a>(lengths: &[u32], files: impl ExactSizeIterator<Item = &'a Bytes>) -> bool {
+	lengths.len() == files.len() && files.zip(lengths).all(|(f, l)| f.len() == *l as usize)
+}
+
+impl P2File {
+	pub fn to_bytes(&self) -> Bytes {
+		if let Some(layout) = &self.layout {
+			if lengths_match(&layout.lengths, self.subfiles.iter().map(|f| &f.content)) {
+				let offsets: Vec<usize> = layout.offsets.iter().map(|o| *o as usize).collect();
+				let mut buf = self.write(layout.header_size as usize, &offsets);
+				apply_patches(&mut buf, &layout.patches);
+				return buf.freeze()
+			}
+		}
+		let n_files = self.subfiles.len();
+		let mut header_len = 16 + n_files * 6 + (n_files & 1) * 2;
+		if self.named {
+			header_len += n_files * 8;
+		}
+		let header_size = next_multiple_of_512(header_len);
+		let mut offsets = Vec::with_capacity(n_files);
+		let mut offset = header_size;
+		for file in &self.subfiles {
+			offsets.push(offset);
+			offset = next_multiple_of_512(offset + file.content.len());
+		}
+		let mut buf = self.write(header_size, &offsets);
+		buf.resize(offset, 0);
+		buf.freeze()
+	}
+	
+	fn write(&self, header_size: usize, offsets: &[usize]) -> BytesMut {
 		let n_files = self.subfiles.len() as u16;
 		let mut header_buf = BytesMut::new();
 		header_buf.put_u16_le(P2_MAGIC);
 		header_buf.put_u16_le(n_files | (0x8000 * self.named as u16));
 		header_buf.put_u64_le(0); // padding
-		let header_size_ptr = header_buf.len();
-		header_buf.put_u32_le(0); // placeholder
-		
-		let mut contents_buf = BytesMut::new();
-		for file in &self.subfiles {
-			header_buf.put_u16_le((contents_buf.len() >> 9) as u16);
-			contents_buf.put(file.content.clone());
-			let dist = next_multiple_of_512(contents_buf.len()) - contents_buf.len();
-			contents_buf.put(&NULS[..dist]);
+		header_buf.put_u32_le(header_size as u32);
+		for offset in offsets {
+			header_buf.put_u16_le(((offset - header_size) >> 9) as u16);
 		}
-		let contents_buf = contents_buf.freeze();
 		if n_files & 1 != 0 {
 			header_buf.put_u16_le(0); // padding if odd
 		}
@@ -195,60 +384,64 @@ impl Into<Bytes> for P2File {
 				header_buf.put(&NULS[..8 - name_bytes.len()]);
 			}
 		}
-		let header_size = next_multiple_of_512(header_buf.len());
-		let header_dist = header_size - header_buf.len();
-		header_buf.put(&NULS[..header_dist]);
-		let mut header_s_bytes = &mut header_buf[header_size_ptr..header_size_ptr + 4];
-		header_s_bytes.put_u32_le(header_size as u32);
-		header_buf.put(contents_buf);
-		header_buf.freeze()
+		if header_buf.len() < header_size {
+			header_buf.resize(header_size, 0);
+		}
+		for (file, offset) in self.subfiles.iter().zip(offsets) {
+			let end = offset + file.content.len();
+			if header_buf.len() < end {
+				header_buf.resize(end, 0);
+			}
+			header_buf[*offset..end].copy_from_slice(&file.content);
+		}
+		header_buf
 	}
 }
 
-impl Into<GroupedFiles> for HPAK {
-	fn into(self) -> GroupedFiles {
+impl From<HPAK> for GroupedFiles {
+	fn from(hpak: HPAK) -> GroupedFiles {
 		[
-			self.nsbca,
-			self.nsbva,
-			self.nsbma,
-			self.nsbtp,
-			self.nsbta,
-			self.unknown5,
-			self.unknown6,
-			self.nsbmd
+			hpak.nsbca,
+			hpak.nsbva,
+			hpak.nsbma,
+			hpak.nsbtp,
+			hpak.nsbta,
+			hpak.unknown5,
+			hpak.unknown6,
+			hpak.nsbmd
 		]
 	}
 }
 
-impl Into<GroupedFiles> for PK2D {
-	fn into(self) -> GroupedFiles {
+impl From<PK2D> for GroupedFiles {
+	fn from(pk2d: PK2D) -> GroupedFiles {
 		[
-			self.nclr,
-			self.ncgr,
-			self.unknown2,
-			self.ncer,
-			self.unknown4,
-			self.nanr,
-			self.nscr,
-			self.unknown7
+			pk2d.nclr,
+			pk2d.ncgr,
+			pk2d.unknown2,
+			pk2d.ncer,
+			pk2d.unknown4,
+			pk2d.nanr,
+			pk2d.nscr,
+			pk2d.unknown7
 		]
 	}
 }
 
-impl Into<GroupedFiles> for PKAC {
-	fn into(self) -> GroupedFiles {
+impl From<PKAC> for GroupedFiles {
+	fn from(pkac: PKAC) -> GroupedFiles {
 		let mut names_buf = BytesMut::new();
-		names_buf.put_u16_le(self.files.len() as u16);
-		names_buf.put(&NULS[..self.files.len() * 2]);
-		let mut files = Vec::with_capacity(self.files.len());
+		names_buf.put_u16_le(pkac.files.len() as u16);
+		names_buf.put(&NULS[..pkac.files.len() * 2]);
+		let mut files = Vec::with_capacity(pkac.files.len());
 		let mut offset = names_buf.len();
-		for (i, (name, file)) in self.files.iter().enumerate() {
+		for (i, (name, file)) in pkac.files.iter().enumerate() {
 			files.push(file.clone());
 			let mut offset_loc = &mut names_buf[(i + 1) * 2..(i + 2) * 2];
 			offset_loc.put_u16_le(offset as u16);
 			names_buf.put(name.as_bytes());
 			names_buf.put_u8(0); // null terminator
-			offset += name.as_bytes().len() + 1;
+			offset += name.len() + 1;
 		}
 		[
 			vec![names_buf.freeze()],
@@ -263,36 +456,157 @@ impl Into<GroupedFiles> for PKAC {
 	}
 }
 
-struct GFWrapper(GroupedFiles);
+// Writes a full HPAK/PK2D/PKAC, in the original layout if there is one and it still fits.
+pub fn write_grouped(magic: u32, groups: &GroupedFiles, layout: Option<&GroupLayout>) -> Bytes {
+	if let Some(layout) = layout {
+		let fits = groups.iter().zip(&layout.lengths).all(|(g, l)| lengths_match(l, g.iter()));
+		if fits && layout.lengths.len() == groups.len() {
+			let offsets: Vec<Vec<usize>> = layout.offsets.iter().map(|g| g.iter().map(|o| *o as usize).collect()).collect();
+			let info_offsets: Vec<usize> = layout.info_offsets.iter().map(|o| *o as usize).collect();
+			let mut buf = write_grouped_at(magic, groups, &info_offsets, &offsets);
+			apply_patches(&mut buf, &layout.patches);
+			return buf.freeze()
+		}
+	}
+	// header, then each non-empty group's info, then all the files back to back
+	let mut info_offsets = vec![0xFFFFFFFF; groups.len()];
+	let mut offsets = Vec::with_capacity(groups.len());
+	let mut offset = 8 + 4 * groups.len();
+	for (i, group) in groups.iter().enumerate() {
+		if !group.is_empty() {
+			info_offsets[i] = offset;
+			offset += 4 + 8 * group.len();
+		}
+	}
+	for group in groups.iter() {
+		let mut group_offsets = Vec::with_capacity(group.len());
+		for file in group {
+			group_offsets.push(offset);
+			offset += file.len();
+		}
+		offsets.push(group_offsets);
+	}
+	write_grouped_at(magic, groups, &info_offsets, &offsets).freeze()
+}
 
-impl Into<Bytes> for GFWrapper { // does not contain magic bytes or 4 padding!
-	fn into(self) -> Bytes {
-		let mut buf = BytesMut::new();
-		buf.put(&FFS[..32]);
-		let mut offset_offsets = [0; 8];
-		for (i, group) in self.0.iter().enumerate() {
-			if !group.is_empty() {
-				let info_offset = buf.len();
-				buf.put_u32_le(group.len() as u32);
-				offset_offsets[i] = buf.len();
-				let mut info_loc = &mut buf[i * 4..]; 
-				info_loc.put_u32_le(info_offset as u32 + 8); // account for missing magic + padding
-				buf.put(&NULS[..4 * group.len()]);
-				for f in group.iter() {
-					buf.put_u32_le(f.len() as u32);
-				}
-			}
+fn write_grouped_at(magic: u32, groups: &GroupedFiles, info_offsets: &[usize], offsets: &[Vec<usize>]) -> BytesMut {
+	let mut buf = BytesMut::new();
+	buf.put_u32_le(magic);
+	buf.put_u32_le(0); // padding
+	for offset in info_offsets {
+		buf.put_u32_le(*offset as u32);
+	}
+	let put_at = |buf: &mut BytesMut, at: usize, data: &[u8]| {
+		if buf.len() < at + data.len() {
+			buf.resize(at + data.len(), 0);
 		}
-		for (i, group) in self.0.iter().enumerate() {
-			if !group.is_empty() {
-				for (fi, file) in group.iter().enumerate() {
-					let offset = buf.len() + 8;
-					let mut offset_buf = &mut buf[offset_offsets[i] + fi * 4..];
-					offset_buf.put_u32_le(offset as u32);
-					buf.put(&file[..]);
-				}
-			}
+		buf[at..at + data.len()].copy_from_slice(data);
+	};
+	for ((group, info_offset), group_offsets) in groups.iter().zip(info_offsets).zip(offsets) {
+		if *info_offset == 0xFFFFFFFF {
+			continue; // empty
 		}
-		buf.freeze()
+		let mut info = BytesMut::new();
+		info.put_u32_le(group.len() as u32);
+		for offset in group_offsets {
+			info.put_u32_le(*offset as u32);
+		}
+		for file in group {
+			info.put_u32_le(file.len() as u32);
+		}
+		put_at(&mut buf, *info_offset, &info);
+		for (file, offset) in group.iter().zip(group_offsets) {
+			put_at(&mut buf, *offset, file);
+		}
+	}
+	buf
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// a header, some data, and padding that isn't zeros
+	fn original() -> Vec<u8> {
+		let mut buf = vec![0x50, 0x32, 0, 0, 0x10, 0, 0, 0, 0x80, 0, 0, 0];
+		buf.extend((0..100u8).map(|i| i.wrapping_mul(37)));
+		buf.extend([0xFF; 12]);
+		buf
+	}
+
+	#[test]
+	fn patches_turn_rebuilds_back_into_the_original() {
+		let original = original();
+		let padding = original.len() - 12;
+		let mut rebuilt = original.clone();
+		rebuilt[4] = 0x20; // a field worked out differently
+		rebuilt[padding..].fill(0);
+		let unpadded = rebuilt[..padding].to_vec();
+		let mut longer = rebuilt.clone();
+		longer.extend([0; 4]);
+		for rebuilt in [rebuilt, unpadded, longer, original.clone()] {
+			let patches = make_patches(&original, &rebuilt);
+			let mut buf = BytesMut::from(&rebuilt[..]);
+			apply_patches(&mut buf, &patches);
+			assert_eq!(&buf[..], &original[..], "from {} bytes", rebuilt.len());
+		}
+	}
+
+	#[test]
+	fn patches_leave_the_bytes_between_them_alone() {
+		let original = original();
+		let mut rebuilt = original.clone();
+		rebuilt[4] = 0x20;
+		rebuilt[8] = 0x90;
+		let patches = make_patches(&original, &rebuilt);
+		assert_eq!(patches.patches.len(), 2);
+		// a file in between edited without changing its length
+		rebuilt[6] = 0x55;
+		let mut buf = BytesMut::from(&rebuilt[..]);
+		apply_patches(&mut buf, &patches);
+		assert_eq!((buf[4], buf[6], buf[8]), (original[4], 0x55, original[8]));
+	}
+
+	#[test]
+	fn matching_files_need_no_patches() {
+		let original = original();
+		assert!(make_patches(&original, &original).patches.is_empty());
+	}
+
+	#[test]
+	fn bad_patches_are_rejected_when_read() {
+		let read = |s: &str| ron::de::from_str::<Patches>(s);
+		assert!(read(r#"(len: 4, patches: [(2, "abcd")])"#).is_ok());
+		assert!(read(r#"(len: 4, patches: [(2, "abc")])"#).is_err());
+		assert!(read(r#"(len: 4, patches: [(2, "zz")])"#).is_err());
+		assert!(read(r#"(len: 4, patches: [(3, "abcd")])"#).is_err());
+	}
+
+	#[test]
+	fn grown_overlays_get_their_new_sizes_in_the_table() {
+		let dir = std::env::temp_dir().join(format!("kh358extractor-overlays-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join(OVERLAY9_DIR)).unwrap();
+		let plain: Vec<u8> = (0..0x180u32).map(|i| (i * 37 % 251) as u8).collect();
+		let compressible = b"an overlay that compresses well. ".repeat(24);
+		std::fs::write(dir.join(OVERLAY9_DIR).join("0.bin"), &plain).unwrap();
+		std::fs::write(dir.join(OVERLAY9_DIR).join("1.bin"), &compressible).unwrap();
+		let metas: Vec<CodeMeta> = ["(unpacked_name: \"0.bin\", compressed: false)", "(unpacked_name: \"1.bin\", compressed: true)"]
+			.iter().map(|m| ron::de::from_str(m).unwrap()).collect();
+
+		// both were 0x100 bytes, the second compressed to 0x80
+		let mut table = vec![0; 0x40];
+		for (i, field) in [(0x08, 0x100), (0x18, 0), (0x28, 0x100), (0x38, 1), (0x3C, 0x01000080)] {
+			table[i..i + 4].copy_from_slice(&(field as u32).to_le_bytes());
+		}
+		let helper = IOHelper::new(dir.clone(), dir.clone(), Default::default());
+		let mut files = HashMap::new();
+		let table = pack_overlays(&metas, OVERLAY9_DIR, Bytes::from(table), &helper, &mut files).unwrap().unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!(read_header_u32(&table, 0x08), 0x180);
+		assert_eq!(read_header_u32(&table, 0x28), compressible.len() as u32);
+		assert_eq!(read_header_u32(&table, 0x3C), 0x01000000 | files[&1].len() as u32);
+		assert_eq!(&files[&0][..], &plain[..]);
+		assert_eq!(blz::decompress(&files[&1]).unwrap(), compressible);
 	}
 }
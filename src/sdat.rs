@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+use bytes::{Bytes, BytesMut};
+use crate::{
+	BErr,
+	magic::*,
+	meta::SDATLayout,
+	pack::apply_patches,
+	textures::safe_name,
+	util::Reader
+};
+
+// The info records that point at files, in the order SYMB and INFO list them, with what to call their files
+// when they don't have a symbol. Players and groups don't have files of their own.
+const FILE_RECORDS: [(usize, &str); 5] = [(0, "seq"), (1, "seqarc"), (2, "bank"), (3, "wavearc"), (7, "strm")];
+const SEQARC_RECORD: usize = 1;
+
+const FAT_FIELD: usize = 0x20;
+const FILE_BLOCK_FIELD: usize = 0x28;
+const FILE_BLOCK_HEADER: usize = 0x10;
+
+// A sound archive. Everything before the file data (the header, the symbol and info blocks, the file table) is
+// kept as-is, only the file table and the sizes change when it's rebuilt.
+pub struct SDAT {
+	pub headers: Bytes,
+	pub files: Vec<(String, Bytes)>, // by file id, named after the first thing referring to them
+	pub offsets: Vec<u32>
+}
+
+impl SDAT {
+	pub fn parse(buf: &[u8]) -> Result<Self, BErr> {
+		let mut r = Reader::new(buf, 0x10);
+		let symb = r.u32()? as usize;
+		r.pos += 4;
+		let info = r.u32()? as usize;
+		r.pos += 4;
+		let fat = r.u32()? as usize;
+		r.pos += 4;
+		let file_block = r.u32()? as usize;
+		if Reader::new(buf, fat).u32()? != FAT_MAGIC || Reader::new(buf, file_block).u32()? != FILE_MAGIC {
+			return Err("SDAT is missing its file table".into())
+		}
+		let mut r = Reader::new(buf, fat + 8);
+		let count = r.u32()? as usize;
+		let mut files = Vec::with_capacity(count);
+		let mut offsets = Vec::with_capacity(count);
+		for id in 0..count {
+			let (offset, size) = (r.u32()? as usize, r.u32()? as usize);
+			r.pos += 8;
+			let content = buf.get(offset..offset + size).ok_or(format!("SDAT file {} is out of bounds", id))?;
+			files.push(Bytes::copy_from_slice(content));
+			offsets.push(offset as u32);
+		}
+		let names = name_files(buf, symb, info, count)?;
+		let headers_end = (file_block + FILE_BLOCK_HEADER).min(buf.len());
+		Ok(SDAT {
+			headers: Bytes::copy_from_slice(&buf[..headers_end]),
+			files: names.into_iter().zip(files).collect(),
+			offsets
+		})
+	}
+}
+
+// Names each file after the first record using it, with its symbol if the archive has them
+fn name_files(buf: &[u8], symb: usize, info: usize, count: usize) -> Result<Vec<String>, BErr> {
+	let mut names: Vec<Option<String>> = vec![None; count];
+	let mut used = HashSet::new();
+	for (record, prefix) in FILE_RECORDS.iter() {
+		let symbols = if symb != 0 {read_symbols(buf, symb, *record)?} else {Vec::new()};
+		let at = info + Reader::new(buf, info + 8 + record * 4).u32()? as usize;
+		let mut r = Reader::new(buf, at);
+		for i in 0..r.u32()? as usize {
+			let entry = r.u32()? as usize;
+			if entry == 0 {
+				continue; // unused slot
+			}
+			let id = Reader::new(buf, info + entry).u16()? as usize;
+			if id >= count || names[id].is_some() {
+				continue;
+			}
+			let name = match symbols.get(i) {
+				Some(Some(symbol)) => safe_name(symbol),
+				_ => format!("{}_{:03}", prefix, i)
+			};
+			let name = if used.contains(&name) {format!("{}_{}", name, id)} else {name};
+			used.insert(name.clone());
+			names[id] = Some(name);
+		}
+	}
+	Ok(names.into_iter().enumerate().map(|(id, n)| n.unwrap_or_else(|| format!("file_{:03}", id))).collect())
+}
+
+fn read_symbols(buf: &[u8], symb: usize, record: usize) -> Result<Vec<Option<String>>, BErr> {
+	let at = symb + Reader::new(buf, symb + 8 + record * 4).u32()? as usize;
+	let mut r = Reader::new(buf, at);
+	let count = r.u32()? as usize;
+	(0..count).map(|_| {
+		let offset = r.u32()? as usize;
+		if record == SEQARC_RECORD {
+			r.pos += 4; // the symbols of the sequences inside
+		}
+		if offset == 0 {
+			return Ok(None)
+		}
+		let name = buf.get(symb + offset..).ok_or("SDAT symbol is out of bounds")?;
+		let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+		Ok(Some(String::from_utf8_lossy(&name[..end]).into_owned()))
+	}).collect()
+}
+
+// Puts the files back after the headers. They stay where they were if none of them changed size, otherwise
+// they're laid out one after the other, 0x20 aligned like the original tools do.
+pub fn write_sdat(headers: &[u8], files: &[Bytes], layout: Option<&SDATLayout>) -> Result<Bytes, BErr> {
+	let file_block = Reader::new(headers, FILE_BLOCK_FIELD).u32()? as usize;
+	let fat = Reader::new(headers, FAT_FIELD).u32()? as usize;
+	if Reader::new(headers, fat + 8).u32()? as usize != files.len() {
+		return Err(format!("SDAT has {} files but its file table has room for {}", files.len(), Reader::new(headers, fat + 8).u32()?).into())
+	}
+	let layout = layout.filter(|l| l.lengths.len() == files.len() && files.iter().zip(&l.lengths).all(|(f, l)| f.len() == *l as usize));
+	let mut offsets = Vec::with_capacity(files.len());
+	let mut end = if layout.is_some() {headers.len()} else {align(headers.len())};
+	for (i, file) in files.iter().enumerate() {
+		let offset = match layout {
+			Some(layout) => layout.offsets[i] as usize,
+			None => end
+		};
+		offsets.push(offset);
+		// the original padding at the end comes back with the patches
+		end = end.max(if layout.is_some() {offset + file.len()} else {align(offset + file.len())});
+	}
+	let mut buf = BytesMut::from(headers);
+	buf.resize(end, 0);
+	for (i, (file, offset)) in files.iter().zip(&offsets).enumerate() {
+		buf[*offset..offset + file.len()].copy_from_slice(file);
+		let entry = fat + 0xC + i * 16;
+		buf[entry..entry + 4].copy_from_slice(&(*offset as u32).to_le_bytes());
+		buf[entry + 4..entry + 8].copy_from_slice(&(file.len() as u32).to_le_bytes());
+	}
+	let file_size = (end - file_block) as u32;
+	buf[8..12].copy_from_slice(&(end as u32).to_le_bytes());
+	buf[FILE_BLOCK_FIELD + 4..FILE_BLOCK_FIELD + 8].copy_from_slice(&file_size.to_le_bytes());
+	buf[file_block + 4..file_block + 8].copy_from_slice(&file_size.to_le_bytes());
+	if let Some(layout) = layout {
+		apply_patches(&mut buf, &layout.patches);
+	}
+	Ok(buf.freeze())
+}
+
+fn align(offset: usize) -> usize {
+	(offset + 0x1F) & !0x1F
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::pack::make_patches;
+
+	// An SDAT with two sequence files and no symbols, with junk in the padding between the files
+	fn sdat() -> Vec<u8> {
+		let mut buf = vec![0; 0x120];
+		let mut put = |at: usize, value: u32| buf[at..at + 4].copy_from_slice(&value.to_le_bytes());
+		for (at, value) in [(0, SDAT_MAGIC), (4, 0x0100FEFF), (8, 0x120), (0xC, 0x00040040), (0x18, 0x40), (0x1C, 0x40),
+			(0x20, 0x80), (0x24, 0x30), (0x28, 0xB0), (0x2C, 0x70)] {
+			put(at, value);
+		}
+		// INFO: the sequence record has both files, every other record is empty
+		put(0x40, u32::from_le_bytes(*b"INFO"));
+		put(0x44, 0x40);
+		for record in 0..8 {
+			put(0x48 + record * 4, if record == 0 {0x28} else {0x3C});
+		}
+		for (at, value) in [(0x68, 2), (0x6C, 0x34), (0x70, 0x38), (0x74, 0), (0x78, 1)] {
+			put(at, value);
+		}
+		for (at, value) in [(0x80, FAT_MAGIC), (0x84, 0x2C), (0x88, 2), (0x8C, 0xC0), (0x90, 0x25), (0x9C, 0x100), (0xA0, 0x10)] {
+			put(at, value);
+		}
+		for (at, value) in [(0xB0, FILE_MAGIC), (0xB4, 0x70), (0xB8, 2)] {
+			put(at, value);
+		}
+		buf[0xC0..0xE5].fill(0x11);
+		buf[0xE5..0xE8].fill(0xEE);
+		buf[0x100..0x110].fill(0x22);
+		buf
+	}
+
+	fn layout_of(original: &[u8], sdat: &SDAT) -> SDATLayout {
+		let files: Vec<Bytes> = sdat.files.iter().map(|(_, f)| f.clone()).collect();
+		let mut layout = SDATLayout {
+			offsets: sdat.offsets.clone(),
+			lengths: files.iter().map(|f| f.len() as u32).collect(),
+			patches: Default::default()
+		};
+		layout.patches = make_patches(original, &write_sdat(&sdat.headers, &files, Some(&layout)).unwrap());
+		layout
+	}
+
+	fn read_u32(buf: &[u8], at: usize) -> usize {
+		Reader::new(buf, at).u32().unwrap() as usize
+	}
+
+	#[test]
+	fn unchanged_files_rebuild_the_original() {
+		let original = sdat();
+		let sdat = SDAT::parse(&original).unwrap();
+		assert_eq!(sdat.files.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["seq_000", "seq_001"]);
+		let layout = layout_of(&original, &sdat);
+		let files: Vec<Bytes> = sdat.files.into_iter().map(|(_, f)| f).collect();
+		assert_eq!(&write_sdat(&sdat.headers, &files, Some(&layout)).unwrap()[..], &original[..]);
+	}
+
+	#[test]
+	fn resized_files_are_laid_out_again() {
+		let original = sdat();
+		let sdat = SDAT::parse(&original).unwrap();
+		let layout = layout_of(&original, &sdat);
+		let files = vec![Bytes::from(vec![0x33; 0x41]), sdat.files[1].1.clone()];
+		let rebuilt = write_sdat(&sdat.headers, &files, Some(&layout)).unwrap();
+
+		let reread = SDAT::parse(&rebuilt).unwrap();
+		assert_eq!(reread.files.into_iter().map(|(_, f)| f).collect::<Vec<_>>(), files);
+		assert_eq!(reread.offsets, [0xC0, 0x120]);
+		assert_eq!(read_u32(&rebuilt, 0x90), 0x41);
+		assert_eq!(read_u32(&rebuilt, 0xA0), 0x10);
+		assert_eq!(rebuilt.len(), 0x140);
+		assert_eq!(read_u32(&rebuilt, 8), rebuilt.len());
+		assert_eq!(read_u32(&rebuilt, 0x2C), rebuilt.len() - 0xB0);
+		assert_eq!(read_u32(&rebuilt, 0xB4), rebuilt.len() - 0xB0);
+	}
+}
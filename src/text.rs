@@ -0,0 +1,448 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	util::from_hex
+};
+use bytes::{Bytes, BufMut};
+use serde::{Serialize, Deserialize};
+use std::{collections::HashMap, fs};
+
+// Message files: a count, a table of pointers, then the strings they point to, each ending with a control code.
+// How the game encodes its characters and control codes isn't known to the tool, it comes from a table given with
+// --text=<table.json>:
+// {
+//   "chars": {"41": "A", "8140": "　", ...}, // bytes in hex, and the text they stand for
+//   "controls": {"FF": {"name": "end", "end": true}, "F1": {"name": "color", "args": 1}, ...},
+//   "container": {"count": 4, "pointer": 4, "base": "start", "align": 4} // optional, these are the defaults
+// }
+// Any file the table reads completely as a message file is converted, everything else is left alone.
+// In the text, control codes are written `{name}` or `{name:01 02}` with their argument bytes, bytes the table
+// doesn't have `{#A3}`, and a `{` that's really part of the text `{{`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TextTable {
+	chars: HashMap<String, String>,
+	controls: HashMap<String, Control>,
+	#[serde(default)]
+	container: Container
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Control {
+	name: String,
+	#[serde(default)]
+	args: usize, // how many bytes follow it
+	#[serde(default)]
+	end: bool // ends the string
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Container {
+	count: usize, // bytes, 2 or 4
+	pointer: usize, // bytes, 2 or 4
+	base: PointerBase,
+	align: usize // strings start on a multiple of this, padded with 0
+}
+
+impl Default for Container {
+	fn default() -> Self {
+		Container {count: 4, pointer: 4, base: PointerBase::Start, align: 1}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PointerBase {
+	Start, // pointers count from the start of the file
+	TableEnd // or from right after the pointer table
+}
+
+// What a sequence of bytes decodes to
+enum Token<'a> {
+	Char(&'a str),
+	Control(&'a Control)
+}
+
+impl TextTable {
+	pub fn load(path: &str) -> Result<Self, BErr> {
+		let table: TextTable = serde_json::from_slice(&fs::read(path)?).map_err(|e| format!("{}: {}", path, e))?;
+		let bytes = |hex: &String| from_hex(hex).ok().filter(|b| !b.is_empty()).ok_or_else(|| format!("{}: {} isn't hex", path, hex));
+		for key in table.chars.keys().chain(table.controls.keys()) {
+			bytes(key)?;
+		}
+		if !table.controls.values().any(|c| c.end) {
+			return Err(format!("{}: no control code ends strings, one needs \"end\": true", path).into())
+		}
+		let c = table.container;
+		if ![2, 4].contains(&c.count) || ![2, 4].contains(&c.pointer) || c.align == 0 {
+			return Err(format!("{}: counts and pointers are 2 or 4 bytes, and align can't be 0", path).into())
+		}
+		Ok(table)
+	}
+
+	fn decoder(&self) -> (HashMap<Vec<u8>, Token<'_>>, usize) {
+		let mut map = HashMap::new();
+		for (hex, text) in &self.chars {
+			map.insert(from_hex(hex).unwrap(), Token::Char(text));
+		}
+		for (hex, control) in &self.controls {
+			map.insert(from_hex(hex).unwrap(), Token::Control(control));
+		}
+		let longest = map.keys().map(|k| k.len()).max().unwrap_or(1);
+		(map, longest)
+	}
+
+	fn end_bytes(&self) -> Vec<u8> {
+		self.controls.iter().find(|(_, c)| c.end).map(|(hex, _)| from_hex(hex).unwrap()).unwrap()
+	}
+
+	// The strings of a message file, without the codes that end them, if `buf` is one
+	fn split(&self, buf: &[u8]) -> Option<Vec<Vec<u8>>> {
+		let c = &self.container;
+		let count = read_uint(buf, 0, c.count)?;
+		let table_end = c.count + count * c.pointer;
+		if count == 0 || table_end > buf.len() {
+			return None
+		}
+		let base = if c.base == PointerBase::Start {0} else {table_end};
+		let starts = (0..count).map(|i| read_uint(buf, c.count + i * c.pointer, c.pointer).map(|p| base + p)).collect::<Option<Vec<_>>>()?;
+		if starts[0] < table_end || starts[0] >= align(table_end, c.align) + c.align || starts.windows(2).any(|w| w[0] > w[1])
+			|| starts.iter().any(|s| *s > buf.len()) {
+			return None
+		}
+		let (decoder, longest) = self.decoder();
+		let mut strings = Vec::with_capacity(count);
+		for start in &starts {
+			// pointers can share a string
+			let next = starts.iter().copied().find(|s| s > start);
+			let limit = next.unwrap_or(buf.len());
+			let end = self.find_end(buf.get(*start..limit)?, &decoder, longest)?;
+			// whatever's between the end and the next string has to be padding, the file itself can be padded to 4
+			let padding = buf.get(start + end.1..limit)?;
+			if padding.len() >= if next.is_some() {c.align} else {c.align.max(4)} || padding.iter().any(|b| *b != 0) {
+				return None
+			}
+			strings.push(buf.get(*start..start + end.0)?.to_vec());
+		}
+		Some(strings)
+	}
+
+	// (where the string ends, where the code ending it does)
+	fn find_end(&self, buf: &[u8], decoder: &HashMap<Vec<u8>, Token<'_>>, longest: usize) -> Option<(usize, usize)> {
+		let mut at = 0;
+		while at < buf.len() {
+			match lookup(&buf[at..], decoder, longest) {
+				Some((len, Token::Control(control))) if control.end => return Some((at, at + len)),
+				Some((len, Token::Control(control))) => at += len + control.args,
+				Some((len, Token::Char(_))) => at += len,
+				None => at += 1
+			}
+		}
+		None
+	}
+
+	pub fn decode(&self, string: &[u8]) -> String {
+		let (decoder, longest) = self.decoder();
+		let mut text = String::new();
+		let mut at = 0;
+		while at < string.len() {
+			match lookup(&string[at..], &decoder, longest) {
+				Some((len, Token::Char(c))) => {
+					text.push_str(&c.replace('{', "{{"));
+					at += len;
+				}
+				Some((len, Token::Control(control))) => {
+					let args = &string[(at + len).min(string.len())..(at + len + control.args).min(string.len())];
+					if args.is_empty() {
+						text.push_str(&format!("{{{}}}", control.name));
+					} else {
+						let args: Vec<String> = args.iter().map(|b| format!("{:02X}", b)).collect();
+						text.push_str(&format!("{{{}:{}}}", control.name, args.join(" ")));
+					}
+					at += len + control.args;
+				}
+				None => {
+					text.push_str(&format!("{{#{:02X}}}", string[at]));
+					at += 1;
+				}
+			}
+		}
+		text
+	}
+
+	// The reverse of decode, picking the longest text the table has at each point
+	pub fn encode(&self, text: &str) -> Result<Vec<u8>, BErr> {
+		let chars: Vec<(&str, Vec<u8>)> = self.chars.iter().map(|(hex, c)| (c.as_str(), from_hex(hex).unwrap())).collect();
+		let controls: HashMap<&str, (Vec<u8>, usize)> = self.controls.iter()
+			.map(|(hex, c)| (c.name.as_str(), (from_hex(hex).unwrap(), c.args)))
+			.collect();
+		let mut out = Vec::new();
+		let mut rest = text;
+		while !rest.is_empty() {
+			if let Some(after) = rest.strip_prefix('{').filter(|a| !a.starts_with('{')) {
+				let close = after.find('}').ok_or_else(|| format!("unclosed {{ in {:?}", text))?;
+				let code = &after[..close];
+				if let Some(hex) = code.strip_prefix('#') {
+					out.extend(from_hex(hex).map_err(|_| format!("{{#{}}} isn't a byte in hex", hex))?);
+				} else {
+					let (name, args) = code.split_once(':').unwrap_or((code, ""));
+					let (bytes, count) = controls.get(name).ok_or_else(|| format!("unknown control code {{{}}}", name))?;
+					let args = from_hex(&args.replace(' ', "")).map_err(|_| format!("{{{}}} has arguments that aren't hex", code))?;
+					if args.len() != *count {
+						return Err(format!("{{{}}} takes {} bytes of arguments", name, count).into())
+					}
+					out.extend_from_slice(bytes);
+					out.extend(args);
+				}
+				rest = &after[close + 1..];
+				continue;
+			}
+			// `{{` is a `{` of the text, which has to be looked up like any character
+			let (lookup_text, skip) = if rest.starts_with("{{") {(&rest[1..], 1)} else {(rest, 0)};
+			let (c, bytes) = chars.iter()
+				.filter(|(c, _)| !c.is_empty() && lookup_text.starts_with(c))
+				.max_by_key(|(c, _)| c.len())
+				.ok_or_else(|| format!("the table has nothing for {:?}", lookup_text.chars().next().unwrap()))?;
+			out.extend_from_slice(bytes);
+			rest = &rest[skip + c.len()..];
+		}
+		Ok(out)
+	}
+
+	// A new message file around these strings, with the pointer table worked out again
+	fn join(&self, strings: &[Vec<u8>]) -> Vec<u8> {
+		let c = &self.container;
+		let end = self.end_bytes();
+		let table_end = c.count + strings.len() * c.pointer;
+		let base = if c.base == PointerBase::Start {0} else {table_end};
+		let mut buf = Vec::new();
+		put_uint(&mut buf, strings.len(), c.count);
+		let data_start = align(table_end, c.align);
+		let mut data = Vec::new();
+		for string in strings {
+			put_uint(&mut buf, data_start + data.len() - base, c.pointer);
+			data.extend_from_slice(string);
+			data.extend_from_slice(&end);
+			data.resize(align(data.len(), c.align), 0);
+		}
+		buf.resize(data_start, 0);
+		buf.extend(data);
+		buf
+	}
+}
+
+// the longest code in the table `buf` starts with
+fn lookup<'a>(buf: &[u8], decoder: &'a HashMap<Vec<u8>, Token<'a>>, longest: usize) -> Option<(usize, &'a Token<'a>)> {
+	(1..=longest.min(buf.len())).rev().find_map(|len| decoder.get(&buf[..len]).map(|t| (len, t)))
+}
+
+fn read_uint(buf: &[u8], at: usize, width: usize) -> Option<usize> {
+	let bytes = buf.get(at..at + width)?;
+	Some(bytes.iter().rev().fold(0, |v, b| v << 8 | *b as usize))
+}
+
+fn put_uint(buf: &mut Vec<u8>, value: usize, width: usize) {
+	if width == 2 {
+		buf.put_u16_le(value as u16);
+	} else {
+		buf.put_u32_le(value as u32);
+	}
+}
+
+fn align(at: usize, to: usize) -> usize {
+	at.div_ceil(to) * to
+}
+
+fn messages_path(dir: &RelPath, file_name: &str, po: bool) -> RelPath {
+	let mut path = dir.clone();
+	path.push(format!("{}.{}", file_name, if po {"po"} else {"messages.json"}));
+	path
+}
+
+// Writes `<file>.messages.json`, or `<file>.po` with --po, next to a file the table reads as messages
+pub fn write_messages(helper: &IOHelper, path: &RelPath, buf: &[u8], table: &TextTable) {
+	let strings = match table.split(buf) {
+		Some(strings) => strings,
+		None => return
+	};
+	let mut dir = path.clone();
+	let name = dir.pop().unwrap_or_default();
+	let texts: Vec<String> = strings.iter().map(|s| table.decode(s)).collect();
+	let po = helper.get_options().po;
+	let result = if po {
+		helper.write_file(&messages_path(&dir, &name, true), write_po(&texts).as_bytes()).map_err(BErr::from)
+	} else {
+		serde_json::to_vec_pretty(&texts).map_err(BErr::from)
+			.and_then(|json| helper.write_file(&messages_path(&dir, &name, false), &json).map_err(BErr::from))
+	};
+	if let Err(e) = result {
+		println!("Couldn't write the messages of {:?}: {}", path, e);
+	}
+}
+
+// Encodes the messages next to a file back into it when they changed. Translations in a PO replace the strings
+// they're for, the ones left empty keep the original.
+pub fn import_messages(helper: &IOHelper, dir: &RelPath, file_name: &str, file: Bytes) -> Result<Bytes, BErr> {
+	let (json_path, po_path) = (messages_path(dir, file_name, false), messages_path(dir, file_name, true));
+	let path = match (helper.is_file(&json_path), helper.is_file(&po_path)) {
+		(true, _) => json_path,
+		(_, true) => po_path,
+		_ => return Ok(file)
+	};
+	let table = match &helper.get_options().text {
+		Some(table) => table,
+		None => {
+			println!("Not reading {:?}, messages need the --text table they were extracted with", path);
+			return Ok(file)
+		}
+	};
+	let strings = match table.split(&file) {
+		Some(strings) => strings,
+		None => return Ok(file)
+	};
+	let original: Vec<String> = strings.iter().map(|s| table.decode(s)).collect();
+	let content = String::from_utf8(helper.read_file(&path)?.to_vec())?;
+	let edited: Vec<String> = if path.peek().ends_with(".po") {
+		read_po(&content, &original).map_err(|e| format!("{:?}: {}", path, e))?
+	} else {
+		serde_json::from_str(&content).map_err(|e| format!("{:?}: {}", path, e))?
+	};
+	if edited == original {
+		return Ok(file)
+	}
+	let strings = edited.iter().map(|t| table.encode(t)).collect::<Result<Vec<_>, _>>().map_err(|e| format!("{:?}: {}", path, e))?;
+	Ok(Bytes::from(table.join(&strings)))
+}
+
+fn po_quote(text: &str) -> String {
+	let escaped = text.replace('\\', "\\\\").replace('"', "\\\"").replace('\t', "\\t");
+	// one quoted line per line of text, like other PO tools write them
+	let lines: Vec<String> = escaped.split_inclusive('\n').map(|l| format!("\"{}\"", l.replace('\n', "\\n"))).collect();
+	match lines.len() {
+		0 => "\"\"".into(),
+		1 => lines[0].clone(),
+		_ => format!("\"\"\n{}", lines.join("\n"))
+	}
+}
+
+fn write_po(texts: &[String]) -> String {
+	let mut po = String::from("msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain; charset=UTF-8\\n\"\n");
+	for (i, text) in texts.iter().enumerate() {
+		po.push_str(&format!("\nmsgctxt \"{}\"\nmsgid {}\nmsgstr \"\"\n", i, po_quote(text)));
+	}
+	po
+}
+
+fn po_unquote(line: &str) -> Result<String, BErr> {
+	let inner = line.trim().strip_prefix('"').and_then(|l| l.strip_suffix('"')).ok_or_else(|| format!("{} isn't quoted", line))?;
+	let mut out = String::new();
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => out.push('\n'),
+			Some('t') => out.push('\t'),
+			Some(c) => out.push(c),
+			None => return Err(format!("{} ends with a \\", line).into())
+		}
+	}
+	Ok(out)
+}
+
+// Only reads what write_po writes: a context with the string's number, its text and the translation
+fn read_po(content: &str, original: &[String]) -> Result<Vec<String>, BErr> {
+	let mut texts = original.to_vec();
+	let (mut context, mut field, mut translation) = (None, "", String::new());
+	let mut finish = |context: Option<usize>, translation: &str| -> Result<(), BErr> {
+		if let Some(i) = context {
+			if !translation.is_empty() {
+				*texts.get_mut(i).ok_or_else(|| format!("there's no string {}", i))? = translation.into();
+			}
+		}
+		Ok(())
+	};
+	for line in content.lines() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		if let Some(rest) = line.strip_prefix("msgctxt ") {
+			finish(context, &translation)?;
+			translation.clear();
+			context = Some(po_unquote(rest)?.parse()?);
+			field = "msgctxt";
+		} else if line.starts_with("msgid ") {
+			field = "msgid";
+		} else if let Some(rest) = line.strip_prefix("msgstr ") {
+			field = "msgstr";
+			translation = po_unquote(rest)?;
+		} else if line.starts_with('"') {
+			if field == "msgstr" {
+				translation.push_str(&po_unquote(line)?);
+			}
+		} else {
+			return Err(format!("can't read {}", line).into())
+		}
+	}
+	finish(context, &translation)?;
+	Ok(texts)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn table(container: &str) -> TextTable {
+		serde_json::from_str(&format!(r#"{{
+			"chars": {{"41": "A", "42": "B", "20": " ", "7B": "{{", "8140": "　", "8141": "、"}},
+			"controls": {{"FF": {{"name": "end", "end": true}}, "F1": {{"name": "color", "args": 1}}, "F2": {{"name": "wait"}}}},
+			"container": {}
+		}}"#, container)).unwrap()
+	}
+
+	fn strings() -> Vec<Vec<u8>> {
+		vec![
+			b"AB BA".to_vec(),
+			vec![0x41, 0xF1, 0xFF, 0x42, 0xF2, 0x81, 0x40, 0x81, 0x41], // a color argument that looks like the end
+			vec![0x7B, 0x41, 0x99], // a { and a byte the table doesn't have
+			Vec::new(),
+			vec![0x42; 3]
+		]
+	}
+
+	#[test]
+	fn strings_decode_and_encode_back() {
+		let table = table("{}");
+		for string in strings() {
+			let text = table.decode(&string);
+			assert_eq!(table.encode(&text).unwrap(), string, "{:?}", text);
+		}
+		assert_eq!(table.decode(&strings()[1]), "A{color:FF}B{wait}　、");
+		assert_eq!(table.decode(&strings()[2]), "{{A{#99}");
+	}
+
+	#[test]
+	fn joined_files_split_back() {
+		let containers = [
+			"{}",
+			r#"{"count": 2, "pointer": 2, "base": "table_end", "align": 4}"#,
+			r#"{"count": 4, "pointer": 2, "align": 2}"#
+		];
+		for container in containers {
+			let table = table(container);
+			assert_eq!(table.split(&table.join(&strings())), Some(strings()), "{}", container);
+		}
+	}
+
+	#[test]
+	fn files_that_arent_messages_dont_split() {
+		let table = table("{}");
+		assert_eq!(table.split(&[]), None);
+		assert_eq!(table.split(&[1, 0, 0, 0, 0xFF, 0, 0, 0]), None); // the pointer is past the end
+		assert_eq!(table.split(&[1, 0, 0, 0, 8, 0, 0, 0, 0x41, 0x42]), None); // no end code
+	}
+}
@@ -0,0 +1,545 @@
+use crate::{
+	BErr,
+	iohelper::{IOHelper, RelPath},
+	magic::*,
+	nitro::{NitroFile, read_dict},
+	graphics::{Color, bgr555_to_rgb, rgb_to_bgr555, read_rgba}
+};
+use bytes::Bytes;
+
+// texture formats, as numbered in the texture parameters
+pub const A3I5: u8 = 1;
+pub const COLOR4: u8 = 2;
+pub const COLOR16: u8 = 3;
+pub const COLOR256: u8 = 4;
+pub const COMPRESSED4X4: u8 = 5;
+pub const A5I3: u8 = 6;
+pub const DIRECT: u8 = 7;
+
+pub struct Texture {
+	pub name: String,
+	pub format: u8,
+	pub width: usize,
+	pub height: usize,
+	pub transparent0: bool, // color 0 is see-through, for the paletted formats
+	pub data_start: usize, // where the texels are in the file
+	pub index_start: usize // for 4x4 textures, where the palette index of each block is
+}
+
+impl Texture {
+	pub fn data_len(&self) -> usize {
+		let bits = match self.format {
+			COLOR4 | COMPRESSED4X4 => 2,
+			COLOR16 => 4,
+			DIRECT => 16,
+			_ => 8
+		};
+		self.width * self.height * bits / 8
+	}
+}
+
+pub struct Palette {
+	pub name: String,
+	pub data_start: usize, // where its colors are in the file
+	pub data_end: usize // end of all the palette data, since nothing says how long each one is
+}
+
+// TEX0, the textures of an NSBTX or the ones embedded in an NSBMD
+pub struct TEX0 {
+	pub textures: Vec<Texture>,
+	pub palettes: Vec<Palette>
+}
+
+impl TEX0 {
+	pub fn parse(buf: &[u8]) -> Result<Option<Self>, BErr> {
+		let file = NitroFile::parse_g3d(buf)?;
+		let start = match file.get_section_offset(TEX0_MAGIC) {
+			Some(offset) => offset - 8, // everything in here is relative to the section header
+			None => return Ok(None)
+		};
+		let block = &buf[start..start + 8 + file.get_section(TEX0_MAGIC).unwrap().len()];
+		if block.len() < 0x3C {
+			return Err("texture section too small".into())
+		}
+		let texture_dict = read_u16(block, 0xE)? as usize;
+		let texture_data = read_u32(block, 0x14)? as usize;
+		let compressed_data = read_u32(block, 0x24)? as usize;
+		let compressed_index = read_u32(block, 0x28)? as usize;
+		let palette_size = (read_u32(block, 0x30)? as usize) << 3;
+		let palette_dict = read_u32(block, 0x34)? as usize;
+		let palette_data = read_u32(block, 0x38)? as usize;
+
+		let mut textures = Vec::new();
+		for (name, entry) in read_dict(block, texture_dict)? {
+			let params = read_u32(entry, 0)?;
+			let offset = ((params & 0xFFFF) as usize) << 3;
+			let format = (params >> 26) as u8 & 7;
+			let (data_start, index_start) = if format == COMPRESSED4X4 {
+				(start + compressed_data + offset, start + compressed_index + offset / 2)
+			} else {
+				(start + texture_data + offset, 0)
+			};
+			textures.push(Texture {
+				name, format,
+				width: 8 << ((params >> 20) & 7),
+				height: 8 << ((params >> 23) & 7),
+				transparent0: params & 0x20000000 != 0,
+				data_start, index_start
+			});
+		}
+		let data_end = (start + palette_data + palette_size).min(buf.len());
+		let mut palettes = Vec::new();
+		for (name, entry) in read_dict(block, palette_dict)? {
+			palettes.push(Palette {
+				name,
+				data_start: start + palette_data + ((read_u16(entry, 0)? as usize) << 3),
+				data_end
+			});
+		}
+		Ok(Some(TEX0 {textures, palettes}))
+	}
+
+	// The palette the texture is most likely drawn with: the one named after it with "_pl" on the end, which is
+	// what the usual tools make, or else the one in the same position.
+	pub fn palette_for(&self, index: usize) -> Option<&Palette> {
+		let texture = self.textures.get(index)?;
+		if texture.format == DIRECT {
+			return None
+		}
+		let name = format!("{}_pl", texture.name);
+		self.palettes.iter().find(|p| p.name == name)
+			.or_else(|| self.palettes.get(index))
+			.or_else(|| self.palettes.first())
+	}
+
+	// RGBA pixels, row by row
+	pub fn decode(&self, buf: &[u8], index: usize) -> Result<Vec<u8>, BErr> {
+		self.decode_with(buf, index, self.palette_for(index))
+	}
+
+	pub fn decode_with(&self, buf: &[u8], index: usize, palette: Option<&Palette>) -> Result<Vec<u8>, BErr> {
+		let texture = &self.textures[index];
+		let data = buf.get(texture.data_start..texture.data_start + texture.data_len()).ok_or("texture data out of range")?;
+		if texture.format != DIRECT && palette.is_none() {
+			return Err("no palette for the texture".into())
+		}
+		let color = |i: usize| -> Color {
+			palette.and_then(|p| read_color(buf, p, i)).map_or([0; 3], bgr555_to_rgb)
+		};
+		if texture.format == COMPRESSED4X4 {
+			let indices = buf.get(texture.index_start..texture.index_start + data.len() / 2).ok_or("4x4 palette indices out of range")?;
+			let mut out = vec![0; texture.width * texture.height * 4];
+			let blocks_wide = texture.width / 4;
+			for (b, (texels, info)) in data.chunks_exact(4).zip(indices.chunks_exact(2)).enumerate() {
+				let texels = u32::from_le_bytes([texels[0], texels[1], texels[2], texels[3]]);
+				let colors = block_colors(u16::from_le_bytes([info[0], info[1]]), color);
+				let (x, y) = ((b % blocks_wide) * 4, (b / blocks_wide) * 4);
+				for t in 0..16 {
+					let pos = ((y + t / 4) * texture.width + x + t % 4) * 4;
+					out[pos..pos + 4].copy_from_slice(&colors[(texels >> (t * 2)) as usize & 3]);
+				}
+			}
+			return Ok(out)
+		}
+		let mut out = Vec::with_capacity(texture.width * texture.height * 4);
+		let mut push = |c: Color, a: u8| out.extend_from_slice(&[c[0], c[1], c[2], a]);
+		let opaque0 = |i: usize| if i == 0 && texture.transparent0 {0} else {0xFF};
+		match texture.format {
+			A3I5 => for b in data {
+				let a = b >> 5;
+				push(color((b & 0x1F) as usize), scale5((a << 2) | (a >> 1)));
+			},
+			A5I3 => for b in data {
+				push(color((b & 7) as usize), scale5(b >> 3));
+			},
+			COLOR4 => for b in data {
+				for shift in [0, 2, 4, 6] {
+					let i = (b >> shift) as usize & 3;
+					push(color(i), opaque0(i));
+				}
+			},
+			COLOR16 => for b in data {
+				for shift in [0, 4] {
+					let i = (b >> shift) as usize & 0xF;
+					push(color(i), opaque0(i));
+				}
+			},
+			COLOR256 => for b in data {
+				push(color(*b as usize), opaque0(*b as usize));
+			},
+			DIRECT => for c in data.chunks_exact(2) {
+				let c = u16::from_le_bytes([c[0], c[1]]);
+				push(bgr555_to_rgb(c), if c & 0x8000 != 0 {0xFF} else {0});
+			},
+			f => return Err(format!("unknown texture format {}", f).into())
+		}
+		Ok(out)
+	}
+
+	// Writes RGBA pixels into the texture in place, using the colors its palette already has. Only the pixels that don't
+	// match what the texture decodes to are encoded, so whatever wasn't edited stays exactly as it was.
+	pub fn encode(&self, buf: &mut [u8], index: usize, rgba: &[u8]) -> Result<(), BErr> {
+		let texture = &self.textures[index];
+		let current = self.decode(buf, index)?;
+		if rgba.len() != current.len() {
+			return Err(format!("the image isn't {}x{} like the texture", texture.width, texture.height).into())
+		}
+		let same = |a: &[u8], b: &[u8]| a == b || (a[3] == 0 && b[3] == 0);
+		let palette: Vec<Color> = match self.palette_for(index) {
+			Some(p) => (0..).map_while(|i| read_color(buf, p, i)).take(256).map(bgr555_to_rgb).collect(),
+			None => Vec::new()
+		};
+		if texture.format == COMPRESSED4X4 {
+			return self.encode_4x4(buf, texture, &palette, rgba, &current)
+		}
+		let bits = texture.data_len() * 8 / (texture.width * texture.height);
+		for (p, (new, old)) in rgba.chunks_exact(4).zip(current.chunks_exact(4)).enumerate() {
+			if same(new, old) {
+				continue;
+			}
+			let color = [new[0], new[1], new[2]];
+			let value = match texture.format {
+				A3I5 => nearest(&palette[..palette.len().min(32)], color, false) as u16 | ((new[3] as u16 * 7 + 127) / 255) << 5,
+				A5I3 => nearest(&palette[..palette.len().min(8)], color, false) as u16 | ((new[3] as u16 * 31 + 127) / 255) << 3,
+				DIRECT => rgb_to_bgr555(color) | if new[3] >= 0x80 {0x8000} else {0},
+				_ => {
+					let colors = &palette[..palette.len().min(1 << bits)];
+					if new[3] < 0x80 && texture.transparent0 {
+						0
+					} else {
+						nearest(colors, color, texture.transparent0) as u16
+					}
+				}
+			};
+			if palette.is_empty() && texture.format != DIRECT {
+				return Err("no palette for the texture".into())
+			}
+			let bit = p * bits;
+			let at = texture.data_start + bit / 8;
+			if bits == 16 {
+				buf[at..at + 2].copy_from_slice(&value.to_le_bytes());
+			} else {
+				let mask = ((1u16 << bits) - 1) as u8;
+				let shift = bit % 8;
+				buf[at] = buf[at] & !(mask << shift) | (value as u8 & mask) << shift;
+			}
+		}
+		Ok(())
+	}
+
+	// Every block with changes picks whichever palette colors and mode, out of the ones this texture already uses,
+	// come closest, along with the best of their four colors for each texel.
+	fn encode_4x4(&self, buf: &mut [u8], texture: &Texture, palette: &[Color], rgba: &[u8], current: &[u8]) -> Result<(), BErr> {
+		let blocks = texture.width * texture.height / 16;
+		let infos: Vec<u16> = (0..blocks).map(|b| read_u16(buf, texture.index_start + b * 2)).collect::<Result<_, _>>()?;
+		let mut candidates = infos.clone();
+		candidates.sort_unstable();
+		candidates.dedup();
+		let color = |i: usize| palette.get(i).copied().unwrap_or([0; 3]);
+		let blocks_wide = texture.width / 4;
+		for b in 0..blocks {
+			let (x, y) = ((b % blocks_wide) * 4, (b / blocks_wide) * 4);
+			let texel = |t: usize| {
+				let pos = ((y + t / 4) * texture.width + x + t % 4) * 4;
+				pos..pos + 4
+			};
+			if (0..16).all(|t| rgba[texel(t)] == current[texel(t)] || (rgba[texel(t)][3] == 0 && current[texel(t)][3] == 0)) {
+				continue;
+			}
+			let mut best = (u64::MAX, 0, 0);
+			for info in &candidates {
+				let colors = block_colors(*info, color);
+				let mut error = 0;
+				let mut texels = 0u32;
+				for t in 0..16 {
+					let px = &rgba[texel(t)];
+					let (i, e) = colors.iter().enumerate()
+						.map(|(i, c)| (i, color_distance(px, c)))
+						.min_by_key(|(_, e)| *e)
+						.unwrap();
+					error += e;
+					texels |= (i as u32) << (t * 2);
+				}
+				if error < best.0 {
+					best = (error, texels, *info);
+				}
+			}
+			let at = texture.data_start + b * 4;
+			buf[at..at + 4].copy_from_slice(&best.1.to_le_bytes());
+			let at = texture.index_start + b * 2;
+			buf[at..at + 2].copy_from_slice(&best.2.to_le_bytes());
+		}
+		Ok(())
+	}
+}
+
+// squared RGB distance, with a see-through color only matching see-through pixels
+fn color_distance(a: &[u8], b: &[u8]) -> u64 {
+	match (a[3] < 0x80, b[3] == 0) {
+		(true, true) => 0,
+		(false, false) => (0..3).map(|i| (a[i] as i64 - b[i] as i64).pow(2) as u64).sum(),
+		_ => 1 << 20
+	}
+}
+
+fn nearest(palette: &[Color], color: Color, skip0: bool) -> usize {
+	let pixel = [color[0], color[1], color[2], 0xFF];
+	palette.iter().enumerate()
+		.skip(if skip0 {1} else {0})
+		.min_by_key(|(_, c)| color_distance(&pixel, &[c[0], c[1], c[2], 0xFF]))
+		.map_or(0, |(i, _)| i)
+}
+
+// The four colors a 4x4 block can use. The low 14 bits of its info pick the colors in pairs, the top two the mode:
+// three colors and transparency, two and their average and transparency, four colors, or two and two blends.
+pub fn block_colors(info: u16, color: impl Fn(usize) -> Color) -> [[u8; 4]; 4] {
+	let base = (info & 0x3FFF) as usize * 2;
+	let (c0, c1) = (color(base), color(base + 1));
+	let mix = |w0: u16, w1: u16| -> [u8; 4] {
+		let m = |i: usize| ((c0[i] as u16 * w0 + c1[i] as u16 * w1) / (w0 + w1)) as u8;
+		[m(0), m(1), m(2), 0xFF]
+	};
+	let rgba = |c: Color| [c[0], c[1], c[2], 0xFF];
+	match info >> 14 {
+		0 => [rgba(c0), rgba(c1), rgba(color(base + 2)), [0; 4]],
+		1 => [rgba(c0), rgba(c1), mix(1, 1), [0; 4]],
+		2 => [rgba(c0), rgba(c1), rgba(color(base + 2)), rgba(color(base + 3))],
+		_ => [rgba(c0), rgba(c1), mix(5, 3), mix(3, 5)]
+	}
+}
+
+pub fn read_color(buf: &[u8], palette: &Palette, i: usize) -> Option<u16> {
+	let at = palette.data_start + i * 2;
+	if at + 2 > palette.data_end {
+		return None
+	}
+	Some(u16::from_le_bytes([buf[at], buf[at + 1]]))
+}
+
+fn scale5(v: u8) -> u8 {
+	(v << 3) | (v >> 2)
+}
+
+fn read_u16(buf: &[u8], at: usize) -> Result<u16, BErr> {
+	let b = buf.get(at..at + 2).ok_or("texture section too small")?;
+	Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(buf: &[u8], at: usize) -> Result<u32, BErr> {
+	let b = buf.get(at..at + 4).ok_or("texture section too small")?;
+	Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn texture_file_name(file_name: &str, texture: &str) -> String {
+	format!("{}.{}.png", file_name, safe_name(texture))
+}
+
+// names in the 3D formats can be anything, so keep them from turning into paths
+pub fn safe_name(name: &str) -> String {
+	name.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) {c} else {'_'}).collect()
+}
+
+// Writes `<file>.<texture>.png` next to an NSBTX or NSBMD for each of its textures.
+pub fn write_texture_pngs(helper: &IOHelper, path: &RelPath, buf: &[u8]) {
+	let tex0 = match TEX0::parse(buf) {
+		Ok(Some(tex0)) => tex0,
+		Ok(None) => return,
+		Err(e) => {
+			println!("Couldn't read the textures in {:?}: {}", path, e);
+			return
+		}
+	};
+	let mut dir = path.clone();
+	let file_name = dir.pop().unwrap_or_default();
+	for (i, texture) in tex0.textures.iter().enumerate() {
+		let mut png_path = dir.clone();
+		png_path.push(texture_file_name(&file_name, &texture.name));
+		let result = tex0.decode(buf, i).and_then(|pixels| {
+			let png = encode_rgba(texture.width, texture.height, &pixels)?;
+			Ok(helper.write_file(&png_path, &png)?)
+		});
+		if let Err(e) = result {
+			println!("Couldn't convert texture {} in {:?} to png: {}", texture.name, path, e);
+		}
+	}
+}
+
+fn encode_rgba(width: usize, height: usize, pixels: &[u8]) -> Result<Vec<u8>, BErr> {
+	let mut out = Vec::new();
+	let mut encoder = png::Encoder::new(&mut out, width as u32, height as u32);
+	encoder.set_color(png::ColorType::Rgba);
+	encoder.set_depth(png::BitDepth::Eight);
+	let mut writer = encoder.write_header()?;
+	writer.write_image_data(pixels)?;
+	drop(writer);
+	Ok(out)
+}
+
+// Encodes `<file>.<texture>.png` images that were edited back into the file's textures.
+pub fn import_texture_pngs(helper: &IOHelper, dir: &RelPath, file_name: &str, file: Bytes) -> Result<Bytes, BErr> {
+	let magic = file.get(..4).map(|m| u32::from_le_bytes([m[0], m[1], m[2], m[3]]));
+	if magic != Some(NSBTX_MAGIC) && magic != Some(NSBMD_MAGIC) {
+		return Ok(file)
+	}
+	let tex0 = match TEX0::parse(&file) {
+		Ok(Some(tex0)) => tex0,
+		_ => return Ok(file)
+	};
+	let mut buf = None;
+	for (i, texture) in tex0.textures.iter().enumerate() {
+		let mut png_path = dir.clone();
+		png_path.push(texture_file_name(file_name, &texture.name));
+		if !helper.is_file(&png_path) {
+			continue;
+		}
+		let (width, height, rgba) = read_rgba(&helper.read_file(&png_path)?)?;
+		let buf = buf.get_or_insert_with(|| file.to_vec());
+		let result = if (width, height) != (texture.width, texture.height) {
+			Err(format!("is {}x{} but the texture is {}x{}", width, height, texture.width, texture.height).into())
+		} else {
+			tex0.encode(buf, i, &rgba)
+		};
+		result.map_err(|e| format!("{:?}: {}", png_path, e))?;
+	}
+	Ok(buf.map_or(file, Bytes::from))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::nitro::{build_g3d, write_dict};
+
+	// an 8x8 texture of each, with color 0 see-through for the ones that can have it
+	const FORMATS: [u8; 7] = [A3I5, COLOR4, COLOR16, COLOR256, COMPRESSED4X4, A5I3, DIRECT];
+
+	// the palette: 32 grays, one BGR555 step apart
+	fn gray(i: usize) -> Color {
+		bgr555_to_rgb(i as u16 * 0x0421)
+	}
+
+	// Pixel p is color p % 32, or as far as the format goes. The 4x4 texture has four blocks, one of each mode, all
+	// with colors 2 to 5 and every row going through the four colors of the block.
+	fn texels(format: u8) -> Vec<u8> {
+		match format {
+			A3I5 => (0..64).map(|p| (p % 32) as u8 | ((p % 8) as u8) << 5).collect(),
+			A5I3 => (0..64).map(|p| (p % 8) as u8 | ((p % 32) as u8) << 3).collect(),
+			COLOR4 | COMPRESSED4X4 => vec![0b11100100; 16],
+			COLOR16 => (0..32u8).map(|b| (b * 2 % 16) | ((b * 2 + 1) % 16) << 4).collect(),
+			COLOR256 => (0..64).map(|p| (p % 32) as u8).collect(),
+			_ => (0..64u16).flat_map(|p| ((p % 32 * 0x0421) | (p % 2) << 15).to_le_bytes()).collect()
+		}
+	}
+
+	fn expected(format: u8, p: usize) -> [u8; 4] {
+		let with = |c: Color, a: u8| [c[0], c[1], c[2], a];
+		let indexed = |i: usize| with(gray(i), if i == 0 {0} else {0xFF});
+		match format {
+			A3I5 => with(gray(p % 32), [0, 33, 74, 107, 148, 181, 222, 255][p % 8]),
+			A5I3 => with(gray(p % 8), gray(p % 32)[0]),
+			COLOR4 => indexed(p % 4),
+			COLOR16 => indexed(p % 16),
+			COLOR256 => indexed(p % 32),
+			DIRECT => with(gray(p % 32), if p % 2 == 1 {0xFF} else {0}),
+			_ => {
+				let (x, y) = (p % 8, p / 8);
+				let g = |v: u8| [v, v, v, 0xFF];
+				// colors 2 to 5 are 16, 24, 33 and 41
+				match (x / 4 + y / 4 * 2, x % 4) {
+					(_, 0) => g(16),
+					(_, 1) => g(24),
+					(0 | 2, 2) => g(33),
+					(1, 2) => g(20),
+					(3, 2) => g(19),
+					(2, 3) => g(41),
+					(3, 3) => g(21),
+					_ => [0; 4]
+				}
+			}
+		}
+	}
+
+	fn tex0_file() -> Vec<u8> {
+		let (mut data, mut compressed, mut params) = (Vec::new(), Vec::new(), Vec::new());
+		for format in FORMATS {
+			let offset = if format == COMPRESSED4X4 {&mut compressed} else {&mut data}.len();
+			if format == COMPRESSED4X4 {
+				compressed.extend(texels(format));
+			} else {
+				data.extend(texels(format));
+			}
+			let mut entry = (offset as u32 >> 3 | (format as u32) << 26 | 1 << 29).to_le_bytes().to_vec();
+			entry.extend([0; 4]);
+			params.push(entry);
+		}
+		let index: Vec<u8> = (0..4u16).flat_map(|mode| (mode << 14 | 1).to_le_bytes()).collect();
+		let palette: Vec<u8> = (0..32u16).flat_map(|i| (i * 0x0421).to_le_bytes()).collect();
+		let names: Vec<String> = (0..FORMATS.len()).map(|i| format!("t{}", i)).collect();
+		let texture_dict = write_dict(&names, &params);
+		let palette_dict = write_dict(&["gray".to_string()], &[vec![0; 4]]);
+
+		// the texture dictionary comes right after the header, then everything else in the order its offset is set
+		let mut block = vec![0; 0x3C];
+		block[0x0E..0x10].copy_from_slice(&0x3Cu16.to_le_bytes());
+		let mut at = block.len() + texture_dict.len();
+		let mut place = |field: usize, len: usize, block: &mut Vec<u8>| {
+			block[field..field + 4].copy_from_slice(&(at as u32).to_le_bytes());
+			at += len;
+		};
+		place(0x34, palette_dict.len(), &mut block);
+		place(0x14, data.len(), &mut block);
+		place(0x24, compressed.len(), &mut block);
+		place(0x28, index.len(), &mut block);
+		place(0x38, palette.len(), &mut block);
+		block[0x30..0x34].copy_from_slice(&(palette.len() as u32 >> 3).to_le_bytes());
+		for part in [texture_dict, palette_dict, data, compressed, index, palette] {
+			block.extend(part);
+		}
+		build_g3d(NSBTX_MAGIC, &[(TEX0_MAGIC, &block[8..])])
+	}
+
+	#[test]
+	fn every_format_decodes() {
+		let file = tex0_file();
+		let tex0 = TEX0::parse(&file).unwrap().unwrap();
+		for (i, &format) in FORMATS.iter().enumerate() {
+			let texture = &tex0.textures[i];
+			assert_eq!((texture.format, texture.width, texture.height), (format, 8, 8));
+			let rgba = tex0.decode(&file, i).unwrap();
+			for p in 0..64 {
+				assert_eq!(rgba[p * 4..p * 4 + 4], expected(format, p), "format {} pixel {}", format, p);
+			}
+		}
+	}
+
+	#[test]
+	fn edits_encode_back() {
+		let file = tex0_file();
+		let tex0 = TEX0::parse(&file).unwrap().unwrap();
+		for (i, &format) in FORMATS.iter().enumerate() {
+			let mut rgba = tex0.decode(&file, i).unwrap();
+			if format == COMPRESSED4X4 {
+				// the top left block becomes a copy of the bottom right one, mode and all
+				for y in 0..4 {
+					rgba.copy_within(((y + 4) * 8 + 4) * 4..((y + 4) * 8 + 8) * 4, y * 8 * 4);
+				}
+			} else {
+				// the first pixel gets another palette color and the second turns see-through
+				let color = gray(if format == COLOR4 {3} else {7});
+				rgba[..8].copy_from_slice(&[color[0], color[1], color[2], 0xFF, 0, 0, 0, 0]);
+			}
+			let mut buf = file.clone();
+			tex0.encode(&mut buf, i, &rgba).unwrap();
+			assert_eq!(tex0.decode(&buf, i).unwrap(), rgba, "format {}", format);
+			let texture = &tex0.textures[i];
+			// nothing but the texels, and the block's palette index for 4x4, changed
+			let texels = texture.data_start..texture.data_start + texture.data_len();
+			buf[texels.clone()].copy_from_slice(&file[texels]);
+			if format == COMPRESSED4X4 {
+				let index = texture.index_start..texture.index_start + 2;
+				assert_eq!(buf[index.clone()], (3 << 14 | 1u16).to_le_bytes());
+				buf[index.clone()].copy_from_slice(&file[index]);
+			}
+			assert!(buf == file, "format {}", format);
+		}
+	}
+}
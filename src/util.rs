@@ -1,11 +1,14 @@
 use std::{
 	fmt::{Debug, Display, Formatter},
 	fmt,
-	error::Error
+	error::Error,
+	convert::TryInto
 };
+use crate::BErr;
 
 #[derive(Debug, Clone)]
 pub struct UnwrapError<T> {
+	#[allow(dead_code)] // kept so the caller can get the value back out if it wants
 	pub value: T,
 	pub error_msg: String
 }
@@ -36,4 +39,64 @@ impl<T> TryUnwrap<T> for Option<T> {
 			Err(UnwrapError::create(self, "called try_unwrap on none value".into()))
 		}
 	}
-}
\ No newline at end of file
+}
+// FNV-1a. Only used to notice whether a file changed since extraction, so it doesn't need to be fancy.
+pub fn content_hash(data: &[u8]) -> u64 {
+	let mut hash = 0xcbf29ce484222325u64;
+	for b in data {
+		hash ^= *b as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+	data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+	if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return Err(format!("{:?} isn't hex", s))
+	}
+	Ok((0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect())
+}
+
+// Bounds checked little endian reads, since everything in these files is found through offsets
+pub struct Reader<'a> {
+	buf: &'a [u8],
+	pub pos: usize
+}
+
+impl<'a> Reader<'a> {
+	pub fn new(buf: &'a [u8], pos: usize) -> Self {
+		Reader {buf, pos}
+	}
+
+	pub fn bytes<const N: usize>(&mut self) -> Result<[u8; N], BErr> {
+		let b = self.buf.get(self.pos..self.pos + N).ok_or_else(|| format!("read past the end of the data at {:#X}", self.pos))?;
+		self.pos += N;
+		Ok(b.try_into().unwrap())
+	}
+
+	pub fn u8(&mut self) -> Result<u8, BErr> {
+		Ok(self.bytes::<1>()?[0])
+	}
+
+	pub fn u16(&mut self) -> Result<u16, BErr> {
+		Ok(u16::from_le_bytes(self.bytes()?))
+	}
+
+	pub fn u32(&mut self) -> Result<u32, BErr> {
+		Ok(u32::from_le_bytes(self.bytes()?))
+	}
+
+	// 1.3.12 fixed point
+	pub fn fx16(&mut self) -> Result<f32, BErr> {
+		Ok(self.u16()? as i16 as f32 / 4096.0)
+	}
+
+	// 1.19.12 fixed point
+	pub fn fx32(&mut self) -> Result<f32, BErr> {
+		Ok(self.u32()? as i32 as f32 / 4096.0)
+	}
+}
@@ -0,0 +1,159 @@
+use crate::{
+	BErr, FileType, GroupedFiles, P2File, HPAK, PK2D, PKAC,
+	compression::{decompress, type_name},
+	extract::Parse,
+	nds::{NDSRom, NitroDir, NitroEntry}
+};
+use bytes::Bytes;
+use std::{
+	convert::TryFrom,
+	fs,
+	path::Path
+};
+
+// Where the walk is: how many directories and archives deep, the path there, and the index and name
+// extracting would give it
+pub struct Entry<'a> {
+	pub depth: usize,
+	pub path: &'a str,
+	pub index: &'a str,
+	pub name: &'a str
+}
+
+// What list and analyze do with what the walk finds. Files are seen after they're decompressed.
+pub trait Visitor {
+	fn dir(&mut self, _entry: &Entry) {}
+
+	// `compressed` is the kind of compression and the size before decompressing
+	fn file(&mut self, entry: &Entry, ty: FileType, size: usize, compressed: Option<(&str, usize)>);
+
+	// an HPAK or PK2D's groups, before their files are walked
+	fn bundle(&mut self, _entry: &Entry, _ty: FileType, _groups: &[(FileType, &[Bytes]); 8]) {}
+
+	// a file that doesn't decompress or parse
+	fn failed(&mut self, entry: &Entry, problem: String);
+}
+
+// What the archive a file came out of says about it. Like the hints extracting passes along, a known type isn't
+// guessed and a known compression isn't either.
+#[derive(Clone, Copy, Default)]
+struct Hints {
+	ty: Option<FileType>,
+	compressed: Option<bool>,
+	add_extension: bool // P2 and PKAC members get the extension of what they turn out to be
+}
+
+fn join(dir: &str, name: &str) -> String {
+	if dir.is_empty() {name.to_string()} else {format!("{}/{}", dir, name)}
+}
+
+// Goes through a file and whatever is inside it, in memory
+fn walk_file(v: &mut impl Visitor, depth: usize, dir: &str, index: &str, name: &str, buf: Bytes, hints: Hints) {
+	let mut ty = hints.ty.unwrap_or_else(|| FileType::guess_from(&buf, hints.compressed != Some(false)));
+	let compressed = hints.compressed == Some(true) || matches!(ty, FileType::LZ | FileType::RLE | FileType::Huffman);
+	let (buf, compression) = if compressed {
+		match decompress(&buf) {
+			Ok(content) => {
+				let content = Bytes::from(content);
+				ty = FileType::guess_from(&content, false);
+				(content, Some((type_name(&buf), buf.len())))
+			}
+			Err(e) => {
+				let entry = Entry {depth, path: &join(dir, name), index, name};
+				v.file(&entry, ty, buf.len(), None);
+				v.failed(&entry, format!("doesn't decompress: {}", e));
+				return
+			}
+		}
+	} else {
+		(buf, None)
+	};
+	let name = if hints.add_extension {format!("{}.{}", name, ty.get_extension())} else {name.to_string()};
+	let path = join(dir, &name);
+	let entry = Entry {depth, path: &path, index, name: &name};
+	v.file(&entry, ty, buf.len(), compression);
+	let member = Hints {add_extension: true, ..Hints::default()};
+	match ty {
+		FileType::P2 => match P2File::parse(&buf) {
+			Ok(p2) => {
+				for subfile in p2.subfiles {
+					let hints = Hints {compressed: Some(subfile.compressed), ..member};
+					walk_file(v, depth + 1, &path, &subfile.index.to_string(), &subfile.suggest_name(), subfile.content, hints);
+				}
+			}
+			Err(e) => v.failed(&entry, format!("doesn't parse: {}", e))
+		},
+		FileType::HPAK | FileType::PK2D | FileType::PKAC => match GroupedFiles::parse(&buf) {
+			Ok(files) if ty == FileType::HPAK => walk_groups(v, &entry, ty, HPAK::from(files).get_type_map()),
+			Ok(files) if ty == FileType::PK2D => walk_groups(v, &entry, ty, PK2D::from(files).get_type_map()),
+			Ok(files) => match PKAC::try_from(files) {
+				Ok(pkac) => {
+					for (i, (name, file)) in pkac.files.into_iter().enumerate() {
+						walk_file(v, depth + 1, &path, &i.to_string(), &name, file, member);
+					}
+				}
+				Err(e) => v.failed(&entry, format!("doesn't parse: {}", e))
+			},
+			Err(e) => v.failed(&entry, format!("doesn't parse: {}", e))
+		},
+		_ => ()
+	}
+}
+
+fn walk_groups(v: &mut impl Visitor, entry: &Entry, ty: FileType, groups: [(FileType, &[Bytes]); 8]) {
+	v.bundle(entry, ty, &groups);
+	for (group, (ty, files)) in groups.iter().enumerate() {
+		for (i, file) in files.iter().enumerate() {
+			let hints = Hints {ty: Some(*ty), compressed: Some(false), add_extension: false};
+			let name = format!("{}.{}", i, ty.get_extension());
+			walk_file(v, entry.depth + 1, entry.path, &format!("{}.{}", group, i), &name, file.clone(), hints);
+		}
+	}
+}
+
+fn walk_dir(v: &mut impl Visitor, path: &Path, depth: usize, dir: &str) -> Result<(), BErr> {
+	let mut entries: Vec<_> = fs::read_dir(path)?.collect::<Result<_, _>>()?;
+	entries.sort_by_key(|e| e.file_name());
+	for entry in entries {
+		let name = entry.file_name().to_string_lossy().into_owned();
+		if entry.path().is_dir() {
+			let sub = join(dir, &name);
+			v.dir(&Entry {depth, path: &sub, index: "", name: &name});
+			walk_dir(v, &entry.path(), depth + 1, &sub)?;
+		} else {
+			walk_file(v, depth, dir, "", &name, Bytes::from(fs::read(entry.path())?), Hints::default());
+		}
+	}
+	Ok(())
+}
+
+fn walk_rom_dir(v: &mut impl Visitor, rom: &NDSRom, nitro_dir: &NitroDir, depth: usize, dir: &str) -> Result<(), BErr> {
+	for entry in &nitro_dir.entries {
+		match entry {
+			NitroEntry::File(name, id) => walk_file(v, depth, dir, "", name, rom.get_file(*id)?, Hints::default()),
+			NitroEntry::Dir(subdir) => {
+				let sub = join(dir, &subdir.name);
+				v.dir(&Entry {depth, path: &sub, index: "", name: &subdir.name});
+				walk_rom_dir(v, rom, subdir, depth + 1, &sub)?
+			}
+		}
+	}
+	Ok(())
+}
+
+// Walks a directory, a rom or a single file, going into every archive in memory
+pub fn walk(v: &mut impl Visitor, target: &Path) -> Result<(), BErr> {
+	if target.is_dir() {
+		return walk_dir(v, target, 0, "")
+	}
+	let buf = Bytes::from(fs::read(target)?);
+	if FileType::guess_from(&buf, true) == FileType::OtherOrNotGuessable {
+		if let Ok(rom) = NDSRom::parse(buf.clone()) {
+			v.dir(&Entry {depth: 0, path: "data", index: "", name: "data"});
+			return walk_rom_dir(v, &rom, rom.get_root(), 1, "data")
+		}
+	}
+	let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+	walk_file(v, 0, "", "", &name, buf, Hints::default());
+	Ok(())
+}